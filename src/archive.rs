@@ -0,0 +1,2766 @@
+use std::{
+    cell::Cell,
+    collections::{hash_map, HashMap, HashSet},
+    fs,
+    io::{self, Read, Seek, Write},
+    path::{Path, PathBuf},
+    rc::Rc,
+    time::SystemTime,
+};
+
+#[cfg(any(feature = "flate", feature = "lz4"))]
+use crate::compression;
+#[cfg(feature = "encrypt")]
+use crate::crypto;
+#[cfg(feature = "dedup")]
+use crate::dedup;
+#[cfg(test)]
+use crate::list;
+use crate::{
+    crc32,
+    flags::{self, Flags},
+    index, platform, sparse, trailer, ChecksumAlgorithm, Metadata, MAX_PATH_LEN,
+};
+#[cfg(all(test, unix))]
+use crate::{extract, EntryKind};
+
+/// Failure while archiving, naming the object and the step that failed.
+#[derive(Debug)]
+pub enum ArchiveError {
+    /// Reading the metadata of `path` failed.
+    Stat { path: PathBuf, source: io::Error },
+    /// Opening or reading `path` failed, for directories listing their contents.
+    Read { path: PathBuf, source: io::Error },
+    /// `path` is of a type that can't be archived, e.g. a socket.
+    Unsupported { path: PathBuf },
+    /// `path` isn't below the prefix to strip, see [`ArchiverBuilder::strip_prefix`].
+    OutsidePrefix { path: PathBuf, prefix: PathBuf },
+    /// `path` would be archived under more than [`MAX_PATH_LEN`] bytes,
+    /// which readers reject.
+    PathTooLong { path: PathBuf, len: usize },
+    /// `path` differs from `other` only in case, so restoring both to a
+    /// case-insensitive filesystem fails, see [`ArchiverBuilder::case_collisions`].
+    CaseCollision { path: PathBuf, other: PathBuf },
+    /// Writing the entry of `path` to the archive failed.
+    Write { path: PathBuf, source: io::Error },
+    /// Any other failure, e.g. while flushing the archive.
+    Io(io::Error),
+}
+
+impl ArchiveError {
+    pub(crate) fn stat(path: &Path) -> impl FnOnce(io::Error) -> Self + '_ {
+        move |source| Self::Stat {
+            path: path.into(),
+            source,
+        }
+    }
+
+    pub(crate) fn read(path: &Path) -> impl FnOnce(io::Error) -> Self + '_ {
+        move |source| Self::Read {
+            path: path.into(),
+            source,
+        }
+    }
+
+    pub(crate) fn write(path: &Path) -> impl FnOnce(io::Error) -> Self + '_ {
+        move |source| Self::Write {
+            path: path.into(),
+            source,
+        }
+    }
+
+    /// The object that failed to be archived, if the error concerns one.
+    pub fn path(&self) -> Option<&Path> {
+        match self {
+            Self::Stat { path, .. }
+            | Self::Read { path, .. }
+            | Self::Unsupported { path }
+            | Self::OutsidePrefix { path, .. }
+            | Self::PathTooLong { path, .. }
+            | Self::CaseCollision { path, .. }
+            | Self::Write { path, .. } => Some(path),
+            Self::Io(_) => None,
+        }
+    }
+
+    /// Kind of the underlying I/O error.
+    pub fn kind(&self) -> io::ErrorKind {
+        match self {
+            Self::Stat { source, .. } | Self::Read { source, .. } | Self::Write { source, .. } => {
+                source.kind()
+            }
+            Self::Unsupported { .. } => io::ErrorKind::Unsupported,
+            Self::OutsidePrefix { .. } | Self::PathTooLong { .. } | Self::CaseCollision { .. } => {
+                io::ErrorKind::InvalidInput
+            }
+            Self::Io(e) => e.kind(),
+        }
+    }
+}
+
+impl std::fmt::Display for ArchiveError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Stat { path, source } => {
+                write!(f, "failed to read metadata of {}: {source}", path.display())
+            }
+            Self::Read { path, source } => write!(f, "failed to read {}: {source}", path.display()),
+            Self::Unsupported { path } => {
+                write!(f, "can't archive {}: unsupported file type", path.display())
+            }
+            Self::OutsidePrefix { path, prefix } => write!(
+                f,
+                "can't archive {}: not below {}",
+                path.display(),
+                prefix.display()
+            ),
+            Self::PathTooLong { path, len } => write!(
+                f,
+                "can't archive {}: path of {len}B exceeds {MAX_PATH_LEN}B",
+                path.display()
+            ),
+            Self::CaseCollision { path, other } => write!(
+                f,
+                "can't archive {}: differs from {} only in case",
+                path.display(),
+                other.display()
+            ),
+            Self::Write { path, source } => {
+                write!(f, "failed to write {} to archive: {source}", path.display())
+            }
+            Self::Io(e) => e.fmt(f),
+        }
+    }
+}
+
+impl std::error::Error for ArchiveError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Stat { source, .. } | Self::Read { source, .. } | Self::Write { source, .. } => {
+                Some(source)
+            }
+            Self::Unsupported { .. }
+            | Self::OutsidePrefix { .. }
+            | Self::PathTooLong { .. }
+            | Self::CaseCollision { .. } => None,
+            Self::Io(e) => Some(e),
+        }
+    }
+}
+
+impl From<io::Error> for ArchiveError {
+    fn from(e: io::Error) -> Self {
+        Self::Io(e)
+    }
+}
+
+impl From<ArchiveError> for io::Error {
+    fn from(err: ArchiveError) -> Self {
+        match err {
+            ArchiveError::Io(e) => e,
+            err => io::Error::new(err.kind(), err),
+        }
+    }
+}
+
+/// An object left out of an archive in best-effort mode, with the reason.
+pub type Skipped = (PathBuf, io::Error);
+
+/// Records `err` in `skipped` if best-effort mode allows leaving its object out,
+/// otherwise returns it.
+///
+/// Only errors raised before anything of an entry is written can be skipped.
+fn skip(
+    err: ArchiveError,
+    options: &ArchiveOptions,
+    skipped: &mut Vec<Skipped>,
+) -> Result<(), ArchiveError> {
+    if !options.best_effort {
+        return Err(err);
+    }
+    log::warn!("Skipping: {err}");
+    match err {
+        ArchiveError::Stat { path, source } | ArchiveError::Read { path, source } => {
+            skipped.push((path, source));
+        }
+        ArchiveError::Unsupported { path } => skipped.push((
+            path,
+            io::Error::new(io::ErrorKind::Unsupported, "unsupported file type"),
+        )),
+        ArchiveError::PathTooLong { path, len } => skipped.push((
+            path,
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("path of {len}B exceeds {MAX_PATH_LEN}B"),
+            ),
+        )),
+        err => return Err(err),
+    }
+    Ok(())
+}
+
+/// Rejects paths `path` would be archived under that don't fit an entry.
+pub(crate) fn check_path_len(path: &Path, stored: &[u8]) -> Result<(), ArchiveError> {
+    if stored.len() > MAX_PATH_LEN {
+        return Err(ArchiveError::PathTooLong {
+            path: path.into(),
+            len: stored.len(),
+        });
+    }
+    Ok(())
+}
+
+/// What to do about objects whose paths differ only in case, which collide
+/// when restored to a case-insensitive filesystem, see [`ArchiverBuilder::case_collisions`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum CaseCollisions {
+    /// Archive them without checking.
+    #[default]
+    Ignore,
+    /// Log a warning for every collision and archive them anyway.
+    Warn,
+    /// Fail with [`ArchiveError::CaseCollision`] before anything is written.
+    Error,
+}
+
+/// Reports the paths of `files` that are equal once case-folded, by `mode`.
+fn check_case_collisions(
+    files: &[(PathBuf, bool)],
+    mode: CaseCollisions,
+) -> Result<(), ArchiveError> {
+    if mode == CaseCollisions::Ignore {
+        return Ok(());
+    }
+    let mut folded = HashMap::<String, &Path>::new();
+    for (path, _) in files {
+        match folded.entry(path.to_string_lossy().to_lowercase()) {
+            hash_map::Entry::Occupied(first) if mode == CaseCollisions::Error => {
+                return Err(ArchiveError::CaseCollision {
+                    path: path.clone(),
+                    other: first.get().into(),
+                });
+            }
+            hash_map::Entry::Occupied(first) => log::warn!(
+                "{} differs from {} only in case",
+                path.display(),
+                first.get().display()
+            ),
+            hash_map::Entry::Vacant(slot) => {
+                slot.insert(path);
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Behavior shared by all entries an [`Archiver`] writes.
+#[derive(Clone, Debug)]
+struct ArchiveOptions {
+    follow_symlinks: bool,
+    preserve_perms: bool,
+    sparse: bool,
+    best_effort: bool,
+    strip_prefix: Option<PathBuf>,
+    modified_since: Option<SystemTime>,
+    max_file_size: Option<u64>,
+    reproducible: bool,
+    case_collisions: CaseCollisions,
+    checksum: ChecksumAlgorithm,
+    #[cfg(feature = "flate")]
+    compress: bool,
+    #[cfg(feature = "flate")]
+    compression_level: u32,
+    #[cfg(feature = "lz4")]
+    lz4: bool,
+    #[cfg(feature = "xattr")]
+    xattrs: bool,
+    #[cfg(feature = "encrypt")]
+    key: Option<crypto::Key>,
+    #[cfg(feature = "dedup")]
+    dedup: bool,
+}
+
+impl Default for ArchiveOptions {
+    fn default() -> Self {
+        Self {
+            follow_symlinks: false,
+            preserve_perms: true,
+            sparse: false,
+            best_effort: false,
+            strip_prefix: None,
+            modified_since: None,
+            max_file_size: None,
+            reproducible: false,
+            case_collisions: CaseCollisions::Ignore,
+            checksum: ChecksumAlgorithm::Crc32,
+            #[cfg(feature = "flate")]
+            compress: false,
+            #[cfg(feature = "flate")]
+            compression_level: compression::DEFAULT_LEVEL,
+            #[cfg(feature = "lz4")]
+            lz4: false,
+            #[cfg(feature = "xattr")]
+            xattrs: false,
+            #[cfg(feature = "encrypt")]
+            key: None,
+            #[cfg(feature = "dedup")]
+            dedup: false,
+        }
+    }
+}
+
+impl ArchiveOptions {
+    fn metadata(&self, path: &Path) -> io::Result<fs::Metadata> {
+        if self.follow_symlinks {
+            // dangling links can't be followed and are stored as links.
+            path.metadata().or_else(|_| path.symlink_metadata())
+        } else {
+            path.symlink_metadata()
+        }
+    }
+
+    /// The path `path` is archived under.
+    fn stored_path<'p>(&self, path: &'p Path) -> Result<&'p Path, ArchiveError> {
+        let Some(prefix) = &self.strip_prefix else {
+            return Ok(path);
+        };
+        path.strip_prefix(prefix)
+            .map_err(|_| ArchiveError::OutsidePrefix {
+                path: path.into(),
+                prefix: prefix.clone(),
+            })
+    }
+
+    /// The object on the filesystem archived under `stored`.
+    fn disk_path(&self, stored: &Path) -> PathBuf {
+        match &self.strip_prefix {
+            Some(prefix) => prefix.join(stored),
+            None => stored.into(),
+        }
+    }
+
+    /// Encoded extended attributes of `path`, empty unless they are recorded.
+    #[cfg(feature = "xattr")]
+    fn xattrs(&self, path: &Path) -> io::Result<Vec<u8>> {
+        if !self.xattrs {
+            return Ok(vec![]);
+        }
+        let xattrs = crate::xattrs::get(path, self.follow_symlinks)?;
+        if xattrs.is_empty() {
+            return Ok(vec![]);
+        }
+        Ok(crate::xattrs::encode(&xattrs))
+    }
+
+    #[cfg(not(feature = "xattr"))]
+    fn xattrs(&self, _path: &Path) -> io::Result<Vec<u8>> {
+        Ok(vec![])
+    }
+}
+
+/// Largest body read into memory before its header is written, so that the
+/// size recorded is the size read even if the file changes meanwhile.
+/// Larger bodies are streamed at the size they had beforehand, see [`FixedSize`].
+pub(crate) const MAX_BUFFERED: u64 = 256 * 1024;
+
+/// An object ready to be written to an archive.
+struct PendingEntry<'a> {
+    meta: Metadata,
+    path: Vec<u8>,
+    /// encoded extended attributes, empty unless flagged with `HAS_XATTR`.
+    xattrs: Vec<u8>,
+    body: Box<dyn Read + 'a>,
+    /// identity of a file with multiple hard links.
+    inode: Option<(u64, u64)>,
+    /// whether `body` is known to have the recorded size, otherwise
+    /// [`settle`](Self::settle) has to make it so before the entry is written.
+    settled: bool,
+    /// set once the body turned out not to have the recorded size, see [`FixedSize`].
+    resized: Rc<Cell<bool>>,
+}
+
+impl<'a> PendingEntry<'a> {
+    fn from_path(path: &Path, options: &ArchiveOptions) -> Result<Self, ArchiveError> {
+        let path_str = platform::path_to_bytes(options.stored_path(path)?);
+        check_path_len(path, &path_str)?;
+
+        let flags: u32;
+        let file_size: u64;
+        let mut body: Box<dyn Read> = Box::new(io::empty());
+
+        let metadata = options.metadata(path).map_err(ArchiveError::stat(path))?;
+        let file_type = metadata.file_type();
+
+        let perms = if options.preserve_perms {
+            platform::perms(&metadata)
+        } else {
+            0
+        };
+        let (owner, group) = if options.reproducible {
+            (0, 0)
+        } else {
+            platform::owner(&metadata)
+        };
+
+        // the format can't represent times before the epoch, they're stored as the epoch.
+        let modified_at = metadata
+            .modified()
+            .map_err(ArchiveError::stat(path))?
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .map_or(0, |since| since.as_secs());
+        let modified_at = if options.reproducible { 0 } else { modified_at };
+
+        let mut settled = true;
+        let resized = Rc::default();
+        if file_type.is_file() {
+            let mut file = std::fs::File::open(path).map_err(ArchiveError::read(path))?;
+            let segments = if options.sparse {
+                sparse::scan(&mut file).map_err(ArchiveError::read(path))?
+            } else {
+                vec![]
+            };
+
+            if sparse::has_holes(&segments) {
+                flags = flags::FILE | flags::SPARSE;
+                file_size = sparse::stored_size(&segments);
+                let encoder = sparse::Encoder::new(file, segments, Rc::clone(&resized));
+                body = Box::new(encoder.map_err(ArchiveError::read(path))?);
+            } else {
+                flags = flags::FILE;
+                // scanning for holes left the file at its end.
+                file.rewind().map_err(ArchiveError::read(path))?;
+                file_size = file.metadata().map_err(ArchiveError::stat(path))?.len();
+                body = Box::new(file);
+                settled = false;
+            }
+        } else if file_type.is_dir() {
+            flags = flags::DIR;
+            file_size = 0;
+        } else if file_type.is_symlink() {
+            flags = flags::SOFT_LINK;
+
+            // the link target is stored as the body.
+            let target = std::fs::read_link(path).map_err(ArchiveError::read(path))?;
+            let target = platform::path_to_bytes(&target);
+            file_size = target.len() as _;
+            body = Box::new(io::Cursor::new(target));
+        } else if let Some((kind, special)) = platform::special(&metadata) {
+            flags = kind;
+            file_size = special.len() as _;
+            body = Box::new(io::Cursor::new(special));
+        } else {
+            return Err(ArchiveError::Unsupported { path: path.into() });
+        }
+
+        let xattrs = options.xattrs(path).map_err(ArchiveError::read(path))?;
+
+        let flags = if xattrs.is_empty() {
+            flags
+        } else {
+            flags | flags::HAS_XATTR
+        };
+        let path_len = path_str.len() as _;
+        let meta = Metadata::new(flags, file_size, path_len, perms, owner, group, modified_at);
+
+        Ok(Self {
+            meta,
+            path: path_str,
+            xattrs,
+            body,
+            inode: platform::hard_link_inode(&metadata),
+            settled,
+            resized,
+        })
+    }
+
+    /// Turns the entry into a hard link to the archived path `target`,
+    /// which is stored as the body instead of the file contents.
+    fn link_to(&mut self, target: Vec<u8>) {
+        self.meta.flags = Flags::new(flags::HARD_LINK | self.meta.flags.bits() & flags::HAS_XATTR);
+        self.meta.file_size = target.len() as _;
+        self.body = Box::new(io::Cursor::new(target));
+        self.settled = true;
+    }
+
+    /// Describes `size` bytes of `body` to be archived as `path`,
+    /// which don't have to come from the filesystem.
+    fn from_reader(
+        path: &str,
+        body: impl Read + 'a,
+        size: u64,
+        modified_at: u64,
+    ) -> Result<Self, ArchiveError> {
+        check_path_len(Path::new(path), path.as_bytes())?;
+        let meta = Metadata::new(flags::FILE, size, path.len() as _, 0, 0, 0, modified_at);
+        // only `size` bytes are archived, a longer reader isn't an error.
+        Ok(Self {
+            meta,
+            path: path.as_bytes().to_vec(),
+            xattrs: vec![],
+            body: Box::new(body.take(size)),
+            inode: None,
+            settled: false,
+            resized: Rc::default(),
+        })
+    }
+
+    /// Makes the body have exactly the recorded size.
+    ///
+    /// A body of up to [`MAX_BUFFERED`] bytes is read into memory and its size
+    /// recorded instead, a larger one is padded or cut off by [`FixedSize`].
+    fn settle(&mut self) -> Result<(), ArchiveError> {
+        if self.settled {
+            return Ok(());
+        }
+        self.settled = true;
+        let path = platform::bytes_to_path(&self.path);
+        let mut contents = vec![];
+        if self.meta.file_size <= MAX_BUFFERED {
+            (&mut self.body)
+                .take(MAX_BUFFERED + 1)
+                .read_to_end(&mut contents)
+                .map_err(ArchiveError::read(&path))?;
+            if contents.len() as u64 <= MAX_BUFFERED {
+                self.meta.file_size = contents.len() as _;
+                self.body = Box::new(io::Cursor::new(contents));
+                return Ok(());
+            }
+        }
+        // what was read already comes first, in case the file grew past the limit.
+        let rest = std::mem::replace(&mut self.body, Box::new(io::empty()));
+        let body = io::Cursor::new(contents).chain(rest);
+        let resized = Rc::clone(&self.resized);
+        self.body = Box::new(FixedSize::new(body, self.meta.file_size, &path, resized));
+        Ok(())
+    }
+
+    /// Reads the body into memory, so the source is no longer accessed when writing.
+    /// Its size is the size read, whatever size was recorded before.
+    #[cfg(feature = "parallel")]
+    fn buffer(mut self) -> Result<BufferedEntry, ArchiveError> {
+        let mut body = vec![];
+        let path = platform::bytes_to_path(&self.path);
+        self.body
+            .read_to_end(&mut body)
+            .map_err(ArchiveError::read(&path))?;
+        self.meta.file_size = body.len() as _;
+        Ok(BufferedEntry {
+            meta: self.meta,
+            path: self.path,
+            xattrs: self.xattrs,
+            body,
+            inode: self.inode,
+        })
+    }
+
+    /// Replaces the body of a file with its form compressed by `compress`,
+    /// `algorithm` being the flag telling the algorithm apart from gzip.
+    #[cfg(any(feature = "flate", feature = "lz4"))]
+    fn compress(
+        &mut self,
+        algorithm: u32,
+        compress: impl FnOnce(&mut Box<dyn Read + 'a>) -> io::Result<Vec<u8>>,
+    ) -> Result<(), ArchiveError> {
+        if !self.meta.flags.is_file() {
+            return Ok(());
+        }
+        let path = platform::bytes_to_path(&self.path);
+        let compressed = compress(&mut self.body).map_err(ArchiveError::read(&path))?;
+        self.meta.file_size = compressed.len() as _;
+        self.meta.flags = self.meta.flags.with(flags::COMPRESSED | algorithm);
+        self.body = Box::new(io::Cursor::new(compressed));
+        Ok(())
+    }
+
+    /// Replaces the body of a file with its encrypted form.
+    #[cfg(feature = "encrypt")]
+    fn encrypt(&mut self, key: &crypto::Key) {
+        if !self.meta.flags.is_file() {
+            return;
+        }
+        let aad = crypto::associated_data(&self.meta, &self.path);
+        let body = std::mem::replace(&mut self.body, Box::new(io::empty()));
+        let (stored, encrypted) = crypto::encrypt(body, self.meta.file_size, key, aad);
+        self.meta.file_size = stored;
+        self.meta.flags = self.meta.flags.with(flags::ENCRYPTED);
+        self.body = Box::new(encrypted);
+    }
+
+    /// Replaces the body of a plain file with its chunks, referring to chunks
+    /// already in `chunks` instead of storing them again.
+    #[cfg(feature = "dedup")]
+    fn dedup(
+        &mut self,
+        chunks: &mut dedup::ChunkStore,
+        body_start: u64,
+    ) -> Result<(), ArchiveError> {
+        let transformed = flags::SPARSE | flags::COMPRESSED | flags::ENCRYPTED;
+        if !self.meta.flags.is_file() || self.meta.flags.intersects(transformed) {
+            return Ok(());
+        }
+        let path = platform::bytes_to_path(&self.path);
+        let mut contents = vec![];
+        self.body
+            .read_to_end(&mut contents)
+            .map_err(ArchiveError::read(&path))?;
+        let chunked = chunks.encode(&contents, body_start);
+        self.meta.file_size = chunked.len() as _;
+        self.meta.flags = self.meta.flags.with(flags::CHUNKED);
+        self.body = Box::new(io::Cursor::new(chunked));
+        Ok(())
+    }
+
+    /// Number of bytes [`write`](Self::write) writes, once the entry is [settled](Self::settle).
+    fn len(&self) -> u64 {
+        (2 * Metadata::SIZE + self.path.len() + self.xattrs.len()) as u64 + self.meta.file_size
+    }
+
+    /// Writes the entry, returning the number of bytes written.
+    fn write(self, archive: &mut impl Write) -> Result<u64, ArchiveError> {
+        let PendingEntry {
+            meta,
+            path,
+            xattrs,
+            body,
+            resized,
+            ..
+        } = self;
+        let fs_path = platform::bytes_to_path(&path);
+
+        let mut write_all = |bytes: &[u8]| {
+            archive
+                .write_all(bytes)
+                .map_err(ArchiveError::write(&fs_path))
+        };
+        write_all(&meta.header(&path, &xattrs).to_bytes())?;
+        write_all(&path)?;
+        write_all(&xattrs)?;
+
+        // copied by hand to tell failures reading the body from failures writing it.
+        let mut body = crc32::Reader::for_body(body, meta.flags);
+        let mut buf = [0u8; 8 * 1024];
+        let mut body_len = 0;
+        loop {
+            let n = match body.read(&mut buf) {
+                Ok(0) => break,
+                Ok(n) => n,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(ArchiveError::read(&fs_path)(e)),
+            };
+            write_all(&buf[..n])?;
+            body_len += n as u64;
+        }
+
+        let mut footer = meta.footer(body.digest());
+        if resized.get() {
+            footer.flags = footer.flags.with(flags::RESIZED);
+            footer.set_checksum();
+        }
+        write_all(&footer.to_bytes())?;
+
+        Ok(2 * Metadata::SIZE as u64 + (path.len() + xattrs.len()) as u64 + body_len)
+    }
+}
+
+/// A [`PendingEntry`] with its body in memory, which can be sent between threads.
+#[cfg(feature = "parallel")]
+struct BufferedEntry {
+    meta: Metadata,
+    path: Vec<u8>,
+    xattrs: Vec<u8>,
+    body: Vec<u8>,
+    inode: Option<(u64, u64)>,
+}
+
+#[cfg(feature = "parallel")]
+impl From<BufferedEntry> for PendingEntry<'static> {
+    fn from(entry: BufferedEntry) -> Self {
+        Self {
+            meta: entry.meta,
+            path: entry.path,
+            xattrs: entry.xattrs,
+            body: Box::new(io::Cursor::new(entry.body)),
+            inode: entry.inode,
+            settled: true,
+            resized: Rc::default(),
+        }
+    }
+}
+
+/// Body of an entry copied from another archive by [`Archiver::update_from`].
+///
+/// The footer following it is read as soon as the body ends, before the footer
+/// of the copy is written, so that a [`flags::RESIZED`] mark carries over.
+struct CopiedBody<'r, R> {
+    body: crc32::Reader<io::Take<&'r mut R>>,
+    footer: Option<Result<Metadata, crate::DecodeError>>,
+    resized: Rc<Cell<bool>>,
+}
+
+impl<R: Read> Read for CopiedBody<'_, R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.body.read(buf)?;
+        if n == 0 && !buf.is_empty() && self.footer.is_none() && self.body.get_ref().limit() == 0 {
+            let footer = crate::read_footer(self.body.get_mut().get_mut());
+            if let Ok(footer) = &footer {
+                self.resized.set(footer.flags.resized());
+            }
+            self.footer = Some(footer);
+        }
+        Ok(n)
+    }
+}
+
+/// Reads exactly the size a body larger than [`MAX_BUFFERED`] had when its
+/// header was prepared.
+///
+/// The size is already written by then, so a file that shrank in the meantime
+/// is padded with zeros and one that grew is cut off, keeping the archive
+/// well-formed. Either sets `resized`, which marks the footer with [`flags::RESIZED`]
+/// so that extraction rejects the entry rather than restoring made-up contents.
+struct FixedSize<R> {
+    inner: R,
+    remaining: u64,
+    path: PathBuf,
+    resized: Rc<Cell<bool>>,
+}
+
+impl<R: Read> FixedSize<R> {
+    fn new(inner: R, size: u64, path: &Path, resized: Rc<Cell<bool>>) -> Self {
+        Self {
+            inner,
+            remaining: size,
+            path: path.into(),
+            resized,
+        }
+    }
+}
+
+impl<R: Read> Read for FixedSize<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.remaining == 0 {
+            return Ok(0);
+        }
+        let max = buf
+            .len()
+            .min(self.remaining.try_into().unwrap_or(usize::MAX));
+        let mut n = match self.resized.get() {
+            true => 0,
+            false => self.inner.read(&mut buf[..max])?,
+        };
+        if n == 0 {
+            if !self.resized.replace(true) {
+                log::warn!(
+                    "{} shrank while archiving, padding {}B with zeros",
+                    self.path.display(),
+                    self.remaining
+                );
+            }
+            buf[..max].fill(0);
+            n = max;
+        }
+
+        self.remaining -= n as u64;
+        if self.remaining == 0 && !self.resized.get() && self.inner.read(&mut [0])? != 0 {
+            log::warn!(
+                "{} grew while archiving, cutting it off",
+                self.path.display()
+            );
+            self.resized.set(true);
+        }
+        Ok(n)
+    }
+}
+
+/// Writes filesystem objects to an archive.
+///
+/// Every entry takes several small writes, e.g. for its header, path and footer,
+/// so unbuffered writers like files are best wrapped in a [`BufWriter`](io::BufWriter).
+///
+/// ```no_run
+/// # use std::{fs::File, io::BufWriter, path::Path};
+/// # use bitumen::Archiver;
+/// let mut archiver = Archiver::new(BufWriter::new(File::create("src.bit")?))
+///     .follow_symlinks(false)
+///     .preserve_perms(true)
+///     .build();
+/// archiver.add_path(Path::new("src"))?;
+/// archiver.finish()?;
+/// # Ok::<(), std::io::Error>(())
+/// ```
+pub struct Archiver<W: Write> {
+    writer: W,
+    options: ArchiveOptions,
+    excludes: Vec<Exclude>,
+    /// archived path of the first occurrence of every file with multiple hard links.
+    links: HashMap<(u64, u64), Vec<u8>>,
+    /// number of bytes written so far.
+    written: u64,
+    /// objects written so far, the bytes are counted by `written`.
+    stats: ArchiveStats,
+    /// entries written so far, if an index is written on finishing.
+    index: Option<Vec<index::Record>>,
+    /// objects left out in best-effort mode.
+    skipped: Vec<Skipped>,
+    /// chunks written so far, if file contents are deduplicated.
+    #[cfg(feature = "dedup")]
+    chunks: dedup::ChunkStore,
+    /// checksum of everything written so far, if a trailer is written on finishing.
+    trailer: Option<crc32::Crc32>,
+    /// called with the length of every entry before it's written.
+    before_entry: Option<fn(&mut W, u64) -> io::Result<()>>,
+    /// whether the writer is flushed after every entry, for buffering writers.
+    flush_entries: bool,
+}
+
+/// Predicate selecting paths to leave out of an archive.
+type Exclude = Box<dyn Fn(&Path) -> bool>;
+
+/// Collects the options of an [`Archiver`], see [`Archiver::new`].
+pub struct ArchiverBuilder<W: Write> {
+    writer: W,
+    options: ArchiveOptions,
+    excludes: Vec<Exclude>,
+    index: bool,
+    trailer: bool,
+    flush_entries: bool,
+}
+
+impl<W: Write> ArchiverBuilder<W> {
+    /// Archive the objects symlinks point to instead of the links themselves.
+    /// A directory reached again, e.g. through a link to an ancestor, is left out.
+    /// Defaults to `false`.
+    pub fn follow_symlinks(mut self, follow: bool) -> Self {
+        self.options.follow_symlinks = follow;
+        self
+    }
+
+    /// Record the permission bits of every object. Defaults to `true`.
+    pub fn preserve_perms(mut self, preserve: bool) -> Self {
+        self.options.preserve_perms = preserve;
+        self
+    }
+
+    /// Leave out runs of zeros in files, which are recreated as holes on extraction.
+    /// Defaults to `false`.
+    pub fn sparse(mut self, sparse: bool) -> Self {
+        self.options.sparse = sparse;
+        self
+    }
+
+    /// Store paths relative to `prefix`, failing for any path not below it.
+    /// `prefix` itself is stored as an empty path, which extracts to the destination.
+    /// By default paths are stored as given.
+    pub fn strip_prefix(mut self, prefix: impl Into<PathBuf>) -> Self {
+        self.options.strip_prefix = Some(prefix.into());
+        self
+    }
+
+    /// Only archive objects modified at or after `since` while walking a tree,
+    /// for incremental backups. Directories are always archived, so the
+    /// structure of the tree is kept. By default everything is archived.
+    pub fn modified_since(mut self, since: SystemTime) -> Self {
+        self.options.modified_since = Some(since);
+        self
+    }
+
+    /// Leave out files larger than `max` bytes while walking a tree, e.g. to keep
+    /// backups bounded. By default files of any size are archived.
+    pub fn max_file_size(mut self, max: u64) -> Self {
+        self.options.max_file_size = Some(max);
+        self
+    }
+
+    /// Write the same bytes for the same tree contents on every run: modification
+    /// times, owners and groups are stored as `0` and entries are sorted by path
+    /// instead of following the directory order. Defaults to `false`.
+    pub fn reproducible(mut self, reproducible: bool) -> Self {
+        self.options.reproducible = reproducible;
+        self
+    }
+
+    /// Leave out objects that can't be read while walking a tree instead of failing,
+    /// see [`Archiver::skipped`]. Failures writing the archive are never skipped.
+    /// Defaults to `false`.
+    pub fn best_effort(mut self, best_effort: bool) -> Self {
+        self.options.best_effort = best_effort;
+        self
+    }
+
+    /// Check the tree for paths differing only in case, e.g. `File.txt` and `file.txt`,
+    /// before archiving it. Restoring such an archive to a case-insensitive filesystem
+    /// makes one overwrite the other. Defaults to [`CaseCollisions::Ignore`].
+    pub fn case_collisions(mut self, mode: CaseCollisions) -> Self {
+        self.options.case_collisions = mode;
+        self
+    }
+
+    /// Algorithm of the body checksums, which readers pick up from every entry.
+    /// Defaults to [`ChecksumAlgorithm::Crc32`], which older readers understand.
+    pub fn checksum(mut self, algorithm: ChecksumAlgorithm) -> Self {
+        self.options.checksum = algorithm;
+        self
+    }
+
+    /// Store the bodies of files gzip compressed. Defaults to `false`.
+    #[cfg(feature = "flate")]
+    pub fn compress(mut self, compress: bool) -> Self {
+        self.options.compress = compress;
+        self
+    }
+
+    /// Gzip level from `0`, fastest, to `9`, smallest, higher levels are taken as `9`.
+    /// Level `0` still stores bodies flagged as compressed, just in uncompressed
+    /// deflate blocks; turn [`compress`](Self::compress) off to store them as they are.
+    /// Defaults to `6`.
+    #[cfg(feature = "flate")]
+    pub fn compression_level(mut self, level: u32) -> Self {
+        self.options.compression_level = level;
+        self
+    }
+
+    /// Store the bodies of files LZ4 compressed, which is faster than gzip
+    /// but compresses less. Takes precedence over gzip compression.
+    /// Defaults to `false`.
+    #[cfg(feature = "lz4")]
+    pub fn compress_lz4(mut self, compress: bool) -> Self {
+        self.options.lz4 = compress;
+        self
+    }
+
+    /// Encrypt the bodies of files with AES-256-GCM, after compressing them.
+    /// Metadata and paths stay readable.
+    #[cfg(feature = "encrypt")]
+    pub fn encrypt(mut self, key: &[u8; 32]) -> Self {
+        self.options.key = Some(crypto::Key(*key));
+        self
+    }
+
+    /// Store identical chunks of file contents only once, which pays off for
+    /// trees holding many similar files. Sparse, compressed and encrypted
+    /// bodies aren't deduplicated, and deduplicated bodies are buffered in memory.
+    ///
+    /// Chunks are referred to by their offset from the first byte written,
+    /// so the archive has to start there. Defaults to `false`.
+    #[cfg(feature = "dedup")]
+    pub fn dedup(mut self, dedup: bool) -> Self {
+        self.options.dedup = dedup;
+        self
+    }
+
+    /// Record the extended attributes of every object. Defaults to `false`.
+    #[cfg(feature = "xattr")]
+    pub fn xattrs(mut self, xattrs: bool) -> Self {
+        self.options.xattrs = xattrs;
+        self
+    }
+
+    /// Append an index of all entries when finishing, see [`open_indexed`](crate::open_indexed).
+    /// Offsets are counted from the first byte written, so the archive has to start there.
+    /// Defaults to `false`.
+    pub fn index(mut self, index: bool) -> Self {
+        self.index = index;
+        self
+    }
+
+    /// Append a checksum over the whole archive when finishing, which
+    /// [`verify`](crate::verify) checks. Readers without support for it take the
+    /// archive for truncated. [`append_path_to_existing`] moves it behind the
+    /// appended entries. Defaults to `false`.
+    pub fn trailer(mut self, trailer: bool) -> Self {
+        self.trailer = trailer;
+        self
+    }
+
+    /// Skip every path for which `exclude` returns `true` while walking a tree.
+    /// Excluded directories aren't descended into.
+    ///
+    /// ```
+    /// # use bitumen::Archiver;
+    /// let archiver = Archiver::new(vec![])
+    ///     .exclude(|path| path.ends_with("target") || path.ends_with(".git"))
+    ///     .build();
+    /// ```
+    pub fn exclude(mut self, exclude: impl Fn(&Path) -> bool + 'static) -> Self {
+        self.excludes.push(Box::new(exclude));
+        self
+    }
+
+    pub fn build(self) -> Archiver<W> {
+        Archiver {
+            writer: self.writer,
+            options: self.options,
+            excludes: self.excludes,
+            links: HashMap::new(),
+            written: 0,
+            stats: ArchiveStats::default(),
+            index: self.index.then(Vec::new),
+            skipped: vec![],
+            #[cfg(feature = "dedup")]
+            chunks: Default::default(),
+            trailer: self.trailer.then(crc32::Crc32::new),
+            before_entry: None,
+            flush_entries: self.flush_entries,
+        }
+    }
+}
+
+impl<W: Write> Archiver<W> {
+    /// Starts configuring an archiver writing to `writer`.
+    #[allow(clippy::new_ret_no_self)]
+    pub fn new(writer: W) -> ArchiverBuilder<W> {
+        ArchiverBuilder {
+            writer,
+            options: ArchiveOptions::default(),
+            excludes: vec![],
+            index: false,
+            trailer: false,
+            flush_entries: false,
+        }
+    }
+
+    /// Archives a single object, without descending into directories,
+    /// for callers walking the tree themselves.
+    ///
+    /// Files with multiple hard links are only stored once, further
+    /// occurrences become hard links to the first one.
+    pub fn add_one(&mut self, path: &Path) -> Result<(), ArchiveError> {
+        let entry = PendingEntry::from_path(path, &self.options)?;
+        self.add_pending(entry)
+    }
+
+    fn add_pending(&mut self, mut entry: PendingEntry<'_>) -> Result<(), ArchiveError> {
+        if let Some(inode) = entry.inode {
+            match self.links.entry(inode) {
+                hash_map::Entry::Occupied(first) => entry.link_to(first.get().clone()),
+                hash_map::Entry::Vacant(slot) => {
+                    slot.insert(entry.path.clone());
+                }
+            }
+        }
+        entry.settle()?;
+        #[cfg(feature = "lz4")]
+        if self.options.lz4 {
+            entry.compress(flags::LZ4, compression::lz4)?;
+        }
+        #[cfg(feature = "flate")]
+        if self.options.compress && !entry.meta.flags.compressed() {
+            let level = self.options.compression_level;
+            entry.compress(0, |body| compression::gzip(body, level))?;
+        }
+        #[cfg(feature = "encrypt")]
+        if let Some(key) = &self.options.key {
+            entry.encrypt(key);
+        }
+        #[cfg(feature = "dedup")]
+        if self.options.dedup {
+            let body_start =
+                self.written + (Metadata::SIZE + entry.path.len() + entry.xattrs.len()) as u64;
+            entry.dedup(&mut self.chunks, body_start)?;
+        }
+        entry.meta.flags = entry.meta.flags.with(self.options.checksum.flag());
+        if let Some(index) = &mut self.index {
+            index.push(index::Record {
+                path: entry.path.clone(),
+                offset: self.written,
+                size: entry.meta.file_size,
+                flags: entry.meta.flags,
+            });
+        }
+        self.write_entry(entry)
+    }
+
+    /// Lets `hook` prepare the writer for every entry, given the entry's length.
+    pub(crate) fn before_entry(&mut self, hook: fn(&mut W, u64) -> io::Result<()>) {
+        self.before_entry = Some(hook);
+    }
+
+    fn write_entry(&mut self, entry: PendingEntry<'_>) -> Result<(), ArchiveError> {
+        if let Some(hook) = self.before_entry {
+            hook(&mut self.writer, entry.len())?;
+        }
+        let path = self
+            .flush_entries
+            .then(|| platform::bytes_to_path(&entry.path));
+        let flags = entry.meta.flags;
+        self.written += match &mut self.trailer {
+            Some(crc) => entry.write(&mut crc32::Writer::new(&mut self.writer, crc))?,
+            None => entry.write(&mut self.writer)?,
+        };
+        // buffered writes only fail once flushed, which still belongs to this entry.
+        if let Some(path) = path {
+            self.writer.flush().map_err(ArchiveError::write(&path))?;
+        }
+        if !flags.is_special() {
+            self.stats.entries += 1;
+            self.stats.files += flags.is_file() as usize;
+            self.stats.dirs += flags.is_dir() as usize;
+        }
+        Ok(())
+    }
+
+    /// Number of bytes [`add_path`](Self::add_path) would write for `path`,
+    /// without reading any file contents.
+    ///
+    /// Compressed, encrypted and deduplicated bodies aren't accounted for.
+    fn estimate(&mut self, path: &Path) -> Result<u64, ArchiveError> {
+        let mut links = HashMap::<_, Vec<u8>>::new();
+        let mut size = 0;
+        for e in self.walk(path)? {
+            let mut entry = match PendingEntry::from_path(&e, &self.options) {
+                Ok(entry) => entry,
+                Err(err) => {
+                    skip(err, &self.options, &mut self.skipped)?;
+                    continue;
+                }
+            };
+            if let Some(inode) = entry.inode {
+                match links.entry(inode) {
+                    hash_map::Entry::Occupied(first) => entry.link_to(first.get().clone()),
+                    hash_map::Entry::Vacant(slot) => {
+                        slot.insert(entry.path.clone());
+                    }
+                }
+            }
+            size += entry.len();
+        }
+        Ok(size)
+    }
+
+    /// Collects the tree below `path` in archiving order,
+    /// all directories before any other object.
+    fn walk(&mut self, path: &Path) -> Result<Vec<PathBuf>, ArchiveError> {
+        fn find(
+            path: &Path,
+            options: &ArchiveOptions,
+            excludes: &[Exclude],
+            skipped: &mut Vec<Skipped>,
+            visited: &mut HashSet<(u64, u64)>,
+            files: &mut Vec<(PathBuf, bool)>,
+        ) -> Result<(), ArchiveError> {
+            if excludes.iter().any(|exclude| exclude(path)) {
+                return Ok(());
+            }
+
+            let metadata = match options.metadata(path) {
+                Ok(metadata) => metadata,
+                Err(e) => return skip(ArchiveError::stat(path)(e), options, skipped),
+            };
+            let is_dir = metadata.is_dir();
+            // a followed symlink can lead back to a directory already walked.
+            if let (true, Some(id)) = (is_dir, platform::identity(&metadata)) {
+                if !visited.insert(id) {
+                    log::warn!(
+                        "Skipping {}, its directory is archived already",
+                        path.display()
+                    );
+                    return Ok(());
+                }
+            }
+            if let (false, Some(since), Ok(modified)) =
+                (is_dir, options.modified_since, metadata.modified())
+            {
+                if modified < since {
+                    return Ok(());
+                }
+            }
+            if let (true, Some(max)) = (metadata.is_file(), options.max_file_size) {
+                if metadata.len() > max {
+                    log::info!(
+                        "Skipping {}, its {}B exceed {max}B",
+                        path.display(),
+                        metadata.len()
+                    );
+                    return Ok(());
+                }
+            }
+            files.push((path.into(), is_dir));
+
+            if is_dir {
+                let entries = match std::fs::read_dir(path) {
+                    Ok(entries) => entries,
+                    Err(e) => return skip(ArchiveError::read(path)(e), options, skipped),
+                };
+                for entry in entries {
+                    match entry {
+                        Ok(entry) => {
+                            find(&entry.path(), options, excludes, skipped, visited, files)?
+                        }
+                        Err(e) => skip(ArchiveError::read(path)(e), options, skipped)?,
+                    }
+                }
+            }
+
+            Ok(())
+        }
+
+        let mut entries = vec![];
+        find(
+            path,
+            &self.options,
+            &self.excludes,
+            &mut self.skipped,
+            &mut HashSet::new(),
+            &mut entries,
+        )?;
+        check_case_collisions(&entries, self.options.case_collisions)?;
+
+        let (mut dirs, mut others): (Vec<_>, Vec<_>) =
+            entries.into_iter().partition(|(_, is_dir)| *is_dir);
+        if self.options.reproducible {
+            dirs.sort();
+            others.sort();
+        }
+        dirs.append(&mut others);
+        Ok(dirs.into_iter().map(|(path, _)| path).collect())
+    }
+
+    /// Archives `size` bytes of `reader` as a file named `path`,
+    /// for contents that don't exist on the filesystem.
+    ///
+    /// A reader that ends early is archived with the bytes it had, further bytes
+    /// are left unread. Beyond 256 KiB, one that ends early is padded with zeros
+    /// and marked with [`flags::RESIZED`] instead, which extraction rejects.
+    pub fn add_reader(
+        &mut self,
+        path: &str,
+        reader: &mut impl Read,
+        size: u64,
+        modified_at: u64,
+    ) -> Result<(), ArchiveError> {
+        let entry = PendingEntry::from_reader(path, reader, size, modified_at)?;
+        self.add_pending(entry)
+    }
+
+    /// Archives the tree below `path`, all directories before any other object.
+    pub fn add_path(&mut self, path: &Path) -> Result<(), ArchiveError> {
+        self.add_path_with_progress(path, &mut |_, _| {})
+    }
+
+    /// Like [`add_path`](Self::add_path), but calls `progress` after every entry
+    /// with its path and the number of bytes written to the archive so far.
+    pub fn add_path_with_progress(
+        &mut self,
+        path: &Path,
+        progress: &mut dyn FnMut(&Path, u64),
+    ) -> Result<(), ArchiveError> {
+        for e in self.walk(path)? {
+            match PendingEntry::from_path(&e, &self.options) {
+                Ok(entry) => self.add_pending(entry)?,
+                Err(err) => {
+                    skip(err, &self.options, &mut self.skipped)?;
+                    continue;
+                }
+            }
+            progress(&e, self.written);
+        }
+        Ok(())
+    }
+
+    /// Like [`add_path`](Self::add_path), but reads file bodies on a thread pool.
+    ///
+    /// Bodies are read into memory in batches and written in the same order
+    /// as [`add_path`](Self::add_path) would, so the archive is identical.
+    #[cfg(feature = "parallel")]
+    pub fn add_path_parallel(&mut self, path: &Path) -> Result<(), ArchiveError> {
+        use rayon::prelude::*;
+
+        /// number of entries held in memory at once.
+        const BATCH: usize = 256;
+
+        for batch in self.walk(path)?.chunks(BATCH) {
+            let entries = batch
+                .par_iter()
+                .map(|e| PendingEntry::from_path(e, &self.options)?.buffer())
+                .collect::<Vec<_>>();
+
+            for entry in entries {
+                match entry {
+                    Ok(entry) => self.add_pending(entry.into())?,
+                    Err(err) => skip(err, &self.options, &mut self.skipped)?,
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Writes the entries of the existing `archive` again, refreshed from the tree below `path`.
+    ///
+    /// Entries whose object was modified after it was archived are archived anew,
+    /// all others are copied as they are without reading the object. Objects
+    /// below `path` that no longer exist are left out and new ones are added
+    /// after all copied entries. Entries outside the tree are copied unchanged.
+    /// The bodies of all entries are checked against their checksums, whether
+    /// they are copied or not.
+    ///
+    /// Modification times are compared in whole seconds, so changes within the
+    /// second the object was archived go unnoticed. Indexes are dropped and
+    /// archives with deduplicated entries are rejected, as copying entries moves them.
+    pub fn update_from(
+        &mut self,
+        archive: &mut impl Read,
+        path: &Path,
+    ) -> Result<UpdateReport, ArchiveError> {
+        let mut report = UpdateReport::default();
+        let walked = self
+            .walk(path)?
+            .into_iter()
+            .map(|e| Ok((platform::path_to_bytes(self.options.stored_path(&e)?), e)))
+            .collect::<Result<Vec<_>, ArchiveError>>()?;
+        let in_tree = walked
+            .iter()
+            .map(|(stored, _)| stored.clone())
+            .collect::<HashSet<_>>();
+        let root = self.options.stored_path(path)?.to_path_buf();
+        let mut archived = HashSet::new();
+        let decode_err = |e: crate::DecodeError| ArchiveError::Io(e.into());
+
+        let mut first = true;
+        loop {
+            let header = match crate::read_header(archive) {
+                Ok(header) => header,
+                Err(crate::DecodeError::Exhausted) => break,
+                Err(e) if first => return Err(decode_err(e)),
+                Err(e) => return Err(decode_err(e.mid_archive())),
+            };
+            first = false;
+            let stored = crate::read_path(&header, archive).map_err(decode_err)?;
+            let xattrs = crate::xattrs::read(&header, &stored, archive).map_err(decode_err)?;
+            let stored_path = platform::bytes_to_path(&stored);
+
+            if header.flags.chunked() {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("can't update deduplicated entry {}", stored_path.display()),
+                )
+                .into());
+            }
+
+            let disk_path = self.options.disk_path(&stored_path);
+            archived.insert(stored.clone());
+            // objects outside the tree aren't looked at, whether they exist or not.
+            let refresh = if header.flags.is_special() || !stored_path.starts_with(&root) {
+                Refresh::Copy
+            } else {
+                match self.options.metadata(&disk_path) {
+                    Err(e) if e.kind() == io::ErrorKind::NotFound => Refresh::Drop,
+                    Ok(metadata) if in_tree.contains(&stored) => {
+                        let modified_at = metadata
+                            .modified()
+                            .ok()
+                            .and_then(|modified| {
+                                modified.duration_since(SystemTime::UNIX_EPOCH).ok()
+                            })
+                            .map_or(0, |since| since.as_secs());
+                        if modified_at > header.modified_at() {
+                            Refresh::Archive
+                        } else {
+                            Refresh::Copy
+                        }
+                    }
+                    // excluded or unreadable.
+                    _ => Refresh::Copy,
+                }
+            };
+
+            if header.flags.is_index() || refresh != Refresh::Copy {
+                // discarded bodies are checked all the same, to not hide corruption.
+                let mut body =
+                    crc32::Reader::for_body(archive.take(header.file_size), header.flags);
+                if io::copy(&mut body, &mut io::sink())? != header.file_size {
+                    log::error!("File contents cut off");
+                    return Err(decode_err(crate::DecodeError::Crop { offset: None }));
+                }
+                let body_checksum = body.digest();
+                let footer = crate::read_footer(archive).map_err(decode_err)?;
+                crate::check_footer(&header, &footer).map_err(decode_err)?;
+                if footer.body_checksum() != body_checksum {
+                    log::error!("Checksum mismatch in {}", stored_path.display());
+                    return Err(decode_err(crate::DecodeError::Checksum { offset: None }));
+                }
+                match refresh {
+                    Refresh::Drop => report.removed.push(stored_path),
+                    Refresh::Archive => {
+                        self.add_one(&disk_path)?;
+                        report.updated.push(stored_path);
+                    }
+                    Refresh::Copy => {}
+                }
+                continue;
+            }
+
+            let xattrs = if header.flags.has_xattr() {
+                crate::xattrs::encode(&xattrs)
+            } else {
+                vec![]
+            };
+            let resized = Rc::default();
+            let mut body = CopiedBody {
+                body: crc32::Reader::for_body(archive.take(header.file_size), header.flags),
+                footer: None,
+                resized: Rc::clone(&resized),
+            };
+            let entry = PendingEntry {
+                meta: header.clone(),
+                path: stored,
+                xattrs,
+                body: Box::new(&mut body),
+                inode: None,
+                settled: true,
+                resized,
+            };
+            let len = entry.len();
+            if let Some(index) = &mut self.index {
+                index.push(index::Record {
+                    path: entry.path.clone(),
+                    offset: self.written,
+                    size: header.file_size,
+                    flags: header.flags,
+                });
+            }
+            let written = self.written;
+            self.write_entry(entry)?;
+            let body_checksum = body.body.digest();
+            let footer = match body.footer {
+                Some(footer) if self.written - written == len => footer.map_err(decode_err)?,
+                _ => {
+                    log::error!("File contents cut off");
+                    return Err(decode_err(crate::DecodeError::Crop { offset: None }));
+                }
+            };
+            crate::check_footer(&header, &footer).map_err(decode_err)?;
+            if footer.body_checksum() != body_checksum {
+                log::error!("Checksum mismatch in {}", stored_path.display());
+                return Err(decode_err(crate::DecodeError::Checksum { offset: None }));
+            }
+            report.copied += 1;
+        }
+
+        for (stored, e) in walked {
+            if archived.contains(&stored) {
+                continue;
+            }
+            match PendingEntry::from_path(&e, &self.options) {
+                Ok(entry) => self.add_pending(entry)?,
+                Err(err) => {
+                    skip(err, &self.options, &mut self.skipped)?;
+                    continue;
+                }
+            }
+            report.added.push(platform::bytes_to_path(&stored));
+        }
+        Ok(report)
+    }
+
+    /// Writes `comment` as the leading record of the archive, see [`read_comment`](crate::read_comment).
+    ///
+    /// Fails if anything has been written already, as only the first entry can be the comment.
+    pub fn set_comment(&mut self, comment: &str) -> Result<(), ArchiveError> {
+        if self.written != 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "the comment has to be written before any entry",
+            )
+            .into());
+        }
+        let body = comment.as_bytes().to_vec();
+        let entry = PendingEntry {
+            meta: Metadata::new(flags::FILE | flags::COMMENT, body.len() as _, 0, 0, 0, 0, 0),
+            path: vec![],
+            xattrs: vec![],
+            body: Box::new(io::Cursor::new(body)),
+            inode: None,
+            settled: true,
+            resized: Rc::default(),
+        };
+        self.write_entry(entry)
+    }
+
+    /// Objects and bytes written so far. Not yet including the index and trailer,
+    /// which are written by [`finish`](Self::finish).
+    pub fn stats(&self) -> ArchiveStats {
+        ArchiveStats {
+            bytes_written: self.written,
+            ..self.stats
+        }
+    }
+
+    /// Objects left out so far in best-effort mode, see
+    /// [`ArchiverBuilder::best_effort`].
+    pub fn skipped(&self) -> &[Skipped] {
+        &self.skipped
+    }
+
+    /// Flushes and returns the underlying writer.
+    pub fn finish(mut self) -> io::Result<W> {
+        if let Some(records) = self.index.take() {
+            let body = index::encode(&records);
+            let entry = PendingEntry {
+                meta: Metadata::new(flags::FILE | flags::INDEX, body.len() as _, 0, 0, 0, 0, 0),
+                path: vec![],
+                xattrs: vec![],
+                body: Box::new(io::Cursor::new(body)),
+                inode: None,
+                settled: true,
+                resized: Rc::default(),
+            };
+            self.write_entry(entry)?;
+        }
+        if let Some(crc) = self.trailer.take() {
+            self.writer.write_all(&trailer::encode(crc.finalize()))?;
+            self.written += trailer::LEN as u64;
+        }
+        self.writer.flush()?;
+        Ok(self.writer)
+    }
+}
+
+/// Archiver for the functions below, batching the small writes every entry takes
+/// into one per entry on the caller's behalf.
+fn buffered<W: Write>(archive: W) -> ArchiverBuilder<io::BufWriter<W>> {
+    let mut builder = Archiver::new(io::BufWriter::new(archive));
+    builder.flush_entries = true;
+    builder
+}
+
+/// Archives exactly the object at `path`, a directory without its contents.
+///
+/// Use an [`Archiver`] and [`Archiver::add_one`] to archive several objects
+/// of a custom traversal, so hard links between them are kept.
+pub fn archive_one(archive: &mut impl Write, path: &Path) -> Result<(), ArchiveError> {
+    let mut archiver = buffered(archive).build();
+    archiver.add_one(path)?;
+    archiver.finish()?;
+    Ok(())
+}
+
+/// Same as [`archive_one`].
+pub fn append_to_archive(archive: &mut impl Write, path: &Path) -> Result<(), ArchiveError> {
+    archive_one(archive, path)
+}
+
+/// Archives `size` bytes of `reader` as a file named `logical_path`, see [`Archiver::add_reader`].
+pub fn append_reader(
+    archive: &mut impl Write,
+    logical_path: &str,
+    reader: &mut impl Read,
+    size: u64,
+    modified_at: u64,
+) -> Result<(), ArchiveError> {
+    let mut archiver = buffered(archive).build();
+    archiver.add_reader(logical_path, reader, size, modified_at)?;
+    archiver.finish()?;
+    Ok(())
+}
+
+/// Like [`append_to_archive`], but stores the body of files gzip compressed.
+#[cfg(feature = "flate")]
+pub fn append_to_archive_compressed(
+    archive: &mut impl Write,
+    path: &Path,
+) -> Result<(), ArchiveError> {
+    let mut archiver = buffered(archive).compress(true).build();
+    archiver.add_one(path)?;
+    archiver.finish()?;
+    Ok(())
+}
+
+/// Appends `path` to the end of an existing archive.
+///
+/// Entries are self-delimiting, so no other part of the archive has to be touched.
+/// A trailer is checked first and then overwritten by the new entry, which is
+/// followed by a trailer over the whole archive again.
+pub fn append_path_to_existing(
+    archive: &mut (impl Read + Write + Seek),
+    path: &Path,
+) -> Result<(), ArchiveError> {
+    archive.rewind()?;
+    let resumed = match trailer::find(archive)? {
+        Some(start) => {
+            trailer::check(archive).map_err(io::Error::from)?;
+            let mut covered = crc32::Reader::new((&mut *archive).take(start));
+            io::copy(&mut covered, &mut io::sink())?;
+            Some(crc32::Crc32::resume(covered.digest()))
+        }
+        None => None,
+    };
+    archive.seek(io::SeekFrom::End(0))?;
+    if resumed.is_some() {
+        archive.seek(io::SeekFrom::Current(-(trailer::LEN as i64)))?;
+    }
+    let mut archiver = buffered(archive).build();
+    archiver.trailer = resumed;
+    archiver.add_one(path)?;
+    archiver.finish()?;
+    Ok(())
+}
+
+/// What was written to an archive, see [`recursive_archive`] and [`Archiver::stats`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ArchiveStats {
+    /// Number of entries, leaving out the comment and index.
+    pub entries: usize,
+    /// Regular files, not counting hard links to them.
+    pub files: usize,
+    pub dirs: usize,
+    /// Length of the archive.
+    pub bytes_written: u64,
+}
+
+/// What [`update`] did with the objects of the tree, named by their archived paths.
+#[derive(Debug, Default)]
+pub struct UpdateReport {
+    /// Number of entries copied from the existing archive.
+    pub copied: usize,
+    /// Objects modified since they were archived, which have been archived anew.
+    pub updated: Vec<PathBuf>,
+    /// Objects that weren't archived before.
+    pub added: Vec<PathBuf>,
+    /// Entries left out as their object no longer exists.
+    pub removed: Vec<PathBuf>,
+}
+
+/// How [`Archiver::update_from`] treats an existing entry.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Refresh {
+    Copy,
+    Archive,
+    Drop,
+}
+
+/// Writes `src_archive` to `out_archive` with the tree below `tree_path` refreshed,
+/// reading only objects modified since they were archived.
+///
+/// See [`Archiver::update_from`] for details, an [`Archiver`] also allows
+/// updating with other options.
+pub fn update(
+    src_archive: &mut impl Read,
+    tree_path: &Path,
+    out_archive: &mut impl Write,
+) -> Result<UpdateReport, ArchiveError> {
+    let mut archiver = buffered(out_archive).build();
+    let report = archiver.update_from(src_archive, tree_path)?;
+    archiver.finish()?;
+    Ok(report)
+}
+
+/// Archives the tree below `path`, all directories before any other object.
+///
+/// Bodies are streamed into `archive`, so trees of any size are archived without
+/// holding them in memory. Only compression buffers a single file at a time.
+/// Writes are batched into one per entry, or a few for large files,
+/// so `archive` doesn't need to be buffered.
+///
+/// Symlinks are stored as links rather than followed, so cycles of them don't
+/// recurse forever, see [`ArchiverBuilder::follow_symlinks`] to follow them.
+///
+/// The archive ends in a trailer, see [`ArchiverBuilder::trailer`].
+///
+/// ```no_run
+/// # use std::{fs::File, path::Path};
+/// let mut archive = File::create("src.bit")?;
+/// bitumen::recursive_archive(&mut archive, Path::new("src"))?;
+/// # Ok::<(), std::io::Error>(())
+/// ```
+pub fn recursive_archive(
+    archive: &mut impl Write,
+    path: &Path,
+) -> Result<ArchiveStats, ArchiveError> {
+    let mut archiver = buffered(archive).trailer(true).build();
+    archiver.add_path(path)?;
+    let mut stats = archiver.stats();
+    archiver.finish()?;
+    stats.bytes_written += trailer::LEN as u64;
+    Ok(stats)
+}
+
+/// Like [`recursive_archive`], but leaves out objects that can't be read
+/// and returns them with the reason instead of failing.
+///
+/// Useful for backing up live directories, where files may vanish
+/// or be inaccessible while walking the tree.
+pub fn recursive_archive_best_effort(
+    archive: &mut impl Write,
+    path: &Path,
+) -> Result<Vec<Skipped>, ArchiveError> {
+    let mut archiver = buffered(archive).best_effort(true).trailer(true).build();
+    archiver.add_path(path)?;
+    let skipped = std::mem::take(&mut archiver.skipped);
+    archiver.finish()?;
+    Ok(skipped)
+}
+
+/// Exact length of the archive [`recursive_archive`] would write for `path`.
+///
+/// Only metadata is read, but the tree may change before it's archived.
+pub fn estimate_archive_size(path: &Path) -> io::Result<u64> {
+    let entries = Archiver::new(io::sink()).build().estimate(path)?;
+    Ok(entries + trailer::LEN as u64)
+}
+
+/// Like [`recursive_archive`], but calls `progress` after every entry
+/// with its path and the number of bytes written so far.
+pub fn recursive_archive_with_progress(
+    archive: &mut impl Write,
+    path: &Path,
+    progress: &mut dyn FnMut(&Path, u64),
+) -> Result<(), ArchiveError> {
+    let mut archiver = buffered(archive).trailer(true).build();
+    archiver.add_path_with_progress(path, progress)?;
+    archiver.finish()?;
+    Ok(())
+}
+
+/// Archives the tree below `path`, encrypting file bodies with `key`.
+///
+/// Metadata and paths stay readable, so the archive can still be listed.
+/// Like [`recursive_archive`], it ends in a trailer.
+#[cfg(feature = "encrypt")]
+pub fn recursive_archive_encrypted(
+    archive: &mut impl Write,
+    path: &Path,
+    key: &[u8; 32],
+) -> Result<(), ArchiveError> {
+    let mut archiver = buffered(archive).encrypt(key).trailer(true).build();
+    archiver.add_path(path)?;
+    archiver.finish()?;
+    Ok(())
+}
+
+/// Like [`recursive_archive`], but reads file bodies on a thread pool.
+#[cfg(feature = "parallel")]
+pub fn recursive_archive_parallel(
+    archive: &mut impl Write,
+    path: &Path,
+) -> Result<(), ArchiveError> {
+    let mut archiver = buffered(archive).trailer(true).build();
+    archiver.add_path_parallel(path)?;
+    archiver.finish()?;
+    Ok(())
+}
+
+/// Archives the tree below `path`, storing file bodies gzip compressed.
+///
+/// Metadata stays uncompressed, so the archive can still be listed by seeking.
+/// Like [`recursive_archive`], it ends in a trailer.
+#[cfg(feature = "flate")]
+pub fn recursive_archive_compressed(
+    archive: &mut impl Write,
+    path: &Path,
+) -> Result<(), ArchiveError> {
+    let mut archiver = buffered(archive).compress(true).trailer(true).build();
+    archiver.add_path(path)?;
+    archiver.finish()?;
+    Ok(())
+}
+
+#[test]
+fn append_to_existing_archive() {
+    let dir = tempfile::tempdir().unwrap();
+    for name in ["a.txt", "b.txt", "c.txt"] {
+        std::fs::write(dir.path().join(name), name).unwrap();
+    }
+
+    let mut archive = io::Cursor::new(vec![]);
+    append_to_archive(&mut archive, &dir.path().join("a.txt")).unwrap();
+    append_to_archive(&mut archive, &dir.path().join("b.txt")).unwrap();
+
+    archive.set_position(0);
+    append_path_to_existing(&mut archive, &dir.path().join("c.txt")).unwrap();
+
+    archive.set_position(0);
+    let entries = list(&mut archive).unwrap();
+    let names = entries
+        .iter()
+        .map(|e| e.path.file_name().unwrap())
+        .collect::<Vec<_>>();
+    assert_eq!(names, ["a.txt", "b.txt", "c.txt"]);
+
+    // the trailer of the base archive moves behind the appended entries.
+    let mut archive = io::Cursor::new(vec![]);
+    recursive_archive(&mut archive, &dir.path().join("a.txt")).unwrap();
+    append_path_to_existing(&mut archive, &dir.path().join("b.txt")).unwrap();
+    append_path_to_existing(&mut archive, &dir.path().join("c.txt")).unwrap();
+    let report = crate::verify(&mut io::Cursor::new(archive.get_ref())).unwrap();
+    assert_eq!((report.ok, report.corrupt), (3, 0));
+    archive.set_position(0);
+    assert!(trailer::find(&mut archive).unwrap().is_some());
+
+    let mut corrupt = archive.into_inner();
+    corrupt[0] ^= 0x01;
+    let len = corrupt.len();
+    let mut corrupt = io::Cursor::new(corrupt);
+    let err = append_path_to_existing(&mut corrupt, &dir.path().join("b.txt")).unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    assert_eq!(corrupt.get_ref().len(), len);
+}
+
+#[cfg(unix)]
+#[test]
+fn recursive_archive_reports_stats() {
+    let dir = tempfile::tempdir().unwrap();
+    std::fs::create_dir(dir.path().join("sub")).unwrap();
+    std::fs::write(dir.path().join("a.txt"), b"abc").unwrap();
+    std::fs::write(dir.path().join("sub/b.txt"), b"defg").unwrap();
+    std::os::unix::fs::symlink("a.txt", dir.path().join("link")).unwrap();
+
+    let mut archive = vec![];
+    let stats = recursive_archive(&mut archive, dir.path()).unwrap();
+    assert_eq!(
+        stats,
+        ArchiveStats {
+            entries: 5,
+            files: 2,
+            dirs: 2,
+            bytes_written: archive.len() as u64,
+        }
+    );
+
+    let summary = crate::summarize(&mut io::Cursor::new(&archive)).unwrap();
+    assert_eq!(summary.entries, stats.entries);
+    assert_eq!(
+        summary.body_bytes + summary.metadata_bytes,
+        stats.bytes_written
+    );
+}
+
+#[test]
+fn archiving_batches_small_writes() {
+    /// Counts the calls reaching the underlying writer.
+    struct Counting {
+        bytes: Vec<u8>,
+        writes: usize,
+    }
+
+    impl Write for Counting {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.writes += 1;
+            self.bytes.extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    let dir = tempfile::tempdir().unwrap();
+    for i in 0..20 {
+        std::fs::write(dir.path().join(format!("{i}.txt")), b"small").unwrap();
+    }
+
+    let mut counting = Counting {
+        bytes: vec![],
+        writes: 0,
+    };
+    recursive_archive(&mut counting, dir.path()).unwrap();
+    let mut plain = vec![];
+    recursive_archive(&mut plain, dir.path()).unwrap();
+
+    // everything arrived before returning, in one write per entry and the trailer.
+    assert_eq!(counting.bytes, plain);
+    assert_eq!(counting.writes, 22);
+}
+
+#[test]
+fn short_writes_produce_valid_archive() {
+    /// Accepts at most three bytes per call, like a bounded buffer would.
+    struct Trickle(Vec<u8>);
+
+    impl Write for Trickle {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            let n = buf.len().min(3);
+            self.0.extend_from_slice(&buf[..n]);
+            Ok(n)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    let dir = tempfile::tempdir().unwrap();
+    std::fs::write(dir.path().join("a.txt"), b"some file contents").unwrap();
+
+    let mut trickle = Trickle(vec![]);
+    recursive_archive(&mut trickle, dir.path()).unwrap();
+    let mut plain = vec![];
+    recursive_archive(&mut plain, dir.path()).unwrap();
+
+    assert_eq!(trickle.0, plain);
+    assert_eq!(
+        crate::read(&mut io::Cursor::new(trickle.0), &mut crate::LogObserver),
+        Ok(())
+    );
+}
+
+#[test]
+fn archive_one_doesnt_recurse() {
+    let dir = tempfile::tempdir().unwrap();
+    let file = dir.path().join("a.txt");
+    std::fs::write(&file, b"abc").unwrap();
+
+    let mut archive = vec![];
+    archive_one(&mut archive, dir.path()).unwrap();
+    archive_one(&mut archive, &file).unwrap();
+    let entries = list(&mut io::Cursor::new(&archive)).unwrap();
+    assert_eq!(entries.len(), 2);
+    assert_eq!(entries[0].kind, crate::EntryKind::Directory);
+    assert_eq!(entries[1].size, 3);
+
+    let mut out = vec![];
+    let found = crate::extract_one(
+        &mut io::Cursor::new(&archive),
+        file.to_str().unwrap(),
+        &mut out,
+    );
+    assert!(found.unwrap());
+    assert_eq!(out, b"abc");
+}
+
+#[test]
+fn estimate_matches_archive_length() {
+    let dir = tempfile::tempdir().unwrap();
+    std::fs::create_dir(dir.path().join("sub")).unwrap();
+    std::fs::write(dir.path().join("a.txt"), b"abc").unwrap();
+    std::fs::write(dir.path().join("sub/b.txt"), vec![7u8; 10_000]).unwrap();
+    #[cfg(unix)]
+    {
+        std::os::unix::fs::symlink("a.txt", dir.path().join("link")).unwrap();
+        std::fs::hard_link(dir.path().join("a.txt"), dir.path().join("hard.txt")).unwrap();
+    }
+
+    let mut archive = vec![];
+    recursive_archive(&mut archive, dir.path()).unwrap();
+    assert_eq!(
+        estimate_archive_size(dir.path()).unwrap(),
+        archive.len() as u64
+    );
+}
+
+#[test]
+fn modified_since_skips_unchanged_files() {
+    let dir = tempfile::tempdir().unwrap();
+    std::fs::create_dir(dir.path().join("sub")).unwrap();
+    std::fs::write(dir.path().join("sub/old.txt"), b"old").unwrap();
+    std::fs::write(dir.path().join("sub/new.txt"), b"new").unwrap();
+    let old = filetime::FileTime::from_unix_time(1_000_000_000, 0);
+    filetime::set_file_mtime(dir.path().join("sub/old.txt"), old).unwrap();
+    filetime::set_file_mtime(dir.path().join("sub"), old).unwrap();
+
+    let since = SystemTime::UNIX_EPOCH + std::time::Duration::from_secs(1_500_000_000);
+    let mut archiver = Archiver::new(vec![])
+        .strip_prefix(dir.path())
+        .modified_since(since)
+        .build();
+    archiver.add_path(dir.path()).unwrap();
+    let archive = archiver.finish().unwrap();
+    let paths = list(&mut io::Cursor::new(&archive))
+        .unwrap()
+        .into_iter()
+        .map(|entry| entry.path)
+        .collect::<Vec<_>>();
+    assert_eq!(
+        paths,
+        [
+            Path::new(""),
+            Path::new("sub"),
+            &Path::new("sub").join("new.txt")
+        ]
+    );
+}
+
+#[test]
+fn max_file_size_skips_large_files() {
+    let dir = tempfile::tempdir().unwrap();
+    std::fs::create_dir(dir.path().join("sub")).unwrap();
+    std::fs::write(dir.path().join("small.txt"), [0u8; 100]).unwrap();
+    std::fs::write(dir.path().join("sub/limit.txt"), [0u8; 1000]).unwrap();
+    std::fs::write(dir.path().join("sub/huge.bin"), [0u8; 1001]).unwrap();
+
+    let mut archiver = Archiver::new(vec![])
+        .strip_prefix(dir.path())
+        .max_file_size(1000)
+        .build();
+    archiver.add_path(dir.path()).unwrap();
+    let archive = archiver.finish().unwrap();
+    let mut paths = list(&mut io::Cursor::new(&archive))
+        .unwrap()
+        .into_iter()
+        .map(|entry| entry.path)
+        .collect::<Vec<_>>();
+    paths.sort();
+    assert_eq!(
+        paths,
+        [
+            Path::new(""),
+            Path::new("small.txt"),
+            Path::new("sub"),
+            &Path::new("sub").join("limit.txt")
+        ]
+    );
+}
+
+#[test]
+fn update_rearchives_only_changed_files() {
+    let dir = tempfile::tempdir().unwrap();
+    let old = filetime::FileTime::from_unix_time(1_000_000_000, 0);
+    for name in ["a.txt", "b.txt", "c.txt"] {
+        std::fs::write(dir.path().join(name), name).unwrap();
+        filetime::set_file_mtime(dir.path().join(name), old).unwrap();
+    }
+    filetime::set_file_mtime(dir.path(), old).unwrap();
+    let mut archive = vec![];
+    recursive_archive(&mut archive, dir.path()).unwrap();
+
+    std::fs::write(dir.path().join("b.txt"), b"changed").unwrap();
+    std::fs::remove_file(dir.path().join("c.txt")).unwrap();
+    std::fs::write(dir.path().join("d.txt"), b"new").unwrap();
+    filetime::set_file_mtime(dir.path(), old).unwrap();
+
+    let mut updated = vec![];
+    let report = update(&mut archive.as_slice(), dir.path(), &mut updated).unwrap();
+    assert_eq!(report.updated, [dir.path().join("b.txt")]);
+    assert_eq!(report.added, [dir.path().join("d.txt")]);
+    assert_eq!(report.removed, [dir.path().join("c.txt")]);
+    assert_eq!(report.copied, 2);
+
+    let read = |name: &str| {
+        let mut body = vec![];
+        crate::extract_one(
+            &mut io::Cursor::new(&updated),
+            dir.path().join(name),
+            &mut body,
+        )
+        .unwrap()
+        .then_some(body)
+    };
+    assert_eq!(read("a.txt").unwrap(), b"a.txt");
+    assert_eq!(read("b.txt").unwrap(), b"changed");
+    assert_eq!(read("c.txt"), None);
+    assert_eq!(read("d.txt").unwrap(), b"new");
+    assert_eq!(
+        crate::verify(&mut io::Cursor::new(&updated))
+            .unwrap()
+            .corrupt,
+        0
+    );
+
+    // nothing changed since, so everything is copied.
+    let mut again = vec![];
+    let report = update(&mut updated.as_slice(), dir.path(), &mut again).unwrap();
+    assert_eq!(report.copied, 4);
+    assert!(report.updated.is_empty() && report.added.is_empty());
+    assert_eq!(again, updated);
+}
+
+#[test]
+fn update_keeps_entries_outside_the_tree() {
+    let dir = tempfile::tempdir().unwrap();
+    let old = filetime::FileTime::from_unix_time(1_000_000_000, 0);
+    std::fs::write(dir.path().join("a.txt"), b"abc").unwrap();
+    filetime::set_file_mtime(dir.path().join("a.txt"), old).unwrap();
+    filetime::set_file_mtime(dir.path(), old).unwrap();
+    let mut archive = vec![];
+    // not on disk, neither relative to the working directory nor below the tree.
+    append_reader(&mut archive, "generated/x.txt", &mut &b"x"[..], 1, 0).unwrap();
+    recursive_archive(&mut archive, dir.path()).unwrap();
+    let trailer = trailer::find(&mut io::Cursor::new(&archive)).unwrap();
+    archive.truncate(trailer.unwrap() as usize);
+
+    std::fs::write(dir.path().join("a.txt"), b"changed").unwrap();
+    filetime::set_file_mtime(dir.path(), old).unwrap();
+    let mut updated = vec![];
+    let report = update(&mut archive.as_slice(), dir.path(), &mut updated).unwrap();
+    assert!(report.removed.is_empty());
+    assert_eq!(report.updated, [dir.path().join("a.txt")]);
+    assert_eq!(report.copied, 2);
+    let mut body = vec![];
+    let found = crate::extract_one(&mut io::Cursor::new(&updated), "generated/x.txt", &mut body);
+    assert!(found.unwrap());
+    assert_eq!(body, b"x");
+
+    // the body of a refreshed entry is checked even though it's replaced.
+    let body_start = archive.len() - Metadata::SIZE - 3;
+    archive[body_start] ^= 0x01;
+    let err = update(&mut archive.as_slice(), dir.path(), &mut vec![]).unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+}
+
+#[test]
+fn update_keeps_resized_mark() {
+    let mut archive = vec![];
+    let size = MAX_BUFFERED + 1;
+    append_reader(&mut archive, "short.bin", &mut &[1u8; 100][..], size, 0).unwrap();
+    let extract_one = |archive: &[u8]| {
+        crate::extract_one(&mut io::Cursor::new(archive), "short.bin", &mut io::sink())
+    };
+    assert!(matches!(
+        extract_one(&archive),
+        Err(crate::DecodeError::Resized { .. })
+    ));
+
+    let dir = tempfile::tempdir().unwrap();
+    let mut updated = vec![];
+    let report = update(&mut archive.as_slice(), dir.path(), &mut updated).unwrap();
+    assert_eq!(report.copied, 1);
+    assert!(matches!(
+        extract_one(&updated),
+        Err(crate::DecodeError::Resized { .. })
+    ));
+    let report = crate::verify(&mut io::Cursor::new(&updated)).unwrap();
+    assert_eq!(report.resized_paths, ["short.bin"]);
+}
+
+#[test]
+fn mtime_before_epoch_is_clamped() {
+    let dir = tempfile::tempdir().unwrap();
+    let file = dir.path().join("old.txt");
+    std::fs::write(&file, b"from the sixties").unwrap();
+    let mtime = filetime::FileTime::from_unix_time(-86_400 * 365, 0);
+    filetime::set_file_mtime(&file, mtime).unwrap();
+
+    let mut archive = vec![];
+    archive_one(&mut archive, &file).unwrap();
+    let entries = list(&mut io::Cursor::new(&archive)).unwrap();
+    assert_eq!(entries[0].modified_at_secs(), 0);
+}
+
+#[test]
+fn reproducible_archives_are_identical() {
+    let archive = |names: [&str; 3], mtime: i64| {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::create_dir(dir.path().join("sub")).unwrap();
+        let mtime = filetime::FileTime::from_unix_time(mtime, 0);
+        for name in names {
+            std::fs::write(dir.path().join(name), name).unwrap();
+            filetime::set_file_mtime(dir.path().join(name), mtime).unwrap();
+        }
+        filetime::set_file_mtime(dir.path().join("sub"), mtime).unwrap();
+
+        let mut archiver = Archiver::new(vec![])
+            .strip_prefix(dir.path())
+            .reproducible(true)
+            .build();
+        archiver.add_path(dir.path()).unwrap();
+        archiver.finish().unwrap()
+    };
+
+    let first = archive(["a.txt", "sub/b.txt", "c.txt"], 1_000_000_000);
+    let second = archive(["c.txt", "a.txt", "sub/b.txt"], 1_500_000_000);
+    assert_eq!(first, second);
+    assert!(list(&mut io::Cursor::new(&first))
+        .unwrap()
+        .iter()
+        .all(|entry| entry.modified_at_secs() == 0));
+}
+
+#[test]
+fn strip_prefix_stores_relative_paths() {
+    let dir = tempfile::tempdir().unwrap();
+    std::fs::create_dir(dir.path().join("src")).unwrap();
+    std::fs::write(dir.path().join("src/main.rs"), b"fn main() {}").unwrap();
+
+    let mut archiver = Archiver::new(vec![]).strip_prefix(dir.path()).build();
+    archiver.add_path(&dir.path().join("src")).unwrap();
+    let archive = archiver.finish().unwrap();
+    let paths = list(&mut io::Cursor::new(&archive))
+        .unwrap()
+        .into_iter()
+        .map(|entry| entry.path)
+        .collect::<Vec<_>>();
+    assert_eq!(paths, [Path::new("src"), &Path::new("src").join("main.rs")]);
+
+    let mut archiver = Archiver::new(vec![])
+        .strip_prefix(dir.path().join("src"))
+        .build();
+    let err = archiver.add_one(dir.path()).unwrap_err();
+    assert!(matches!(err, ArchiveError::OutsidePrefix { .. }));
+    assert_eq!(err.path(), Some(dir.path()));
+}
+
+#[test]
+fn paths_up_to_the_maximum_length() {
+    let longest = "a/".repeat(MAX_PATH_LEN / 2 - 1) + "bc";
+    assert_eq!(longest.len(), MAX_PATH_LEN);
+    let mut archiver = Archiver::new(vec![]).build();
+    archiver
+        .add_reader(&longest, &mut &b"deep"[..], 4, 0)
+        .unwrap();
+    let archive = archiver.finish().unwrap();
+    let entries = list(&mut io::Cursor::new(&archive)).unwrap();
+    assert_eq!(entries[0].path, Path::new(&longest));
+    let mut body = vec![];
+    assert!(extract::extract_one(&mut io::Cursor::new(&archive), &longest, &mut body).unwrap());
+    assert_eq!(body, b"deep");
+
+    let too_long = longest + "d";
+    let mut archiver = Archiver::new(vec![]).build();
+    let err = archiver
+        .add_reader(&too_long, &mut &b"deep"[..], 4, 0)
+        .unwrap_err();
+    assert!(matches!(err, ArchiveError::PathTooLong { len, .. } if len == MAX_PATH_LEN + 1));
+    assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+    assert!(archiver.finish().unwrap().is_empty());
+}
+
+#[test]
+fn errors_name_path_and_operation() {
+    let dir = tempfile::tempdir().unwrap();
+    let missing = dir.path().join("missing.txt");
+
+    let err = append_to_archive(&mut vec![], &missing).unwrap_err();
+    assert!(matches!(err, ArchiveError::Stat { .. }));
+    assert_eq!(err.path(), Some(missing.as_path()));
+    assert_eq!(err.kind(), io::ErrorKind::NotFound);
+    assert!(err.to_string().contains("missing.txt"));
+    let err = io::Error::from(err);
+    assert_eq!(err.kind(), io::ErrorKind::NotFound);
+
+    struct Full;
+    impl Write for Full {
+        fn write(&mut self, _: &[u8]) -> io::Result<usize> {
+            Err(io::ErrorKind::StorageFull.into())
+        }
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+    let file = dir.path().join("a.txt");
+    std::fs::write(&file, b"abc").unwrap();
+    let err = append_to_archive(&mut Full, &file).unwrap_err();
+    assert!(matches!(err, ArchiveError::Write { .. }));
+    assert_eq!(err.path(), Some(file.as_path()));
+}
+
+#[test]
+fn reports_paths_differing_in_case() {
+    let dir = tempfile::tempdir().unwrap();
+    std::fs::write(dir.path().join("File.txt"), b"upper").unwrap();
+    std::fs::write(dir.path().join("file.txt"), b"lower").unwrap();
+    if std::fs::read(dir.path().join("File.txt")).unwrap() != b"upper" {
+        return; // the filesystem is case-insensitive itself.
+    }
+
+    let mut archive = vec![];
+    let mut archiver = Archiver::new(&mut archive)
+        .case_collisions(CaseCollisions::Error)
+        .build();
+    let err = archiver.add_path(dir.path()).unwrap_err();
+    let ArchiveError::CaseCollision { path, other } = &err else {
+        panic!("{err}");
+    };
+    let mut names = [path, other].map(|path| path.file_name().unwrap().to_owned());
+    names.sort();
+    assert_eq!(names, ["File.txt", "file.txt"]);
+    assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+    archiver.finish().unwrap();
+    assert!(archive.is_empty());
+
+    for mode in [CaseCollisions::Warn, CaseCollisions::Ignore] {
+        let mut archiver = Archiver::new(vec![]).case_collisions(mode).build();
+        archiver.add_path(dir.path()).unwrap();
+        let archive = archiver.finish().unwrap();
+        assert_eq!(list(&mut io::Cursor::new(archive)).unwrap().len(), 3);
+    }
+}
+
+#[cfg(unix)]
+#[test]
+fn best_effort_skips_unreadable_objects() {
+    let dir = tempfile::tempdir().unwrap();
+    std::fs::write(dir.path().join("a.txt"), b"abc").unwrap();
+    std::fs::write(dir.path().join("b.txt"), b"defg").unwrap();
+    // sockets can't be archived, unlike unreadable files even as root.
+    let socket = dir.path().join("socket");
+    let _listener = std::os::unix::net::UnixListener::bind(&socket).unwrap();
+
+    let err = recursive_archive(&mut vec![], dir.path()).unwrap_err();
+    assert!(matches!(err, ArchiveError::Unsupported { .. }));
+
+    let mut archive = vec![];
+    let skipped = recursive_archive_best_effort(&mut archive, dir.path()).unwrap();
+    assert_eq!(skipped.len(), 1);
+    assert_eq!(skipped[0].0, socket);
+    assert_eq!(skipped[0].1.kind(), io::ErrorKind::Unsupported);
+
+    let entries = list(&mut io::Cursor::new(&archive)).unwrap();
+    assert_eq!(entries.len(), 3);
+    let dest = tempfile::tempdir().unwrap();
+    extract(&mut io::Cursor::new(&archive), dest.path()).unwrap();
+}
+
+#[test]
+fn progress_reports_every_entry() {
+    let dir = tempfile::tempdir().unwrap();
+    std::fs::create_dir(dir.path().join("sub")).unwrap();
+    std::fs::write(dir.path().join("a.txt"), b"abc").unwrap();
+    std::fs::write(dir.path().join("sub/b.txt"), b"defg").unwrap();
+
+    let mut calls = vec![];
+    let mut archive = vec![];
+    recursive_archive_with_progress(&mut archive, dir.path(), &mut |path, written| {
+        calls.push((path.to_path_buf(), written))
+    })
+    .unwrap();
+
+    assert_eq!(
+        calls.len(),
+        list(&mut io::Cursor::new(&archive)).unwrap().len()
+    );
+    assert!(calls.windows(2).all(|w| w[0].1 < w[1].1));
+    // the trailer follows the last entry.
+    let entries_len = (archive.len() - trailer::LEN) as u64;
+    assert_eq!(calls.last().unwrap().1, entries_len);
+}
+
+#[test]
+fn append_reader_archives_generated_data() {
+    let mut archive = vec![];
+    let mut data = io::repeat(b'x').take(1000);
+    append_reader(&mut archive, "gen/x.txt", &mut data, 1000, 1_700_000_000).unwrap();
+    append_reader(&mut archive, "gen/y.txt", &mut &b"why"[..], 3, 0).unwrap();
+    assert_eq!(
+        crate::read_stream(&mut archive.as_slice(), &mut crate::LogObserver),
+        Ok(())
+    );
+
+    let entries = list(&mut io::Cursor::new(&archive)).unwrap();
+    assert_eq!(entries[0].path, Path::new("gen/x.txt"));
+    assert_eq!(entries[0].size, 1000);
+    assert_eq!(entries[0].modified_at_secs(), 1_700_000_000);
+
+    let mut body = vec![];
+    assert!(crate::extract_one(&mut io::Cursor::new(&archive), "gen/y.txt", &mut body).unwrap());
+    assert_eq!(body, b"why");
+}
+
+#[test]
+fn changed_file_size_is_recorded() {
+    let entry = |content: &[u8], recorded: u64, settled: bool| {
+        let path = Path::new("changing.txt");
+        let resized = Rc::default();
+        let body: Box<dyn Read> = match settled {
+            // as larger bodies are streamed.
+            true => Box::new(FixedSize::new(
+                io::Cursor::new(content.to_vec()),
+                recorded,
+                path,
+                Rc::clone(&resized),
+            )),
+            false => Box::new(io::Cursor::new(content.to_vec())),
+        };
+        PendingEntry {
+            meta: Metadata::new(flags::FILE, recorded, 12, 0, 0, 0, 0),
+            path: platform::path_to_bytes(path),
+            xattrs: vec![],
+            body,
+            inode: None,
+            settled,
+            resized,
+        }
+    };
+    let extract_one = |archive: &[u8]| {
+        let mut body = vec![];
+        crate::extract_one(&mut io::Cursor::new(archive), "changing.txt", &mut body)?;
+        Ok(body)
+    };
+
+    // smaller bodies are read before the header is written, which records their real size.
+    for content in [&b"abc"[..], b"abcdefghij", b"abcdefghijkl"] {
+        let mut entry = entry(content, 10, false);
+        entry.settle().unwrap();
+        let mut archive = vec![];
+        assert_eq!(entry.write(&mut archive).unwrap(), archive.len() as u64);
+        let report = crate::verify(&mut io::Cursor::new(&archive)).unwrap();
+        assert_eq!((report.ok, report.corrupt), (1, 0));
+        assert!(report.resized_paths.is_empty());
+        assert_eq!(extract_one(&archive).unwrap(), content);
+    }
+
+    for (content, stored, resized) in [
+        (&b"abcdefghij"[..], &b"abcdefghij"[..], false),
+        (&b"abc"[..], &b"abc\0\0\0\0\0\0\0"[..], true),
+        (&b"abcdefghijkl"[..], &b"abcdefghij"[..], true),
+    ] {
+        // the entry is well-formed either way, with the change marked on its footer.
+        let mut archive = vec![];
+        entry(content, 10, true).write(&mut archive).unwrap();
+        assert_eq!(archive.len(), 80 + 12 + 10);
+        let report = crate::verify(&mut io::Cursor::new(&archive)).unwrap();
+        assert_eq!((report.ok, report.corrupt), (1, 0));
+        let marked = report.resized_paths == ["changing.txt"];
+        assert_eq!(marked, resized);
+
+        // but the padded or cut off contents aren't restored.
+        match resized {
+            true => assert_eq!(
+                extract_one(&archive),
+                Err(crate::DecodeError::Resized {
+                    offset: Some(archive.len() as u64 - Metadata::SIZE as u64)
+                })
+            ),
+            false => assert_eq!(extract_one(&archive).unwrap(), stored),
+        }
+    }
+
+    // readers may hold more or less than announced.
+    let mut archive = vec![];
+    append_reader(&mut archive, "a.txt", &mut &b"abcdef"[..], 3, 0).unwrap();
+    append_reader(&mut archive, "b.txt", &mut &b"ab"[..], 3, 0).unwrap();
+    let mut body = vec![];
+    assert!(crate::extract_one(&mut io::Cursor::new(&archive), "a.txt", &mut body).unwrap());
+    assert_eq!(body, b"abc");
+    body.clear();
+    assert!(crate::extract_one(&mut io::Cursor::new(&archive), "b.txt", &mut body).unwrap());
+    assert_eq!(body, b"ab");
+}
+
+#[test]
+fn changed_sparse_file_size_is_recorded() {
+    let mut scanned = vec![0u8; 3 * 4096];
+    scanned[4096] = 1;
+    let segments = sparse::scan(&mut scanned.as_slice()).unwrap();
+    let shrunk = &scanned[..4096 + 10];
+    let grown = [&scanned[..], b"more"].concat();
+
+    for (content, resized) in [(&scanned[..], false), (shrunk, true), (&grown[..], true)] {
+        let shared = Rc::default();
+        let encoder = sparse::Encoder::new(
+            io::Cursor::new(content),
+            segments.clone(),
+            Rc::clone(&shared),
+        );
+        let path = Path::new("sparse.bin");
+        let size = sparse::stored_size(&segments);
+        let entry = PendingEntry {
+            meta: Metadata::new(flags::FILE | flags::SPARSE, size, 10, 0, 0, 0, 0),
+            path: platform::path_to_bytes(path),
+            xattrs: vec![],
+            body: Box::new(encoder.unwrap()),
+            inode: None,
+            settled: true,
+            resized: shared,
+        };
+        let mut archive = vec![];
+        entry.write(&mut archive).unwrap();
+
+        let report = crate::verify(&mut io::Cursor::new(&archive)).unwrap();
+        assert_eq!((report.ok, report.corrupt), (1, 0));
+        assert_eq!(report.resized_paths == ["sparse.bin"], resized);
+
+        let mut body = vec![];
+        let extracted = crate::extract_one(&mut io::Cursor::new(&archive), "sparse.bin", &mut body);
+        match resized {
+            true => assert_eq!(
+                extracted,
+                Err(crate::DecodeError::Resized {
+                    offset: Some(archive.len() as u64 - Metadata::SIZE as u64)
+                })
+            ),
+            false => assert!(extracted.unwrap() && body == scanned),
+        }
+    }
+}
+
+#[test]
+fn archiver_options() {
+    let dir = tempfile::tempdir().unwrap();
+    std::fs::write(dir.path().join("a.txt"), b"abc").unwrap();
+
+    let mut archiver = Archiver::new(vec![]).preserve_perms(false).build();
+    archiver.add_path(dir.path()).unwrap();
+    let archive = archiver.finish().unwrap();
+
+    let entries = list(&mut io::Cursor::new(archive)).unwrap();
+    assert_eq!(entries.len(), 2);
+    assert!(entries.iter().all(|e| e.perms == 0));
+}
+
+#[test]
+fn archiver_excludes_paths() {
+    let dir = tempfile::tempdir().unwrap();
+    std::fs::create_dir_all(dir.path().join("target/debug")).unwrap();
+    std::fs::write(dir.path().join("target/debug/big.bin"), b"build output").unwrap();
+    std::fs::write(dir.path().join("main.rs"), b"fn main() {}").unwrap();
+
+    let mut archiver = Archiver::new(vec![])
+        .exclude(|path| path.ends_with("target"))
+        .build();
+    archiver.add_path(dir.path()).unwrap();
+    let archive = archiver.finish().unwrap();
+
+    let entries = list(&mut io::Cursor::new(archive)).unwrap();
+    assert_eq!(entries.len(), 2);
+    assert!(entries
+        .iter()
+        .all(|e| !e.path.starts_with(dir.path().join("target"))));
+    assert!(entries.iter().any(|e| e.path.ends_with("main.rs")));
+}
+
+#[cfg(unix)]
+#[test]
+fn archiver_follows_symlinks() {
+    let dir = tempfile::tempdir().unwrap();
+    std::fs::write(dir.path().join("a.txt"), b"abc").unwrap();
+    std::os::unix::fs::symlink("a.txt", dir.path().join("link")).unwrap();
+
+    let kinds = |follow: bool| {
+        let mut archiver = Archiver::new(vec![]).follow_symlinks(follow).build();
+        archiver.add_path(dir.path()).unwrap();
+        let archive = archiver.finish().unwrap();
+        let entries = list(&mut io::Cursor::new(archive)).unwrap();
+        let link = entries.iter().find(|e| e.path.ends_with("link")).unwrap();
+        (link.kind, link.size)
+    };
+
+    assert_eq!(kinds(false), (EntryKind::SoftLink, 5));
+    assert_eq!(kinds(true), (EntryKind::File, 3));
+}
+
+#[cfg(unix)]
+#[test]
+fn symlink_loops_are_stored_as_links() {
+    let dir = tempfile::tempdir().unwrap();
+    std::fs::create_dir(dir.path().join("sub")).unwrap();
+    std::fs::write(dir.path().join("sub/a.txt"), b"abc").unwrap();
+    std::os::unix::fs::symlink("..", dir.path().join("sub/up")).unwrap();
+    std::os::unix::fs::symlink("loop", dir.path().join("loop")).unwrap();
+
+    let mut archive = vec![];
+    recursive_archive(&mut archive, dir.path()).unwrap();
+    let entries = list(&mut io::Cursor::new(archive)).unwrap();
+
+    assert_eq!(entries.len(), 5);
+    let kind = |name: &str| {
+        entries
+            .iter()
+            .find(|e| e.path.ends_with(name))
+            .unwrap()
+            .kind
+    };
+    assert_eq!(kind("up"), EntryKind::SoftLink);
+    assert_eq!(kind("loop"), EntryKind::SoftLink);
+    assert_eq!(kind("a.txt"), EntryKind::File);
+}
+
+#[cfg(unix)]
+#[test]
+fn followed_symlink_loops_terminate() {
+    let dir = tempfile::tempdir().unwrap();
+    std::fs::create_dir_all(dir.path().join("sub/deeper")).unwrap();
+    std::fs::write(dir.path().join("sub/a.txt"), b"abc").unwrap();
+    std::os::unix::fs::symlink("..", dir.path().join("sub/deeper/up")).unwrap();
+    std::os::unix::fs::symlink("sub", dir.path().join("alias")).unwrap();
+
+    let mut archiver = Archiver::new(vec![]).follow_symlinks(true).build();
+    archiver.add_path(dir.path()).unwrap();
+    let archive = archiver.finish().unwrap();
+    let entries = list(&mut io::Cursor::new(archive)).unwrap();
+
+    // every real directory once, through whichever path was walked first.
+    let dirs = entries
+        .iter()
+        .filter(|e| e.kind == EntryKind::Directory)
+        .count();
+    assert_eq!(dirs, 3);
+    let files = entries.iter().filter(|e| e.kind == EntryKind::File).count();
+    assert_eq!(files, 1);
+}
+
+#[cfg(unix)]
+#[test]
+fn hard_links_store_body_once() {
+    let dir = tempfile::tempdir().unwrap();
+    let contents = vec![7u8; 10_000];
+    std::fs::write(dir.path().join("a.bin"), &contents).unwrap();
+    std::fs::hard_link(dir.path().join("a.bin"), dir.path().join("b.bin")).unwrap();
+
+    let mut archive = vec![];
+    recursive_archive(&mut archive, dir.path()).unwrap();
+    assert!(archive.len() < 2 * contents.len());
+
+    let entries = list(&mut io::Cursor::new(&archive)).unwrap();
+    let files = entries.iter().filter(|e| e.kind == EntryKind::File);
+    let links = entries.iter().filter(|e| e.kind == EntryKind::HardLink);
+    assert_eq!(files.count(), 1);
+    assert_eq!(links.count(), 1);
+
+    let dest = tempfile::tempdir().unwrap();
+    extract(&mut io::Cursor::new(&archive), dest.path()).unwrap();
+    let restored = dest.path().join(dir.path().strip_prefix("/").unwrap());
+    let a = std::fs::metadata(restored.join("a.bin")).unwrap();
+    let b = std::fs::metadata(restored.join("b.bin")).unwrap();
+    assert_eq!(std::fs::read(restored.join("b.bin")).unwrap(), contents);
+    assert_eq!(platform::hard_link_inode(&a), platform::hard_link_inode(&b));
+}
+
+#[cfg(feature = "parallel")]
+#[test]
+fn parallel_archive_matches_sequential() {
+    let dir = tempfile::tempdir().unwrap();
+    for d in 0..4 {
+        let sub = dir.path().join(format!("dir{d}"));
+        std::fs::create_dir(&sub).unwrap();
+        for f in 0..100 {
+            let contents = format!("{d}/{f}").repeat(f + 1);
+            std::fs::write(sub.join(format!("{f}.txt")), contents).unwrap();
+        }
+    }
+
+    let mut sequential = vec![];
+    recursive_archive(&mut sequential, dir.path()).unwrap();
+    let mut parallel = vec![];
+    recursive_archive_parallel(&mut parallel, dir.path()).unwrap();
+    assert!(sequential == parallel);
+}
+
+#[cfg(all(feature = "dedup", unix))]
+#[test]
+fn dedup_stores_identical_files_once() {
+    let dir = tempfile::tempdir().unwrap();
+    let mut contents = vec![0u8; 1 << 20];
+    let mut state = 0x2545_f491u32;
+    for byte in contents.iter_mut() {
+        state = state.wrapping_mul(1_103_515_245).wrapping_add(12345);
+        *byte = (state >> 24) as u8;
+    }
+    std::fs::write(dir.path().join("a.bin"), &contents).unwrap();
+    std::fs::write(dir.path().join("b.bin"), &contents).unwrap();
+
+    let mut naive = vec![];
+    recursive_archive(&mut naive, dir.path()).unwrap();
+    let mut archiver = Archiver::new(vec![]).dedup(true).build();
+    archiver.add_path(dir.path()).unwrap();
+    let archive = archiver.finish().unwrap();
+    assert!(archive.len() < naive.len() * 6 / 10);
+
+    let report = crate::verify(&mut io::Cursor::new(&archive)).unwrap();
+    assert_eq!((report.ok, report.corrupt), (3, 0));
+    for name in ["a.bin", "b.bin"] {
+        let path = dir.path().join(name);
+        let mut out = vec![];
+        let found = crate::extract_one(
+            &mut io::Cursor::new(&archive),
+            path.to_str().unwrap(),
+            &mut out,
+        );
+        assert!(found.unwrap());
+        assert!(out == contents);
+    }
+
+    // chunks are found relative to the start of the archive.
+    let mut shifted = b"leading bytes".to_vec();
+    shifted.extend_from_slice(&archive);
+    let mut reader = io::Cursor::new(&shifted);
+    reader.set_position(13);
+    let dest = tempfile::tempdir().unwrap();
+    crate::extract(&mut reader, dest.path()).unwrap();
+    let extracted = dest.path().join(dir.path().strip_prefix("/").unwrap());
+    assert!(std::fs::read(extracted.join("b.bin")).unwrap() == contents);
+
+    // the body of the second file only refers to chunks of the first, its own
+    // checksum doesn't notice them change.
+    let mut corrupt = archive.clone();
+    let chunk = corrupt
+        .windows(64)
+        .position(|w| w == &contents[..64])
+        .unwrap();
+    corrupt[chunk] ^= 0x01;
+    for name in ["a.bin", "b.bin"] {
+        let path = dir.path().join(name);
+        let found = crate::extract_one(
+            &mut io::Cursor::new(&corrupt),
+            path.to_str().unwrap(),
+            &mut vec![],
+        );
+        assert!(found.is_err());
+    }
+}
+
+#[cfg(all(feature = "xattr", unix))]
+#[test]
+fn xattrs_roundtrip() {
+    let dir = tempfile::tempdir().unwrap();
+    let file = dir.path().join("a.txt");
+    std::fs::write(&file, b"abc").unwrap();
+    if xattr::set(&file, "user.test", b"value").is_err() {
+        // not every filesystem supports user attributes.
+        return;
+    }
+
+    let mut archiver = Archiver::new(vec![]).xattrs(true).build();
+    archiver.add_path(dir.path()).unwrap();
+    let archive = archiver.finish().unwrap();
+
+    let dest = tempfile::tempdir().unwrap();
+    extract(&mut io::Cursor::new(archive), dest.path()).unwrap();
+    let restored = dest.path().join(file.strip_prefix("/").unwrap());
+    assert_eq!(std::fs::read(&restored).unwrap(), b"abc");
+    let value = xattr::get(&restored, "user.test").unwrap();
+    assert_eq!(value.as_deref(), Some(&b"value"[..]));
+}
+
+#[test]
+fn sparse_files_shrink() {
+    let dir = tempfile::tempdir().unwrap();
+    let file = dir.path().join("image.bin");
+    let mut contents = vec![0u8; 1 << 20];
+    contents[100_000..100_005].copy_from_slice(b"hello");
+    contents[(1 << 20) - 1] = 1;
+    std::fs::write(&file, &contents).unwrap();
+
+    let mut archiver = Archiver::new(vec![]).sparse(true).build();
+    archiver.add_path(&file).unwrap();
+    let archive = archiver.finish().unwrap();
+    assert!(archive.len() < 64 * 1024);
+
+    let entries = list(&mut io::Cursor::new(&archive)).unwrap();
+    assert_eq!(
+        entries[0].size as usize,
+        archive.len() - 80 - platform::path_to_bytes(&entries[0].path).len()
+    );
+
+    let mut restored = vec![];
+    assert!(crate::extract_one(&mut io::Cursor::new(&archive), &file, &mut restored).unwrap());
+    assert!(restored == contents);
+}
+
+#[test]
+fn sparse_option_keeps_files_without_holes() {
+    let dir = tempfile::tempdir().unwrap();
+    let file = dir.path().join("dense.txt");
+    std::fs::write(&file, b"no holes in here").unwrap();
+
+    let mut archiver = Archiver::new(vec![]).sparse(true).build();
+    archiver.add_path(&file).unwrap();
+    let archive = archiver.finish().unwrap();
+
+    let mut restored = vec![];
+    assert!(crate::extract_one(&mut io::Cursor::new(&archive), &file, &mut restored).unwrap());
+    assert_eq!(restored, b"no holes in here");
+}
+
+#[cfg(unix)]
+#[test]
+fn sparse_files_extract_with_holes() {
+    use std::os::unix::fs::MetadataExt;
+
+    let dir = tempfile::tempdir().unwrap();
+    let file = dir.path().join("image.bin");
+    let mut contents = vec![0u8; 1 << 20];
+    contents[..5].copy_from_slice(b"hello");
+    std::fs::write(&file, &contents).unwrap();
+
+    let mut archiver = Archiver::new(vec![]).sparse(true).build();
+    archiver.add_path(&file).unwrap();
+    let archive = archiver.finish().unwrap();
+
+    let dest = tempfile::tempdir().unwrap();
+    extract(&mut io::Cursor::new(&archive), dest.path()).unwrap();
+    let restored = dest.path().join(file.strip_prefix("/").unwrap());
+    assert!(std::fs::read(&restored).unwrap() == contents);
+    let meta = std::fs::metadata(&restored).unwrap();
+    assert_eq!(meta.len(), 1 << 20);
+    assert!(meta.blocks() * 512 < meta.len());
+}
@@ -0,0 +1,340 @@
+//! Archiving and reading on tokio, for callers that can't block their runtime.
+//!
+//! Bodies are streamed in chunks and checksummed on the way, decoding is
+//! shared with the synchronous reader.
+
+use std::{
+    io,
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
+
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+#[cfg(test)]
+use crate::EntryKind;
+use crate::{
+    archive::MAX_BUFFERED,
+    check_footer, checksum, flags, platform,
+    read::{
+        as_footer, as_header, check_body, check_path_len, decode_meta, read_failed, verify_path,
+    },
+    xattrs, ArchiveError, ArchiveObserver, DecodeError, EntryInfo, Metadata,
+};
+
+/// Size of the chunks bodies are streamed in.
+const CHUNK: usize = 8 * 1024;
+
+/// Async version of [`append_to_archive`](crate::append_to_archive), archiving
+/// exactly the object at `path` with its permissions.
+pub async fn append_to_archive_async(
+    archive: &mut (impl AsyncWrite + Unpin),
+    path: &Path,
+) -> Result<(), ArchiveError> {
+    let path_bytes = platform::path_to_bytes(path);
+    crate::archive::check_path_len(path, &path_bytes)?;
+    let metadata = tokio::fs::symlink_metadata(path)
+        .await
+        .map_err(ArchiveError::stat(path))?;
+    let file_type = metadata.file_type();
+
+    let (flags, size, mut body): (_, _, Box<dyn AsyncRead + Unpin>) = if file_type.is_file() {
+        let mut file = tokio::fs::File::open(path)
+            .await
+            .map_err(ArchiveError::read(path))?;
+        // like the synchronous archiver, smaller files are read before their
+        // header is written, so it records the size read.
+        let mut contents = vec![];
+        if metadata.len() <= MAX_BUFFERED {
+            (&mut file)
+                .take(MAX_BUFFERED + 1)
+                .read_to_end(&mut contents)
+                .await
+                .map_err(ArchiveError::read(path))?;
+        }
+        if metadata.len() <= MAX_BUFFERED && contents.len() as u64 <= MAX_BUFFERED {
+            (
+                flags::FILE,
+                contents.len() as _,
+                Box::new(io::Cursor::new(contents)),
+            )
+        } else {
+            // what was read already comes first, in case the file grew past the limit.
+            let body = io::Cursor::new(contents).chain(file);
+            (flags::FILE, metadata.len(), Box::new(body))
+        }
+    } else if file_type.is_dir() {
+        (flags::DIR, 0, Box::new(tokio::io::empty()))
+    } else if file_type.is_symlink() {
+        // the link target is stored as the body.
+        let target = tokio::fs::read_link(path)
+            .await
+            .map_err(ArchiveError::read(path))?;
+        let target = platform::path_to_bytes(&target);
+        (
+            flags::SOFT_LINK,
+            target.len() as _,
+            Box::new(io::Cursor::new(target)),
+        )
+    } else if let Some((flags, body)) = platform::special(&metadata) {
+        (flags, body.len() as _, Box::new(io::Cursor::new(body)))
+    } else {
+        return Err(ArchiveError::Unsupported { path: path.into() });
+    };
+
+    // the format can't represent times before the epoch, they're stored as the epoch.
+    let modified_at = metadata
+        .modified()
+        .map_err(ArchiveError::stat(path))?
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .map_or(0, |since| since.as_secs());
+    let (owner, group) = platform::owner(&metadata);
+    let meta = Metadata::new(
+        flags,
+        size,
+        path_bytes.len() as _,
+        platform::perms(&metadata),
+        owner,
+        group,
+        modified_at,
+    );
+
+    let mut head = meta.header(&path_bytes, &[]).to_bytes().to_vec();
+    head.extend_from_slice(&path_bytes);
+    archive
+        .write_all(&head)
+        .await
+        .map_err(ArchiveError::write(path))?;
+
+    // the size of larger files is already written, so the body is padded or cut
+    // off to match it, like the synchronous `FixedSize` does.
+    let mut hasher = checksum::Hasher::for_flags(meta.flags);
+    let mut buf = vec![0u8; CHUNK];
+    let mut remaining = size;
+    let mut resized = false;
+    while remaining > 0 {
+        let max = buf.len().min(remaining.try_into().unwrap_or(usize::MAX));
+        let mut n = match resized {
+            true => 0,
+            false => body
+                .read(&mut buf[..max])
+                .await
+                .map_err(ArchiveError::read(path))?,
+        };
+        if n == 0 {
+            if !resized {
+                log::warn!(
+                    "{} shrank while archiving, padding {remaining}B with zeros",
+                    path.display()
+                );
+            }
+            resized = true;
+            buf[..max].fill(0);
+            n = max;
+        }
+        hasher.update(&buf[..n]);
+        archive
+            .write_all(&buf[..n])
+            .await
+            .map_err(ArchiveError::write(path))?;
+        remaining -= n as u64;
+    }
+    if !resized
+        && body
+            .read(&mut buf[..1])
+            .await
+            .map_err(ArchiveError::read(path))?
+            != 0
+    {
+        log::warn!("{} grew while archiving, cutting it off", path.display());
+        resized = true;
+    }
+
+    let mut footer = meta.footer(hasher.finalize());
+    if resized {
+        footer.flags = footer.flags.with(flags::RESIZED);
+        footer.set_checksum();
+    }
+    archive
+        .write_all(&footer.to_bytes())
+        .await
+        .map_err(ArchiveError::write(path))?;
+    archive.flush().await?;
+    Ok(())
+}
+
+/// Async version of [`read_meta`](crate::read_meta).
+async fn read_meta(
+    name: &str,
+    archive: &mut (impl AsyncRead + Unpin),
+) -> Result<Metadata, DecodeError> {
+    let mut bytes = [0u8; Metadata::SIZE];
+    let mut filled = 0;
+    while filled < bytes.len() {
+        match archive.read(&mut bytes[filled..]).await {
+            Ok(0) => break,
+            Ok(n) => filled += n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(read_failed(name, e)),
+        }
+    }
+    decode_meta(name, &bytes, filled)
+}
+
+/// Reads exactly `len` bytes, running out of them being [`DecodeError::Crop`].
+async fn read_exact(
+    name: &str,
+    archive: &mut (impl AsyncRead + Unpin),
+    len: usize,
+) -> Result<Vec<u8>, DecodeError> {
+    let mut bytes = vec![0u8; len];
+    archive
+        .read_exact(&mut bytes)
+        .await
+        .map_err(|e| read_failed(name, e))?;
+    Ok(bytes)
+}
+
+/// Reads the next entry, which starts at offset `start`, like the synchronous
+/// streaming reader. Returns the offset the entry ends at along with it.
+async fn read1(
+    archive: &mut (impl AsyncRead + Unpin),
+    start: u64,
+) -> Result<(Metadata, PathBuf, u64), DecodeError> {
+    let at_start = |e: DecodeError| e.at(start);
+    let header = as_header(read_meta("Header", archive).await).map_err(at_start)?;
+    log::trace!("{header:?}");
+
+    // the header checksum covers the path, so a mismatch is at the header.
+    check_path_len(&header).map_err(at_start)?;
+    let raw_path = read_exact("path", archive, header.path_len as usize)
+        .await
+        .map_err(at_start)?;
+    verify_path(&header, &raw_path).map_err(at_start)?;
+    let path = platform::bytes_to_path(&raw_path);
+
+    let xattrs_start = start + Metadata::SIZE as u64 + header.path_len as u64;
+    let mut body_start = xattrs_start;
+    if header.flags.has_xattr() {
+        let at_xattrs = |e: DecodeError| e.at(xattrs_start);
+        let mut blob = read_exact("extended attributes", archive, 4)
+            .await
+            .map_err(at_xattrs)?;
+        let len = xattrs::blob_len(&blob).map_err(at_xattrs)?;
+        blob.extend(
+            read_exact("extended attributes", archive, len)
+                .await
+                .map_err(at_xattrs)?,
+        );
+        xattrs::decode_blob(&header, &raw_path, &blob).map_err(at_xattrs)?;
+        body_start += blob.len() as u64;
+    }
+
+    let mut body = (&mut *archive).take(header.file_size);
+    let mut hasher = checksum::Hasher::for_flags(header.flags);
+    let mut buf = vec![0u8; CHUNK];
+    let mut copied = 0;
+    loop {
+        let n = match body.read(&mut buf).await {
+            Ok(0) => break,
+            Ok(n) => n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(read_failed("file contents", e).at(body_start)),
+        };
+        hasher.update(&buf[..n]);
+        copied += n as u64;
+    }
+    if copied != header.file_size {
+        log::error!("File contents cut off after {copied}B");
+        return Err(DecodeError::Crop {
+            offset: Some(body_start + copied),
+        });
+    }
+
+    let footer_start = body_start + header.file_size;
+    let at_footer = |e: DecodeError| e.at(footer_start);
+    let footer = as_footer(read_meta("Footer", archive).await).map_err(at_footer)?;
+    check_footer(&header, &footer).map_err(at_footer)?;
+    check_body(&footer, hasher.finalize(), &path).map_err(|e| e.at(body_start))?;
+
+    Ok((header, path, footer_start + Metadata::SIZE as u64))
+}
+
+/// Async version of [`read_stream`](crate::read_stream), reporting every entry
+/// of the archive to `observer` and verifying their bodies.
+///
+/// Offsets of errors are counted from where reading began.
+pub async fn read_stream_async(
+    archive: &mut (impl AsyncRead + Unpin),
+    observer: &mut impl ArchiveObserver,
+) -> Result<(), DecodeError> {
+    let mut start = 0;
+    loop {
+        match read1(archive, start).await {
+            Ok((header, _, end)) if header.flags.is_special() => start = end,
+            Ok((header, path, end)) => {
+                observer.on_entry(&EntryInfo::from_header(&header, path));
+                start = end;
+            }
+            Err(DecodeError::Exhausted) => return Ok(()),
+            Err(e) => {
+                let e = if start == 0 { e } else { e.mid_archive() };
+                observer.on_error(&e);
+                return Err(e);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+#[tokio::test]
+async fn async_roundtrip() {
+    #[derive(Default)]
+    struct Collect(Vec<EntryInfo>);
+
+    impl ArchiveObserver for Collect {
+        fn on_entry(&mut self, info: &EntryInfo) {
+            self.0.push(info.clone());
+        }
+
+        fn on_error(&mut self, _err: &DecodeError) {}
+    }
+
+    let dir = tempfile::tempdir().unwrap();
+    let file = dir.path().join("a.txt");
+    std::fs::write(&file, b"abc").unwrap();
+    // spans several chunks.
+    let big = dir.path().join("big.bin");
+    let contents = (0..3 * CHUNK + 5).map(|i| i as u8).collect::<Vec<_>>();
+    std::fs::write(&big, &contents).unwrap();
+
+    let mut archive = vec![];
+    for path in [dir.path(), &file, &big] {
+        append_to_archive_async(&mut archive, path).await.unwrap();
+    }
+
+    let mut sync = vec![];
+    for path in [dir.path(), &file, &big] {
+        crate::append_to_archive(&mut sync, path).unwrap();
+    }
+    assert_eq!(archive, sync);
+
+    let mut entries = Collect::default();
+    read_stream_async(&mut archive.as_slice(), &mut entries)
+        .await
+        .unwrap();
+    assert_eq!(entries.0.len(), 3);
+    assert_eq!(entries.0[0].kind, EntryKind::Directory);
+    assert_eq!(entries.0[1].path, file);
+    assert_eq!(entries.0[1].size, 3);
+    assert_eq!(entries.0[2].size, contents.len() as u64);
+
+    let body_start = archive.len() - Metadata::SIZE - contents.len();
+    archive[body_start + 1] ^= 0x01;
+    assert_eq!(
+        read_stream_async(&mut archive.as_slice(), &mut entries).await,
+        Err(DecodeError::Checksum {
+            offset: Some(body_start as u64)
+        })
+    );
+}
@@ -1,14 +1,97 @@
-use std::path::PathBuf;
+use std::{
+    fs::File,
+    io::{self, BufReader, BufWriter},
+    path::{Path, PathBuf},
+    process::ExitCode,
+};
 
-fn main() -> std::io::Result<()> {
-    env_logger::init();
+use clap::{Parser, Subcommand};
 
-    let mut archive = vec![];
-    bitumen::recursive_archive(&mut archive, &PathBuf::from("src"))?;
-    std::fs::write("src.bit", &archive)?;
+#[derive(Parser)]
+#[command(version, about)]
+struct Cli {
+    #[command(subcommand)]
+    command: Command,
+}
 
-    let mut archive = std::io::Cursor::new(archive);
-    bitumen::read(&mut archive);
+#[derive(Subcommand)]
+enum Command {
+    /// Archive the given paths, recursively
+    Create {
+        archive: PathBuf,
+        #[arg(required = true)]
+        paths: Vec<PathBuf>,
+        /// Store paths relative to this directory
+        #[arg(long)]
+        strip_prefix: Option<PathBuf>,
+    },
+    /// List the entries of an archive
+    List { archive: PathBuf },
+    /// Extract an archive below `dest`
+    Extract { archive: PathBuf, dest: PathBuf },
+    /// Check every checksum of an archive
+    Verify { archive: PathBuf },
+}
+
+fn open(archive: &Path) -> io::Result<BufReader<File>> {
+    Ok(BufReader::new(File::open(archive)?))
+}
+
+fn run(command: Command) -> io::Result<bool> {
+    match command {
+        Command::Create {
+            archive,
+            paths,
+            strip_prefix,
+        } => {
+            let mut archiver =
+                bitumen::Archiver::new(BufWriter::new(File::create(archive)?)).trailer(true);
+            if let Some(prefix) = strip_prefix {
+                archiver = archiver.strip_prefix(prefix);
+            }
+            let mut archiver = archiver.build();
+            for path in paths {
+                archiver.add_path(&path)?;
+            }
+            archiver.finish()?;
+        }
+        Command::List { archive } => {
+            for entry in bitumen::list(&mut open(&archive)?)? {
+                println!(
+                    "{: <9} : {} : {}B",
+                    entry.kind,
+                    entry.path.display(),
+                    entry.size
+                );
+            }
+        }
+        Command::Extract { archive, dest } => {
+            bitumen::extract(&mut open(&archive)?, &dest)?;
+        }
+        Command::Verify { archive } => {
+            let report = bitumen::verify(&mut open(&archive)?)?;
+            for path in &report.corrupt_paths {
+                println!("corrupt: {path}");
+            }
+            for path in &report.resized_paths {
+                println!("resized: {path}");
+            }
+            println!("{} ok, {} corrupt", report.ok, report.corrupt);
+            return Ok(report.corrupt == 0);
+        }
+    }
+    Ok(true)
+}
+
+fn main() -> ExitCode {
+    env_logger::init();
 
-    Ok(())
+    match run(Cli::parse().command) {
+        Ok(true) => ExitCode::SUCCESS,
+        Ok(false) => ExitCode::FAILURE,
+        Err(e) => {
+            eprintln!("error: {e}");
+            ExitCode::FAILURE
+        }
+    }
 }
@@ -0,0 +1,171 @@
+//! Algorithms of body checksums, recorded in the [`flags::CHECKSUM_MASK`] bits.
+//!
+//! Metadata checksums and the archive trailer always use CRC-32,
+//! as do archives written before the algorithm could be chosen.
+
+use crate::flags::{self, Flags};
+
+/// Algorithm computing the checksum of an entry's body.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum ChecksumAlgorithm {
+    /// CRC-32 (IEEE), the table driven implementation of this crate.
+    #[default]
+    Crc32,
+    /// CRC-32C (Castagnoli), hardware accelerated where the CPU supports it.
+    Crc32c,
+    /// xxHash32 with a seed of `0`.
+    #[cfg(feature = "xxhash")]
+    XxHash32,
+}
+
+// only archiving records the algorithm, which needs `std`.
+#[cfg_attr(not(feature = "std"), allow(dead_code))]
+impl ChecksumAlgorithm {
+    /// The algorithm recorded in `flags`, `None` for an unknown or unsupported one.
+    pub(crate) fn from_flags(flags: Flags) -> Option<Self> {
+        match flags.checksum_bits() {
+            0 => Some(Self::Crc32),
+            flags::CRC32C => Some(Self::Crc32c),
+            #[cfg(feature = "xxhash")]
+            flags::XXHASH => Some(Self::XxHash32),
+            _ => None,
+        }
+    }
+
+    /// The bits recording the algorithm in the flags.
+    pub(crate) fn flag(self) -> u32 {
+        match self {
+            Self::Crc32 => 0,
+            Self::Crc32c => flags::CRC32C,
+            #[cfg(feature = "xxhash")]
+            Self::XxHash32 => flags::XXHASH,
+        }
+    }
+}
+
+/// Running checksum of a body in any of the algorithms.
+#[cfg(feature = "std")]
+#[derive(Clone)]
+pub enum Hasher {
+    Crc32(crate::Crc32),
+    /// the digest so far, which CRC-32C continues from.
+    Crc32c(u32),
+    #[cfg(feature = "xxhash")]
+    XxHash32(xxhash_rust::xxh32::Xxh32),
+}
+
+#[cfg(feature = "std")]
+impl Hasher {
+    pub fn new(algorithm: ChecksumAlgorithm) -> Self {
+        match algorithm {
+            ChecksumAlgorithm::Crc32 => Self::Crc32(crate::Crc32::new()),
+            ChecksumAlgorithm::Crc32c => Self::Crc32c(0),
+            #[cfg(feature = "xxhash")]
+            ChecksumAlgorithm::XxHash32 => Self::XxHash32(xxhash_rust::xxh32::Xxh32::new(0)),
+        }
+    }
+
+    /// Hasher for the body of the entry with `flags`, which have been validated on decoding.
+    pub fn for_flags(flags: Flags) -> Self {
+        Self::new(ChecksumAlgorithm::from_flags(flags).unwrap_or_default())
+    }
+
+    /// Feeds the next piece of the input.
+    pub fn update(&mut self, bytes: &[u8]) {
+        match self {
+            Self::Crc32(crc) => crc.update(bytes),
+            Self::Crc32c(crc) => *crc = crc32c::crc32c_append(*crc, bytes),
+            #[cfg(feature = "xxhash")]
+            Self::XxHash32(hasher) => hasher.update(bytes),
+        }
+    }
+
+    /// The checksum of all input so far.
+    pub fn finalize(&self) -> u32 {
+        match self {
+            Self::Crc32(crc) => crc.finalize(),
+            Self::Crc32c(crc) => *crc,
+            #[cfg(feature = "xxhash")]
+            Self::XxHash32(hasher) => hasher.digest(),
+        }
+    }
+}
+
+/// Checksum of the whole body of the entry with `flags`.
+#[cfg(feature = "std")]
+pub fn digest(flags: Flags, bytes: &[u8]) -> u32 {
+    let mut hasher = Hasher::for_flags(flags);
+    hasher.update(bytes);
+    hasher.finalize()
+}
+
+#[cfg(feature = "std")]
+#[test]
+fn check_values() {
+    let crc32c = |bytes| digest(Flags::new(flags::CRC32C), bytes);
+    assert_eq!(crc32c(b"123456789"), 0xE306_9283);
+    assert_eq!(digest(Flags::new(0), b"123456789"), 0xCBF4_3926);
+
+    let input = (0..=255u8).cycle().take(10_000).collect::<Vec<_>>();
+    let mut hasher = Hasher::new(ChecksumAlgorithm::Crc32c);
+    for chunk in input.chunks(777) {
+        hasher.update(chunk);
+    }
+    assert_eq!(hasher.finalize(), crc32c(&input));
+
+    #[cfg(feature = "xxhash")]
+    assert_eq!(digest(Flags::new(flags::XXHASH), b""), 0x02CC_5D05);
+    #[cfg(not(feature = "xxhash"))]
+    assert_eq!(
+        ChecksumAlgorithm::from_flags(Flags::new(flags::XXHASH)),
+        None
+    );
+}
+
+#[cfg(feature = "std")]
+#[test]
+fn algorithms_roundtrip() {
+    use std::io::Cursor;
+
+    let algorithms = [
+        ChecksumAlgorithm::Crc32,
+        ChecksumAlgorithm::Crc32c,
+        #[cfg(feature = "xxhash")]
+        ChecksumAlgorithm::XxHash32,
+    ];
+    for algorithm in algorithms {
+        let mut archiver = crate::Archiver::new(vec![]).checksum(algorithm).build();
+        archiver
+            .add_reader("a.txt", &mut &b"checksummed"[..], 11, 0)
+            .unwrap();
+        let mut archive = archiver.finish().unwrap();
+
+        let entry = crate::Entries::new(Cursor::new(&archive))
+            .next()
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            ChecksumAlgorithm::from_flags(entry.meta.flags),
+            Some(algorithm)
+        );
+        assert_eq!(crate::verify(&mut Cursor::new(&archive)).unwrap().ok, 1);
+        let mut restored = vec![];
+        assert!(crate::extract_one(&mut Cursor::new(&archive), "a.txt", &mut restored).unwrap());
+        assert_eq!(restored, b"checksummed");
+
+        let body = archive.len() - crate::Metadata::SIZE - 1;
+        archive[body] ^= 0x01;
+        assert_eq!(
+            crate::read_stream(&mut archive.as_slice(), &mut crate::LogObserver),
+            Err(crate::DecodeError::Checksum { offset: Some(45) })
+        );
+    }
+
+    // archives from before the choice have no algorithm bits and use CRC-32.
+    let mut legacy = vec![];
+    crate::append_reader(&mut legacy, "a.txt", &mut &b"checksummed"[..], 11, 0).unwrap();
+    let footer = crate::Metadata::decode(legacy[legacy.len() - 40..].try_into().unwrap()).unwrap();
+    assert_eq!(footer.flags.checksum_bits(), 0);
+    assert_eq!(footer.body_checksum, crate::crc32::digest(b"checksummed"));
+    assert_eq!(crate::verify(&mut Cursor::new(&legacy)).unwrap().ok, 1);
+}
@@ -0,0 +1,86 @@
+use std::io::{self, Read};
+
+use crate::flags::Flags;
+
+/// Gzip level balancing speed and size, the default of [`gzip`].
+#[cfg(feature = "flate")]
+pub const DEFAULT_LEVEL: u32 = 6;
+
+/// Compresses the whole `body` into a gzip stream at `level`, from `0` to `9`.
+///
+/// The compressed size has to be known before the header is written,
+/// so the stream is buffered in memory.
+#[cfg(feature = "flate")]
+pub fn gzip(body: &mut impl Read, level: u32) -> io::Result<Vec<u8>> {
+    use flate2::{write::GzEncoder, Compression};
+
+    let mut encoder = GzEncoder::new(vec![], Compression::new(level.min(9)));
+    io::copy(body, &mut encoder)?;
+    encoder.finish()
+}
+
+/// Compresses the whole `body` into an LZ4 block, prefixed by its size.
+#[cfg(feature = "lz4")]
+pub fn lz4(body: &mut impl Read) -> io::Result<Vec<u8>> {
+    let mut data = vec![];
+    body.read_to_end(&mut data)?;
+    if u32::try_from(data.len()).is_err() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "LZ4 compressed bodies are limited to 4GiB",
+        ));
+    }
+    Ok(lz4_flex::block::compress_prepend_size(&data))
+}
+
+/// Wraps a stored body in a reader yielding the original bytes,
+/// undoing the compression recorded in `flags`.
+pub fn decoder<'a>(flags: Flags, body: impl Read + 'a) -> io::Result<Box<dyn Read + 'a>> {
+    if !flags.compressed() {
+        return Ok(Box::new(body));
+    }
+    if flags.lz4() {
+        return unlz4(body);
+    }
+    gunzip(body)
+}
+
+#[cfg(feature = "flate")]
+fn gunzip<'a>(body: impl Read + 'a) -> io::Result<Box<dyn Read + 'a>> {
+    Ok(Box::new(flate2::read::GzDecoder::new(body)))
+}
+
+#[cfg(not(feature = "flate"))]
+fn gunzip<'a>(_body: impl Read + 'a) -> io::Result<Box<dyn Read + 'a>> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "compressed entries require the `flate` feature",
+    ))
+}
+
+#[cfg(feature = "lz4")]
+fn unlz4<'a>(mut body: impl Read + 'a) -> io::Result<Box<dyn Read + 'a>> {
+    /// LZ4 can't compress by more than this factor,
+    /// so a corrupt size can't cause a huge allocation.
+    const MAX_RATIO: usize = 255;
+
+    let invalid = |msg: String| io::Error::new(io::ErrorKind::InvalidData, msg);
+    let mut size = [0u8; 4];
+    body.read_exact(&mut size)?;
+    let size = u32::from_le_bytes(size) as usize;
+    let mut block = vec![];
+    body.read_to_end(&mut block)?;
+    if size > block.len().saturating_mul(MAX_RATIO) + 16 {
+        return Err(invalid(format!("LZ4 block claims an impossible {size}B")));
+    }
+    let data = lz4_flex::block::decompress(&block, size).map_err(|e| invalid(e.to_string()))?;
+    Ok(Box::new(io::Cursor::new(data)))
+}
+
+#[cfg(not(feature = "lz4"))]
+fn unlz4<'a>(_body: impl Read + 'a) -> io::Result<Box<dyn Read + 'a>> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "LZ4 compressed entries require the `lz4` feature",
+    ))
+}
@@ -0,0 +1,100 @@
+use std::io::{self, Read, Write};
+
+use crate::{check_footer, read_footer, read_header, read_path, xattrs, DecodeError};
+
+/// Writes the entries of all `archives` to `out`, forming a single archive.
+///
+/// Entries are self-contained, so they are copied as they are. Indexes are
+/// dropped, as their offsets only hold within their own archive; archive the
+/// combined entries again to get one. Deduplicated entries refer to the start
+/// of their archive and are only accepted from the first archive. Likewise only
+/// the comment of the first archive is kept.
+pub fn concat(out: &mut impl Write, archives: &mut [impl Read]) -> io::Result<()> {
+    for (i, archive) in archives.iter_mut().enumerate() {
+        let mut first = true;
+        loop {
+            let header = match read_header(archive) {
+                Ok(header) => header,
+                Err(DecodeError::Exhausted) => break,
+                Err(e) if first => return Err(e.into()),
+                Err(e) => return Err(e.mid_archive().into()),
+            };
+            first = false;
+            let path = read_path(&header, archive)?;
+            let xattrs = xattrs::read(&header, &path, archive)?;
+
+            // only the comment of the first archive can lead the result.
+            let keep = !header.flags.is_index() && (i == 0 || !header.flags.is_comment());
+            if keep && i > 0 && header.flags.chunked() {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!(
+                        "can't move deduplicated entry {} to another archive",
+                        String::from_utf8_lossy(&path)
+                    ),
+                ));
+            }
+            if keep {
+                out.write_all(&header.encode())?;
+                out.write_all(&path)?;
+                if header.flags.has_xattr() {
+                    out.write_all(&xattrs::encode(&xattrs))?;
+                }
+            }
+
+            let mut body = archive.take(header.file_size);
+            let copied = if keep {
+                io::copy(&mut body, out)?
+            } else {
+                io::copy(&mut body, &mut io::sink())?
+            };
+            if copied != header.file_size {
+                log::error!("File contents cut off after {copied}B");
+                return Err(DecodeError::Crop { offset: None }.into());
+            }
+
+            let footer = read_footer(archive)?;
+            check_footer(&header, &footer)?;
+            if keep {
+                out.write_all(&footer.to_bytes())?;
+            }
+        }
+    }
+    Ok(())
+}
+
+#[test]
+fn concat_keeps_entries_of_all_archives() {
+    let mut first = vec![];
+    crate::append_reader(&mut first, "a.txt", &mut &b"abc"[..], 3, 0).unwrap();
+    crate::append_reader(&mut first, "b.txt", &mut &b"defg"[..], 4, 0).unwrap();
+
+    let dir = tempfile::tempdir().unwrap();
+    std::fs::write(dir.path().join("c.txt"), b"hij").unwrap();
+    let mut archiver = crate::Archiver::new(vec![]).index(true).build();
+    archiver.add_path(dir.path()).unwrap();
+    let second = archiver.finish().unwrap();
+
+    let mut combined = vec![];
+    concat(&mut combined, &mut [&first[..], &second[..]]).unwrap();
+
+    let entries = crate::list(&mut io::Cursor::new(&combined)).unwrap();
+    let names = entries
+        .iter()
+        .map(|e| e.path.file_name().unwrap())
+        .collect::<Vec<_>>();
+    assert_eq!(names[..2], ["a.txt", "b.txt"]);
+    assert_eq!(names[3], "c.txt");
+    assert_eq!(entries.len(), 4);
+
+    // the index of the second archive doesn't carry over.
+    let last_footer = (combined.len() - crate::Metadata::SIZE) as u64;
+    assert_eq!(
+        crate::open_indexed(io::Cursor::new(&combined)).err(),
+        Some(DecodeError::Footer {
+            offset: Some(last_footer)
+        })
+    );
+    let report = crate::verify(&mut io::Cursor::new(&combined)).unwrap();
+    assert!(report.corrupt_paths.is_empty());
+}
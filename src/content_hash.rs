@@ -0,0 +1,133 @@
+//! Hash of the logical content of an archive, see [`content_hash`].
+
+use std::io::{self, Read, Seek, Write};
+
+use sha2::{Digest, Sha256};
+
+use crate::{
+    crc32, decode_err,
+    extract::{decode_file, finish_body, read_footer, skip_body},
+    read_header, read_path, xattrs, DecodeError,
+};
+
+/// Feeds everything written to it into a hash.
+struct HashWriter<'a>(&'a mut Sha256);
+
+impl Write for HashWriter<'_> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.update(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// SHA-256 of the logical content of the archive, for content addressing.
+///
+/// Every entry is hashed from its path, kind, permissions, owner, group,
+/// modification time, extended attributes and restored body, and the hashes
+/// are combined in the order of their paths. Archives holding the same objects
+/// hash equally, whatever their order and however their bodies are stored,
+/// e.g. compressed or deduplicated. The index and the comment aren't content.
+///
+/// Encrypted bodies are hashed as stored, as they can't be restored without
+/// the key, so only copies of the same encrypted entry hash equally.
+pub fn content_hash<R: Read + Seek>(archive: &mut R) -> Result<[u8; 32], DecodeError> {
+    let archive_start = archive.stream_position().map_err(decode_err)?;
+
+    let mut entries = vec![];
+    let mut first = true;
+    loop {
+        let start = archive.stream_position().map_err(decode_err)?;
+        let header = match read_header(archive).map_err(|e| e.at(start)) {
+            Ok(header) => header,
+            Err(DecodeError::Exhausted) => break,
+            Err(e) if first => return Err(e),
+            Err(e) => return Err(e.mid_archive()),
+        };
+        first = false;
+        let path = read_path(&header, archive).map_err(|e| e.at(start))?;
+        let mut xattrs = xattrs::read(&header, &path, archive)?;
+        if header.flags.is_special() {
+            skip_body(archive, &header).map_err(decode_err)?;
+            continue;
+        }
+
+        let mut hash = Sha256::new();
+        hash.update((path.len() as u64).to_le_bytes());
+        hash.update(&path);
+        hash.update(header.flags.kind_bits().to_le_bytes());
+        hash.update(header.perms.to_le_bytes());
+        hash.update(header.owner.to_le_bytes());
+        hash.update(header.group.to_le_bytes());
+        hash.update(header.modified_at.to_le_bytes());
+        xattrs.sort();
+        hash.update(xattrs::encode(&xattrs));
+
+        let mut body = crc32::Reader::for_body(archive.take(header.file_size), header.flags);
+        let mut contents = Sha256::new();
+        if header.flags.is_file() && !header.flags.encrypted() {
+            decode_file(
+                &mut body,
+                &header,
+                &path,
+                archive_start,
+                None,
+                &mut HashWriter(&mut contents),
+            )
+            .map_err(decode_err)?;
+        } else {
+            io::copy(&mut body, &mut HashWriter(&mut contents)).map_err(decode_err)?;
+        }
+        let body_checksum = finish_body(body).map_err(decode_err)?;
+        read_footer(archive, &header, body_checksum).map_err(decode_err)?;
+        hash.update(contents.finalize());
+
+        let hash: [u8; 32] = hash.finalize().into();
+        entries.push((path, hash));
+    }
+
+    // entries archived under the same path are ordered by their content.
+    entries.sort();
+    let mut hash = Sha256::new();
+    for (_, entry) in entries {
+        hash.update(entry);
+    }
+    Ok(hash.finalize().into())
+}
+
+#[cfg(unix)]
+#[test]
+fn content_hash_ignores_order() {
+    let dir = tempfile::tempdir().unwrap();
+    std::fs::create_dir(dir.path().join("sub")).unwrap();
+    std::fs::write(dir.path().join("a.txt"), b"abc").unwrap();
+    std::fs::write(dir.path().join("sub/b.txt"), b"defg").unwrap();
+    std::os::unix::fs::symlink("a.txt", dir.path().join("link")).unwrap();
+
+    let mut archive = vec![];
+    crate::recursive_archive(&mut archive, dir.path()).unwrap();
+
+    // the same objects one by one in reverse, leading with a comment.
+    let mut paths = vec![dir.path().to_path_buf()];
+    for name in ["sub", "a.txt", "sub/b.txt", "link"] {
+        paths.push(dir.path().join(name));
+    }
+    let mut archiver = crate::Archiver::new(vec![]).index(true).build();
+    archiver.set_comment("reversed").unwrap();
+    for path in paths.iter().rev() {
+        archiver.add_one(path).unwrap();
+    }
+    let reversed = archiver.finish().unwrap();
+    assert_ne!(archive, reversed);
+
+    let hash = content_hash(&mut io::Cursor::new(&archive)).unwrap();
+    assert_eq!(content_hash(&mut io::Cursor::new(&reversed)).unwrap(), hash);
+
+    std::fs::write(dir.path().join("a.txt"), b"abd").unwrap();
+    let mut changed = vec![];
+    crate::recursive_archive(&mut changed, dir.path()).unwrap();
+    assert_ne!(content_hash(&mut io::Cursor::new(&changed)).unwrap(), hash);
+}
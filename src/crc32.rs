@@ -1,16 +1,213 @@
+#[cfg(feature = "std")]
+use std::io::{self, Read};
+
+#[cfg(feature = "std")]
+use crate::{checksum::Hasher, flags::Flags};
+
 const POLYNOMIAL: u32 = 0x04C11DB7;
 
+/// Lookup table for the reflected polynomial, one entry per byte value.
+const TABLE: [u32; 256] = {
+    let reflected = POLYNOMIAL.reverse_bits();
+    let mut table = [0u32; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = i as u32;
+        let mut bit = 0;
+        while bit < 8 {
+            if crc & 1 > 0 {
+                crc = (crc >> 1) ^ reflected;
+            } else {
+                crc >>= 1;
+            }
+            bit += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+};
+
+/// Initial state of a running checksum.
+pub const INIT: u32 = !0;
+
+/// Feeds `bytes` into the running checksum `crc`.
+///
+/// The state is kept bit-reflected, which lets each byte be processed with a
+/// single table lookup instead of reversing the bits of every input byte.
+pub fn update(mut crc: u32, bytes: &[u8]) -> u32 {
+    for &byte in bytes {
+        crc = TABLE[((crc ^ byte as u32) & 0xFF) as usize] ^ (crc >> 8);
+    }
+    crc
+}
+
+/// Turns a running checksum into the final digest.
+pub fn finalize(crc: u32) -> u32 {
+    !crc
+}
+
 pub fn digest(bytes: &[u8]) -> u32 {
+    finalize(update(INIT, bytes))
+}
+
+/// Running CRC-32 checksum, as stored for archive metadata and bodies.
+///
+/// Feeding the input in pieces yields the same digest as feeding it at once,
+/// so large bodies don't have to be held in memory.
+#[derive(Clone, Copy, Debug)]
+pub struct Crc32 {
+    state: u32,
+}
+
+impl Crc32 {
+    pub fn new() -> Self {
+        Self { state: INIT }
+    }
+
+    /// Feeds the next piece of the input.
+    pub fn update(&mut self, bytes: &[u8]) {
+        self.state = update(self.state, bytes);
+    }
+
+    /// Continues the checksum of input whose digest was `digest`.
+    #[cfg(feature = "std")]
+    pub(crate) fn resume(digest: u32) -> Self {
+        Self {
+            state: finalize(digest),
+        }
+    }
+
+    /// The checksum of all input so far.
+    pub fn finalize(self) -> u32 {
+        finalize(self.state)
+    }
+}
+
+impl Default for Crc32 {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[test]
+fn chunked_updates_match_digest() {
+    let mut input = [0u8; 10_000];
+    input
+        .iter_mut()
+        .zip((0..=255u8).cycle())
+        .for_each(|(b, v)| *b = v);
+    for chunk_size in [1, 7, 256, 4096, input.len()] {
+        let mut crc = Crc32::new();
+        for chunk in input.chunks(chunk_size) {
+            crc.update(chunk);
+        }
+        assert_eq!(crc.finalize(), digest(&input));
+    }
+    assert_eq!(Crc32::new().finalize(), digest(b""));
+}
+
+/// Reader adapter computing the checksum of everything read through it,
+/// CRC-32 unless created for a body.
+#[cfg(feature = "std")]
+pub struct Reader<R> {
+    inner: R,
+    crc: Hasher,
+}
+
+#[cfg(feature = "std")]
+impl<R> Reader<R> {
+    pub fn new(inner: R) -> Self {
+        Self {
+            inner,
+            crc: Hasher::Crc32(Crc32::new()),
+        }
+    }
+
+    /// Checksums the body of the entry with `flags` in the algorithm they record.
+    pub fn for_body(inner: R, flags: Flags) -> Self {
+        Self {
+            inner,
+            crc: Hasher::for_flags(flags),
+        }
+    }
+
+    pub fn get_ref(&self) -> &R {
+        &self.inner
+    }
+
+    /// Reading from the inner reader directly bypasses the checksum.
+    pub fn get_mut(&mut self) -> &mut R {
+        &mut self.inner
+    }
+
+    pub fn digest(&self) -> u32 {
+        self.crc.finalize()
+    }
+}
+
+#[cfg(feature = "std")]
+impl<R: Read> Read for Reader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.crc.update(&buf[..n]);
+        Ok(n)
+    }
+}
+
+/// Writer adapter feeding everything written through it into `crc`.
+#[cfg(feature = "std")]
+pub struct Writer<'a, W> {
+    inner: W,
+    crc: &'a mut Crc32,
+}
+
+#[cfg(feature = "std")]
+impl<'a, W> Writer<'a, W> {
+    pub fn new(inner: W, crc: &'a mut Crc32) -> Self {
+        Self { inner, crc }
+    }
+}
+
+#[cfg(feature = "std")]
+impl<W: io::Write> io::Write for Writer<'_, W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = self.inner.write(buf)?;
+        self.crc.update(&buf[..n]);
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Bit by bit reference implementation the table is derived from.
+#[cfg(test)]
+fn digest_bitwise(bytes: &[u8]) -> u32 {
     let mut crc = !0;
     for byte in bytes {
-        crc = crc ^ ((byte.reverse_bits() as u32) << 24);
+        crc ^= (byte.reverse_bits() as u32) << 24;
         for _ in 0..8 {
             if crc & (1 << 31) > 0 {
                 crc = (crc << 1) ^ POLYNOMIAL;
             } else {
-                crc = crc << 1;
+                crc <<= 1;
             }
         }
     }
     !crc.reverse_bits()
 }
+
+#[test]
+fn table_matches_bitwise() {
+    let mut long = [0u8; 10_000];
+    long.iter_mut()
+        .zip((0..=255u8).cycle())
+        .for_each(|(b, v)| *b = v);
+    let inputs: [&[u8]; 5] = [b"", b"a", b"123456789", b"bitumen archive", &long];
+    for input in inputs {
+        assert_eq!(digest(input), digest_bitwise(input));
+    }
+    assert_eq!(digest(b"123456789"), 0xCBF4_3926);
+}
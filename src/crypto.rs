@@ -0,0 +1,268 @@
+//! AES-256-GCM encryption of file bodies flagged with
+//! [`flags::ENCRYPTED`](crate::flags::ENCRYPTED).
+//!
+//! The stored body is a random 12 byte nonce followed by the plaintext in
+//! chunks of 64 KiB, each encrypted and followed by its 16 byte authentication
+//! tag. The nonce of a chunk is derived from the stored one, its index and
+//! whether it is the last, so chunks can't be reordered or dropped. The header
+//! fields and the path are authenticated with every chunk.
+
+use std::io::{self, Read};
+
+use crate::{flags, Metadata};
+
+/// A 256 bit key, which is left out of debug output.
+#[cfg_attr(not(feature = "encrypt"), allow(dead_code))]
+#[derive(Clone, PartialEq, Eq)]
+pub struct Key(pub [u8; 32]);
+
+impl From<[u8; 32]> for Key {
+    fn from(key: [u8; 32]) -> Self {
+        Self(key)
+    }
+}
+
+impl std::fmt::Debug for Key {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("Key(..)")
+    }
+}
+
+#[cfg(feature = "encrypt")]
+const NONCE_LEN: usize = 12;
+
+#[cfg(feature = "encrypt")]
+const TAG_LEN: usize = 16;
+
+/// Plaintext bytes sealed at a time, the last chunk may be shorter.
+#[cfg(feature = "encrypt")]
+const CHUNK: usize = 64 * 1024;
+
+/// Data authenticated along with the body of the entry with `meta` and `path`:
+/// the encoded header, without the fields only known once the body is stored,
+/// followed by the path. Moving the body to another entry fails authentication.
+#[cfg_attr(not(feature = "encrypt"), allow(dead_code))]
+pub(crate) fn associated_data(meta: &Metadata, path: &[u8]) -> Vec<u8> {
+    let mut fields = meta.clone();
+    fields.file_size = 0;
+    fields.flags = fields
+        .flags
+        .without(flags::HEADER | flags::ENCRYPTED | flags::CHECKSUM_MASK);
+    fields.body_checksum = 0;
+    fields.checksum = 0;
+    let mut data = fields.encode().to_vec();
+    data.extend_from_slice(path);
+    data
+}
+
+/// Nonce of chunk `index` and whether it is the `last`, derived from the
+/// random nonce stored in front of the body.
+#[cfg(feature = "encrypt")]
+fn chunk_nonce(
+    base: &[u8; NONCE_LEN],
+    index: u32,
+    last: bool,
+) -> aes_gcm::Nonce<aes_gcm::aead::consts::U12> {
+    let mut nonce = *base;
+    for (byte, counter) in nonce[7..11].iter_mut().zip(index.to_le_bytes()) {
+        *byte ^= counter;
+    }
+    nonce[11] ^= last as u8;
+    nonce.into()
+}
+
+/// Encrypts the `size` bytes of `body` with a fresh nonce, `aad` from
+/// [`associated_data`]. Returns the size of the stored body along with it,
+/// which is known up front, so the body is encrypted as it is read.
+#[cfg(feature = "encrypt")]
+pub fn encrypt<R: Read>(body: R, size: u64, key: &Key, aad: Vec<u8>) -> (u64, Encryptor<R>) {
+    use aes_gcm::{
+        aead::{AeadCore, OsRng},
+        Aes256Gcm, KeyInit,
+    };
+
+    let chunks = size.div_ceil(CHUNK as u64).max(1);
+    let stored = NONCE_LEN as u64 + size + chunks * TAG_LEN as u64;
+    let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+    let encryptor = Encryptor {
+        inner: body,
+        cipher: Aes256Gcm::new(&key.0.into()),
+        nonce: nonce.into(),
+        aad,
+        remaining: size,
+        index: 0,
+        done: false,
+        out: nonce.to_vec(),
+        pos: 0,
+    };
+    (stored, encryptor)
+}
+
+/// Reader yielding the stored form of a body: the nonce, then every chunk
+/// sealed with its authentication tag.
+#[cfg(feature = "encrypt")]
+pub struct Encryptor<R> {
+    inner: R,
+    cipher: aes_gcm::Aes256Gcm,
+    nonce: [u8; NONCE_LEN],
+    aad: Vec<u8>,
+    /// plaintext bytes not yet read from `inner`.
+    remaining: u64,
+    index: u32,
+    /// whether the last chunk has been sealed.
+    done: bool,
+    /// sealed bytes, returned from `pos` on.
+    out: Vec<u8>,
+    pos: usize,
+}
+
+#[cfg(feature = "encrypt")]
+impl<R: Read> Read for Encryptor<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        use aes_gcm::aead::{Aead, Payload};
+
+        if self.pos == self.out.len() {
+            if self.done {
+                return Ok(0);
+            }
+            let len = self.remaining.min(CHUNK as u64) as usize;
+            let mut chunk = vec![0u8; len];
+            self.inner.read_exact(&mut chunk)?;
+            self.remaining -= len as u64;
+            self.done = self.remaining == 0;
+
+            let nonce = chunk_nonce(&self.nonce, self.index, self.done);
+            let payload = Payload {
+                msg: &chunk,
+                aad: &self.aad,
+            };
+            self.out = self
+                .cipher
+                .encrypt(&nonce, payload)
+                .map_err(|_| io::Error::other("encryption failed"))?;
+            self.pos = 0;
+            self.index += 1;
+        }
+        let n = buf.len().min(self.out.len() - self.pos);
+        buf[..n].copy_from_slice(&self.out[self.pos..self.pos + n]);
+        self.pos += n;
+        Ok(n)
+    }
+}
+
+/// Wraps the stored body of the entry with `header` and `path` in a reader
+/// yielding the decrypted bytes, if its flags mark it as encrypted.
+///
+/// Every chunk is authenticated before any of it is returned, so the bytes
+/// of earlier chunks may already have been returned when a later one fails.
+pub fn decryptor<'a>(
+    header: &Metadata,
+    path: &[u8],
+    body: impl Read + 'a,
+    key: Option<&Key>,
+) -> io::Result<Box<dyn Read + 'a>> {
+    if !header.flags.encrypted() {
+        return Ok(Box::new(body));
+    }
+    let Some(key) = key else {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "encrypted entries require a key",
+        ));
+    };
+    decrypt(body, header.file_size, key, associated_data(header, path))
+}
+
+#[cfg(feature = "encrypt")]
+fn decrypt<'a>(
+    mut body: impl Read + 'a,
+    stored: u64,
+    key: &Key,
+    aad: Vec<u8>,
+) -> io::Result<Box<dyn Read + 'a>> {
+    use aes_gcm::{Aes256Gcm, KeyInit};
+
+    let mut nonce = [0u8; NONCE_LEN];
+    body.read_exact(&mut nonce)?;
+    // at least the tag of a single empty chunk follows.
+    let remaining = stored
+        .checked_sub(NONCE_LEN as u64)
+        .filter(|&remaining| remaining >= TAG_LEN as u64)
+        .ok_or(io::ErrorKind::UnexpectedEof)?;
+    Ok(Box::new(Decryptor {
+        inner: body,
+        cipher: Aes256Gcm::new(&key.0.into()),
+        nonce,
+        aad,
+        remaining,
+        index: 0,
+        out: vec![],
+        pos: 0,
+    }))
+}
+
+/// Reader undoing [`Encryptor`], authenticating a chunk at a time.
+#[cfg(feature = "encrypt")]
+struct Decryptor<R> {
+    inner: R,
+    cipher: aes_gcm::Aes256Gcm,
+    nonce: [u8; NONCE_LEN],
+    aad: Vec<u8>,
+    /// sealed bytes not yet read from `inner`.
+    remaining: u64,
+    index: u32,
+    /// decrypted bytes, returned from `pos` on.
+    out: Vec<u8>,
+    pos: usize,
+}
+
+#[cfg(feature = "encrypt")]
+impl<R: Read> Read for Decryptor<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        use aes_gcm::aead::{Aead, Payload};
+
+        while self.pos == self.out.len() {
+            if self.remaining == 0 {
+                return Ok(0);
+            }
+            let len = self.remaining.min((CHUNK + TAG_LEN) as u64) as usize;
+            if len < TAG_LEN {
+                return Err(io::ErrorKind::UnexpectedEof.into());
+            }
+            let mut sealed = vec![0u8; len];
+            self.inner.read_exact(&mut sealed)?;
+            self.remaining -= len as u64;
+
+            let nonce = chunk_nonce(&self.nonce, self.index, self.remaining == 0);
+            let payload = Payload {
+                msg: &sealed,
+                aad: &self.aad,
+            };
+            self.out = self.cipher.decrypt(&nonce, payload).map_err(|_| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "encrypted body failed authentication",
+                )
+            })?;
+            self.pos = 0;
+            self.index += 1;
+        }
+        let n = buf.len().min(self.out.len() - self.pos);
+        buf[..n].copy_from_slice(&self.out[self.pos..self.pos + n]);
+        self.pos += n;
+        Ok(n)
+    }
+}
+
+#[cfg(not(feature = "encrypt"))]
+fn decrypt<'a>(
+    _body: impl Read + 'a,
+    _stored: u64,
+    _key: &Key,
+    _aad: Vec<u8>,
+) -> io::Result<Box<dyn Read + 'a>> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "encrypted entries require the `encrypt` feature",
+    ))
+}
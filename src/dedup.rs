@@ -0,0 +1,252 @@
+//! Bodies of files flagged with [`flags::CHUNKED`](crate::flags::CHUNKED),
+//! which store every distinct chunk of content only once per archive.
+//!
+//! Files are split at content-defined boundaries found with a rolling hash,
+//! so an insertion only changes the chunks around it. The body is a sequence
+//! of records, each a tag byte and a little-endian `u32` length:
+//!
+//! | tag | followed by                                                                  |
+//! |-----|------------------------------------------------------------------------------|
+//! | `0` | the chunk itself                                                             |
+//! | `1` | a `u64` offset of an identical chunk, from the archive start, and its CRC-32 |
+//!
+//! Chunks are stored the first time they appear, as an archive is written
+//! front to back and can't collect them in a region of their own. Referenced
+//! chunks lie outside the body and its checksum, so their own is stored with
+//! every reference.
+
+use std::io::{self, Read, Seek, SeekFrom, Write};
+
+#[cfg(feature = "dedup")]
+use std::collections::HashMap;
+
+const LITERAL: u8 = 0;
+const REFERENCE: u8 = 1;
+
+/// Length of the tag and length preceding every chunk.
+#[cfg(feature = "dedup")]
+const RECORD_HEADER_LEN: usize = 5;
+
+#[cfg(feature = "dedup")]
+const MIN_CHUNK: usize = 2 * 1024;
+#[cfg(feature = "dedup")]
+const MAX_CHUNK: usize = 64 * 1024;
+/// 13 bits, so chunks average 8KiB past the minimum.
+/// The top bits of the hash depend on the most bytes.
+#[cfg(feature = "dedup")]
+const BOUNDARY_MASK: u64 = ((1 << 13) - 1) << 51;
+
+/// Random values mixed into the rolling hash for every byte value.
+#[cfg(feature = "dedup")]
+const GEAR: [u64; 256] = {
+    // splitmix64, any fixed sequence of well mixed values will do.
+    let mut table = [0u64; 256];
+    let mut state = 0x9e37_79b9_7f4a_7c15u64;
+    let mut i = 0;
+    while i < table.len() {
+        state = state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        table[i] = z ^ (z >> 31);
+        i += 1;
+    }
+    table
+};
+
+/// Length of the chunk starting `data`.
+#[cfg(feature = "dedup")]
+fn chunk_len(data: &[u8]) -> usize {
+    if data.len() <= MIN_CHUNK {
+        return data.len();
+    }
+    let mut hash = 0u64;
+    for (i, &byte) in data.iter().enumerate().take(MAX_CHUNK).skip(MIN_CHUNK) {
+        hash = (hash << 1).wrapping_add(GEAR[byte as usize]);
+        if hash & BOUNDARY_MASK == 0 {
+            return i + 1;
+        }
+    }
+    data.len().min(MAX_CHUNK)
+}
+
+/// Offsets and CRC-32 of the chunks written to an archive so far, by their SHA-256.
+#[cfg(feature = "dedup")]
+#[derive(Default)]
+pub struct ChunkStore {
+    chunks: HashMap<[u8; 32], (u64, u32)>,
+}
+
+#[cfg(feature = "dedup")]
+impl ChunkStore {
+    /// Encodes `data` as a chunked body, which will be written at `body_start`.
+    ///
+    /// Chunks are remembered as stored at once, so the body has to be written.
+    pub fn encode(&mut self, data: &[u8], body_start: u64) -> Vec<u8> {
+        use sha2::{Digest, Sha256};
+
+        let mut body = vec![];
+        let mut rest = data;
+        while !rest.is_empty() {
+            let (chunk, tail) = rest.split_at(chunk_len(rest));
+            rest = tail;
+
+            let hash: [u8; 32] = Sha256::digest(chunk).into();
+            let offset = body_start + (body.len() + RECORD_HEADER_LEN) as u64;
+            match self.chunks.get(&hash) {
+                Some(&(stored, crc)) => {
+                    body.push(REFERENCE);
+                    body.extend_from_slice(&(chunk.len() as u32).to_le_bytes());
+                    body.extend_from_slice(&stored.to_le_bytes());
+                    body.extend_from_slice(&crc.to_le_bytes());
+                }
+                None => {
+                    let crc = crate::crc32::digest(chunk);
+                    self.chunks.insert(hash, (offset, crc));
+                    body.push(LITERAL);
+                    body.extend_from_slice(&(chunk.len() as u32).to_le_bytes());
+                    body.extend_from_slice(chunk);
+                }
+            }
+        }
+        body
+    }
+}
+
+/// Writes the contents of a chunked body to `out`.
+///
+/// Referenced chunks are read by seeking the archive below `body`,
+/// `archive_start` being the position of the archive's first byte. They have to
+/// lie before the reference and match its checksum.
+pub fn restore<R: Read + Seek>(
+    body: &mut crate::crc32::Reader<io::Take<R>>,
+    archive_start: u64,
+    out: &mut impl Write,
+) -> io::Result<()> {
+    loop {
+        let mut tag = [0u8];
+        if body.read(&mut tag)? == 0 {
+            return Ok(());
+        }
+        let mut len = [0u8; 4];
+        body.read_exact(&mut len)?;
+        let len = u32::from_le_bytes(len) as u64;
+
+        match tag[0] {
+            LITERAL => {
+                let copied = io::copy(&mut body.take(len), out)?;
+                if copied != len {
+                    return Err(io::ErrorKind::UnexpectedEof.into());
+                }
+            }
+            REFERENCE => {
+                let mut offset = [0u8; 8];
+                body.read_exact(&mut offset)?;
+                let offset = u64::from_le_bytes(offset);
+                let mut crc = [0u8; 4];
+                body.read_exact(&mut crc)?;
+                let crc = u32::from_le_bytes(crc);
+
+                // the chunk lies outside the body, so it doesn't count for its checksum.
+                let archive = body.get_mut().get_mut();
+                let pos = archive.stream_position()?;
+                // tag, length, offset and checksum of this record.
+                let record_start = pos - 17;
+                let start = archive_start.checked_add(offset);
+                let end = start.and_then(|start| start.checked_add(len));
+                if end.is_none_or(|end| end > record_start) {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!("chunk reference to {offset} out of bounds"),
+                    ));
+                }
+                archive.seek(SeekFrom::Start(archive_start + offset))?;
+                let mut chunk = crate::crc32::Reader::new(archive.take(len));
+                let copied = io::copy(&mut chunk, out)?;
+                let digest = chunk.digest();
+                archive.seek(SeekFrom::Start(pos))?;
+                if copied != len {
+                    return Err(io::ErrorKind::UnexpectedEof.into());
+                }
+                if digest != crc {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!("chunk at {offset} doesn't match its checksum"),
+                    ));
+                }
+            }
+            tag => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("unknown chunk record {tag}"),
+                ))
+            }
+        }
+    }
+}
+
+#[cfg(feature = "dedup")]
+#[test]
+fn chunk_boundaries_follow_content() {
+    let mut data = vec![0u8; 1 << 20];
+    let mut state = 0x1234_5678u32;
+    for byte in data.iter_mut() {
+        state = state.wrapping_mul(1_103_515_245).wrapping_add(12345);
+        *byte = (state >> 24) as u8;
+    }
+
+    let chunks = |data: &[u8]| {
+        let mut lens = vec![];
+        let mut rest = data;
+        while !rest.is_empty() {
+            let len = chunk_len(rest);
+            assert!(len <= MAX_CHUNK);
+            lens.push(len);
+            rest = &rest[len..];
+        }
+        lens
+    };
+    let original = chunks(&data);
+    assert!(original.len() > 16);
+
+    // a prefix shifts the data, the boundaries after the first chunks stay put.
+    let mut shifted = vec![7u8; 100];
+    shifted.extend_from_slice(&data);
+    let shifted = chunks(&shifted);
+    assert_eq!(original[4..], shifted[shifted.len() - original.len() + 4..]);
+}
+
+#[test]
+fn restore_checks_references() {
+    let restore_from = |archive: &[u8], body_start: usize| {
+        let mut archive = io::Cursor::new(archive);
+        archive.set_position(body_start as u64);
+        let len = (archive.get_ref().len() - body_start) as u64;
+        let mut body = crate::crc32::Reader::new(archive.take(len));
+        let mut out = vec![];
+        restore(&mut body, 0, &mut out).map(|()| out)
+    };
+    let reference = |offset: u64, crc: u32| {
+        let mut record = vec![REFERENCE];
+        record.extend_from_slice(&4u32.to_le_bytes());
+        record.extend_from_slice(&offset.to_le_bytes());
+        record.extend_from_slice(&crc.to_le_bytes());
+        record
+    };
+
+    let mut archive = b"abcd".to_vec();
+    archive.extend_from_slice(&reference(0, crate::crc32::digest(b"abcd")));
+    assert_eq!(restore_from(&archive, 4).unwrap(), b"abcd");
+
+    let mut archive = b"abcd".to_vec();
+    archive.extend_from_slice(&reference(0, crate::crc32::digest(b"abce")));
+    let err = restore_from(&archive, 4).unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+
+    for offset in [1, 1 << 40, u64::MAX] {
+        let mut archive = b"abcd".to_vec();
+        archive.extend_from_slice(&reference(offset, crate::crc32::digest(b"abcd")));
+        let err = restore_from(&archive, 4).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+}
@@ -0,0 +1,434 @@
+use std::{
+    hash::{Hash, Hasher},
+    io::{Read, Seek},
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime},
+};
+
+#[cfg(test)]
+use crate::flags;
+use crate::{read::Counting, read1, read1_stream, DecodeError, EntryKind, Metadata};
+
+/// A single object stored in an archive.
+///
+/// Entries compare equal if they describe the same object: path, kind, size,
+/// permissions, owner, group and modification time. The checksum algorithm
+/// of the body doesn't matter.
+#[derive(Clone, Debug)]
+pub struct Entry {
+    pub(crate) meta: Metadata,
+    pub(crate) path: PathBuf,
+    /// length of the stored extended attributes, `0` without any.
+    pub(crate) xattrs_len: u64,
+}
+
+impl Entry {
+    /// The fields equality and hashing are based on.
+    fn key(&self) -> (&Path, EntryKind, u64, u16, u16, u16, u64) {
+        let meta = &self.meta;
+        (
+            &self.path,
+            meta.kind(),
+            meta.file_size,
+            meta.perms,
+            meta.owner,
+            meta.group,
+            meta.modified_at,
+        )
+    }
+}
+
+impl PartialEq for Entry {
+    fn eq(&self, other: &Self) -> bool {
+        self.key() == other.key()
+    }
+}
+
+impl Eq for Entry {}
+
+impl Hash for Entry {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.key().hash(state);
+    }
+}
+
+impl Entry {
+    pub(crate) fn is_index(&self) -> bool {
+        self.meta.flags.is_index()
+    }
+
+    pub(crate) fn is_comment(&self) -> bool {
+        self.meta.flags.is_comment()
+    }
+
+    /// The path the object was archived under.
+    ///
+    /// On unix this holds the archived bytes as they are, even if they aren't UTF-8.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// The kind of the object.
+    pub fn kind(&self) -> EntryKind {
+        self.meta.kind()
+    }
+
+    /// Size of the archived body in bytes.
+    pub fn size(&self) -> u64 {
+        self.meta.file_size
+    }
+
+    /// Modification time of the object.
+    pub fn modified_at(&self) -> SystemTime {
+        to_system_time(self.meta.modified_at)
+    }
+
+    /// Modification time in seconds since the unix epoch, as stored.
+    pub fn modified_at_secs(&self) -> u64 {
+        self.meta.modified_at
+    }
+
+    /// Bytes the entry takes up in the archive: two metadata blocks, the path,
+    /// the extended attributes and the body.
+    pub fn size_on_disk(&self) -> u64 {
+        self.meta.size_on_disk() + self.xattrs_len
+    }
+}
+
+fn to_system_time(secs: u64) -> SystemTime {
+    SystemTime::UNIX_EPOCH + Duration::from_secs(secs)
+}
+
+/// Description of an archived object, as returned by [`list`](crate::list).
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct EntryInfo {
+    pub path: PathBuf,
+    pub kind: EntryKind,
+    /// Size of the archived body in bytes.
+    pub size: u64,
+    pub perms: u16,
+    /// Modification time in seconds since the unix epoch.
+    modified_at: u64,
+}
+
+impl EntryInfo {
+    /// Describes the object archived as `path` with `header`.
+    pub(crate) fn from_header(header: &Metadata, path: PathBuf) -> Self {
+        EntryInfo {
+            path,
+            kind: header.kind(),
+            size: header.file_size,
+            perms: header.perms,
+            modified_at: header.modified_at,
+        }
+    }
+
+    /// Modification time of the object.
+    pub fn modified_at(&self) -> SystemTime {
+        to_system_time(self.modified_at)
+    }
+
+    /// Modification time in seconds since the unix epoch, as stored.
+    pub fn modified_at_secs(&self) -> u64 {
+        self.modified_at
+    }
+
+    /// Sets the modification time in seconds since the unix epoch,
+    /// e.g. for [`map_archive`](crate::map_archive).
+    pub fn set_modified_at_secs(&mut self, secs: u64) {
+        self.modified_at = secs;
+    }
+
+    /// Bytes the entry takes up in the archive: two metadata blocks, the path and the body.
+    /// Extended attributes aren't accounted for.
+    pub fn size_on_disk(&self) -> u64 {
+        let path_len = crate::platform::path_to_bytes(&self.path).len();
+        (2 * Metadata::SIZE + path_len) as u64 + self.size
+    }
+}
+
+impl From<Entry> for EntryInfo {
+    fn from(entry: Entry) -> Self {
+        EntryInfo::from_header(&entry.meta, entry.path)
+    }
+}
+
+/// Iterator over the entries of an archive.
+///
+/// Bodies are skipped by seeking, so walking an archive doesn't read file contents.
+/// Iteration stops after the first error.
+pub struct Entries<R> {
+    archive: R,
+    done: bool,
+    /// whether no entry has been read yet.
+    first: bool,
+    /// reused for the path of every entry.
+    path_buf: Vec<u8>,
+}
+
+impl<R: Read + Seek> Entries<R> {
+    pub fn new(archive: R) -> Self {
+        Self {
+            archive,
+            done: false,
+            first: true,
+            path_buf: vec![],
+        }
+    }
+}
+
+impl<R: Read + Seek> Iterator for Entries<R> {
+    type Item = Result<Entry, DecodeError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+        let first = std::mem::replace(&mut self.first, false);
+        match read1(&mut self.archive, &mut self.path_buf) {
+            // the index and the comment describe the archive, they aren't entries themselves.
+            Ok(entry) if entry.is_index() || entry.is_comment() => self.next(),
+            Ok(entry) => Some(Ok(entry)),
+            Err(DecodeError::Exhausted) => {
+                self.done = true;
+                None
+            }
+            Err(e) => {
+                self.done = true;
+                Some(Err(if first { e } else { e.mid_archive() }))
+            }
+        }
+    }
+}
+
+/// Iterator over the entries of an archive that can only be read sequentially, e.g. `stdin`.
+///
+/// Unlike [`Entries`], bodies are read and discarded, which also verifies their checksums.
+/// Iteration stops after the first error.
+pub struct StreamEntries<R> {
+    archive: Counting<R>,
+    done: bool,
+    /// whether no entry has been read yet.
+    first: bool,
+    /// reused for the path of every entry.
+    path_buf: Vec<u8>,
+}
+
+impl<R: Read> StreamEntries<R> {
+    pub fn new(archive: R) -> Self {
+        Self {
+            archive: Counting::new(archive),
+            done: false,
+            first: true,
+            path_buf: vec![],
+        }
+    }
+}
+
+impl<R: Read> Iterator for StreamEntries<R> {
+    type Item = Result<Entry, DecodeError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+        let first = std::mem::replace(&mut self.first, false);
+        match read1_stream(&mut self.archive, &mut self.path_buf) {
+            Ok(entry) if entry.is_index() || entry.is_comment() => self.next(),
+            Ok(entry) => Some(Ok(entry)),
+            Err(DecodeError::Exhausted) => {
+                self.done = true;
+                None
+            }
+            Err(e) => {
+                self.done = true;
+                Some(Err(if first { e } else { e.mid_archive() }))
+            }
+        }
+    }
+}
+
+#[test]
+fn stream_entries_without_seek() {
+    /// Hands out a few bytes at a time and can't seek, like a pipe.
+    struct Pipe<'a>(&'a [u8]);
+
+    impl Read for Pipe<'_> {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            let n = buf.len().min(self.0.len()).min(7);
+            buf[..n].copy_from_slice(&self.0[..n]);
+            self.0 = &self.0[n..];
+            Ok(n)
+        }
+    }
+
+    let mut archive = vec![];
+    let mut archiver = crate::Archiver::new(&mut archive).index(true).build();
+    archiver.set_comment("piped").unwrap();
+    archiver
+        .add_reader("a.txt", &mut &b"abc"[..], 3, 0)
+        .unwrap();
+    archiver
+        .add_reader("b.txt", &mut &b"defgh"[..], 5, 0)
+        .unwrap();
+    archiver.add_reader("c.txt", &mut &b""[..], 0, 0).unwrap();
+    archiver.finish().unwrap();
+
+    let entries = StreamEntries::new(Pipe(&archive))
+        .collect::<Result<Vec<_>, _>>()
+        .unwrap();
+    let paths = entries.iter().map(Entry::path).collect::<Vec<_>>();
+    assert_eq!(paths, ["a.txt", "b.txt", "c.txt"]);
+    assert_eq!(entries[1].size(), 5);
+
+    let cut = &archive[..archive.len() - 1];
+    let last = StreamEntries::new(Pipe(cut)).last().unwrap();
+    assert_eq!(
+        last.unwrap_err(),
+        DecodeError::Crop {
+            offset: Some((archive.len() - Metadata::SIZE) as u64)
+        }
+    );
+}
+
+#[test]
+fn entries_walk_archive() {
+    let dir = tempfile::tempdir().unwrap();
+    std::fs::create_dir(dir.path().join("sub")).unwrap();
+    std::fs::write(dir.path().join("sub/a.txt"), b"12345").unwrap();
+
+    let mut archive = vec![];
+    crate::recursive_archive(&mut archive, dir.path()).unwrap();
+
+    let entries = Entries::new(std::io::Cursor::new(archive))
+        .collect::<Result<Vec<_>, _>>()
+        .unwrap();
+    assert_eq!(entries.len(), 3);
+
+    let file = entries
+        .iter()
+        .find(|e| e.path().ends_with("a.txt"))
+        .unwrap();
+    assert_eq!(file.kind(), EntryKind::File);
+    assert_eq!(file.size(), 5);
+    assert!(file.modified_at_secs() > 0);
+    assert_eq!(
+        entries
+            .iter()
+            .filter(|e| e.kind() == EntryKind::Directory)
+            .count(),
+        2
+    );
+}
+
+#[test]
+fn entry_kind_from_flags() {
+    assert_eq!(EntryKind::try_from(flags::FILE), Ok(EntryKind::File));
+    assert_eq!(
+        EntryKind::try_from(flags::DIR | flags::HEADER),
+        Ok(EntryKind::Directory)
+    );
+    assert_eq!(
+        EntryKind::try_from(flags::SOFT_LINK),
+        Ok(EntryKind::SoftLink)
+    );
+    assert_eq!(
+        EntryKind::try_from(0x7 | flags::HEADER),
+        Err(DecodeError::Header { offset: None })
+    );
+    assert_eq!(EntryKind::HardLink.to_string(), "Hard Link");
+    assert_eq!(format!("{: <9}|", EntryKind::File), "File     |");
+}
+
+#[test]
+fn modification_time_roundtrips() {
+    let mut archive = vec![];
+    crate::append_reader(&mut archive, "a.txt", &mut &b"abc"[..], 3, 1_700_000_123).unwrap();
+
+    let entry = Entries::new(std::io::Cursor::new(&archive))
+        .next()
+        .unwrap()
+        .unwrap();
+    assert_eq!(entry.modified_at_secs(), 1_700_000_123);
+    let since_epoch = entry
+        .modified_at()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap();
+    assert_eq!(since_epoch, Duration::from_secs(1_700_000_123));
+    assert_eq!(
+        EntryInfo::from(entry.clone()).modified_at(),
+        entry.modified_at()
+    );
+}
+
+#[test]
+fn size_on_disk_adds_up_to_archive() {
+    let dir = tempfile::tempdir().unwrap();
+    std::fs::create_dir(dir.path().join("sub")).unwrap();
+    std::fs::write(dir.path().join("a.txt"), b"abc").unwrap();
+    std::fs::write(dir.path().join("sub/b.txt"), b"defg").unwrap();
+
+    let mut archive = vec![];
+    crate::recursive_archive(&mut archive, dir.path()).unwrap();
+
+    let entries_len = (archive.len() - crate::trailer::LEN) as u64;
+
+    let infos = crate::list(&mut std::io::Cursor::new(&archive)).unwrap();
+    let total = infos.iter().map(EntryInfo::size_on_disk).sum::<u64>();
+    assert_eq!(total, entries_len);
+    let total = Entries::new(std::io::Cursor::new(&archive))
+        .map(|entry| entry.unwrap().size_on_disk())
+        .sum::<u64>();
+    assert_eq!(total, entries_len);
+}
+
+#[test]
+fn entries_collect_into_sets() {
+    use std::collections::HashSet;
+
+    let mut archive = vec![];
+    for (name, body) in [("a.txt", "abc"), ("b.txt", "defg"), ("a.txt", "abc")] {
+        crate::append_reader(
+            &mut archive,
+            name,
+            &mut body.as_bytes(),
+            body.len() as u64,
+            0,
+        )
+        .unwrap();
+    }
+    let infos = crate::list(&mut std::io::Cursor::new(&archive))
+        .unwrap()
+        .into_iter()
+        .collect::<HashSet<_>>();
+    assert_eq!(infos.len(), 2);
+    assert!(infos
+        .iter()
+        .any(|info| info.path == Path::new("b.txt") && info.size == 4));
+
+    // the checksum algorithm only changes how the body is stored.
+    let mut archiver = crate::Archiver::new(vec![])
+        .checksum(crate::ChecksumAlgorithm::Crc32c)
+        .build();
+    archiver
+        .add_reader("a.txt", &mut &b"abc"[..], 3, 0)
+        .unwrap();
+    let other = archiver.finish().unwrap();
+    let entries = Entries::new(std::io::Cursor::new(&archive))
+        .chain(Entries::new(std::io::Cursor::new(&other)))
+        .collect::<Result<HashSet<_>, _>>()
+        .unwrap();
+    assert_eq!(entries.len(), 2);
+
+    // files of different sizes differ, even if modified within the same second.
+    let mut longer = vec![];
+    crate::append_reader(&mut longer, "a.txt", &mut &b"abcd"[..], 4, 0).unwrap();
+    let entries = Entries::new(std::io::Cursor::new(&archive))
+        .take(1)
+        .chain(Entries::new(std::io::Cursor::new(&longer)))
+        .collect::<Result<Vec<_>, _>>()
+        .unwrap();
+    assert_eq!(entries[0].modified_at_secs(), entries[1].modified_at_secs());
+    assert_ne!(entries[0], entries[1]);
+    assert_eq!(entries.into_iter().collect::<HashSet<_>>().len(), 2);
+}
@@ -0,0 +1,1355 @@
+use std::{
+    collections::HashSet,
+    fmt,
+    io::{self, Read, Seek, Write},
+    path::{Component, Path, PathBuf},
+    sync::Arc,
+};
+
+use crate::{
+    check_footer, compression, crc32, crypto, decode_err, dedup, flags, platform, read_header,
+    read_path,
+    sink::{FileSink, RealFs},
+    sparse, xattrs, DecodeError, Entries, EntryKind, Metadata,
+};
+
+#[cfg(feature = "encrypt")]
+use crate::crypto::Key;
+#[cfg(all(test, unix))]
+use std::os::unix::prelude::{OsStrExt, PermissionsExt};
+
+/// Builds the on-disk location of an archived path below `dest`.
+///
+/// Root and prefix components are dropped, so absolute paths stored in the
+/// archive end up relative to `dest`. Parent directory components are rejected.
+pub(crate) fn target_path(dest: &Path, path: &[u8]) -> io::Result<PathBuf> {
+    let mut target = dest.to_path_buf();
+    for component in platform::bytes_to_path(path).components() {
+        match component {
+            Component::Normal(part) => target.push(part),
+            Component::RootDir | Component::CurDir | Component::Prefix(..) => {}
+            Component::ParentDir => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!(
+                        "archived path escapes destination: {}",
+                        String::from_utf8_lossy(path)
+                    ),
+                ))
+            }
+        }
+    }
+    Ok(target)
+}
+
+/// Fails if an archived symlink redirects `target` outside of `dest`.
+///
+/// [`target_path`] only looks at the archived path, whereas an earlier entry
+/// may have placed a symlink to anywhere on one of its directories.
+pub(crate) fn check_contained(dest: &Path, target: &Path) -> io::Result<()> {
+    let Some(parent) = target.parent() else {
+        return Ok(());
+    };
+    // nothing below the deepest existing directory can be a symlink yet.
+    let Some(existing) = parent
+        .ancestors()
+        .take_while(|dir| dir.starts_with(dest))
+        .find(|dir| dir.symlink_metadata().is_ok())
+    else {
+        return Ok(());
+    };
+    if !existing.canonicalize()?.starts_with(dest.canonicalize()?) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!(
+                "archived path escapes destination through a symlink: {}",
+                target.display()
+            ),
+        ));
+    }
+    Ok(())
+}
+
+/// Applies the archived permission bits to `target`.
+///
+/// Archives without recorded permissions store `0` (a recorded mode always
+/// contains the file type bits), in which case nothing is changed.
+pub(crate) fn restore_perms(target: &Path, perms: u16) -> io::Result<()> {
+    if perms == 0 {
+        return Ok(());
+    }
+    platform::set_perms(target, perms)
+}
+
+/// Sets the modification time of `target`, without following symlinks.
+pub(crate) fn restore_mtime(target: &Path, modified_at: u64) -> io::Result<()> {
+    let mtime = filetime::FileTime::from_unix_time(modified_at as i64, 0);
+    filetime::set_symlink_file_times(target, mtime, mtime)
+}
+
+/// Changes the owner of `target` to `owner` and `group`.
+///
+/// Only privileged processes may hand files to other users, a lack of
+/// privilege is logged and otherwise ignored.
+pub(crate) fn restore_owner(target: &Path, owner: u16, group: u16) -> io::Result<()> {
+    match platform::set_owner(target, owner, group) {
+        Err(e) if e.kind() == io::ErrorKind::PermissionDenied => {
+            log::warn!("Not permitted to restore owner of {}", target.display());
+            Ok(())
+        }
+        res => res,
+    }
+}
+
+/// Drains what's left of a body, returning the checksum of the whole body.
+pub(crate) fn finish_body<R: Read>(mut body: crc32::Reader<io::Take<R>>) -> io::Result<u32> {
+    // a decoder may stop short of trailing bytes, they still count for the checksum.
+    io::copy(&mut body, &mut io::sink())?;
+    if body.get_ref().limit() > 0 {
+        return Err(io::ErrorKind::UnexpectedEof.into());
+    }
+    Ok(body.digest())
+}
+
+/// Reads the footer following a body, checking it against the header and the body.
+///
+/// Bodies of files that changed size while they were archived don't hold
+/// the file's contents, so they fail with [`DecodeError::Resized`].
+pub(crate) fn read_footer<R: Read + Seek>(
+    archive: &mut R,
+    header: &Metadata,
+    body_checksum: u32,
+) -> io::Result<()> {
+    let footer_start = archive.stream_position()?;
+    let footer = crate::read_footer(archive).map_err(|e| e.at(footer_start))?;
+    check_footer(header, &footer).map_err(|e| e.at(footer_start))?;
+    if footer.body_checksum != body_checksum {
+        log::error!("Body checksum mismatch");
+        let body_start = footer_start - header.file_size;
+        return Err(DecodeError::Checksum {
+            offset: Some(body_start),
+        }
+        .into());
+    }
+    if footer.flags.resized() {
+        log::error!("File changed size while it was archived");
+        return Err(DecodeError::Resized {
+            offset: Some(footer_start),
+        }
+        .into());
+    }
+    Ok(())
+}
+
+/// What to do with objects that already exist at the destination.
+/// Existing directories are always merged with the archived ones.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum OverwritePolicy {
+    /// Leave the existing object untouched.
+    Skip,
+    /// Replace the existing object.
+    #[default]
+    Overwrite,
+    /// Fail with [`io::ErrorKind::AlreadyExists`].
+    Error,
+}
+
+/// Owner and group to restore for archived ones, see [`ExtractOptions::owner_map`].
+#[derive(Clone, Default)]
+pub enum OwnerMap {
+    /// Restore the archived uid and gid.
+    #[default]
+    Preserve,
+    /// Hand every object to the user and group running the extraction.
+    CurrentUser,
+    /// Restore the uid and gid the function returns for the archived ones,
+    /// e.g. to translate between the users of different machines.
+    Custom(Arc<dyn Fn(u16, u16) -> (u16, u16) + Send + Sync>),
+}
+
+impl OwnerMap {
+    /// Maps owners and groups with `map`, called with the archived uid and gid.
+    pub fn custom(map: impl Fn(u16, u16) -> (u16, u16) + Send + Sync + 'static) -> Self {
+        Self::Custom(Arc::new(map))
+    }
+
+    /// The uid and gid to restore for an archived `owner` and `group`.
+    pub fn map(&self, owner: u16, group: u16) -> (u16, u16) {
+        match self {
+            Self::Preserve => (owner, group),
+            Self::CurrentUser => platform::current_owner(),
+            Self::Custom(map) => map(owner, group),
+        }
+    }
+}
+
+impl fmt::Debug for OwnerMap {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Preserve => f.write_str("Preserve"),
+            Self::CurrentUser => f.write_str("CurrentUser"),
+            Self::Custom(_) => f.write_str("Custom(..)"),
+        }
+    }
+}
+
+/// Options controlling how [`extract_with_options`] restores entries.
+#[derive(Clone, Debug, Default)]
+pub struct ExtractOptions {
+    /// Restore the archived owner and group of every entry.
+    /// This requires privilege, e.g. running as root.
+    pub preserve_owner: bool,
+    /// Owner and group restored for the archived ones with `preserve_owner`.
+    pub owner_map: OwnerMap,
+    /// How to treat objects that already exist at the destination.
+    pub overwrite: OverwritePolicy,
+    /// Size of the buffer file contents are copied through, `0` leaves
+    /// the choice to [`io::copy`]. Larger buffers mean fewer, larger writes,
+    /// which pays off on high-latency storage.
+    pub buffer_size: usize,
+    /// Kinds of objects to restore, bodies of other entries are skipped.
+    /// `None` restores all, e.g. leaving out symlinks and devices
+    /// keeps an untrusted archive from placing them.
+    pub kinds: Option<HashSet<EntryKind>>,
+    /// Key to decrypt encrypted file bodies with.
+    #[cfg(feature = "encrypt")]
+    pub key: Option<Key>,
+}
+
+/// Restores all objects of the archive below `dest`.
+pub fn extract<R: Read + Seek>(archive: &mut R, dest: &Path) -> io::Result<()> {
+    extract_with_options(archive, dest, &ExtractOptions::default())
+}
+
+/// Lists where [`extract`] would restore each object below `dest`, without touching the disk.
+///
+/// Sizes are those of the archived bodies, which differ from the restored
+/// files for compressed, sparse and deduplicated entries.
+pub fn extract_dry_run<R: Read + Seek>(
+    archive: &mut R,
+    dest: &Path,
+) -> Result<Vec<(PathBuf, u64)>, DecodeError> {
+    Entries::new(archive)
+        .map(|entry| {
+            let entry = entry?;
+            let target =
+                target_path(dest, &platform::path_to_bytes(entry.path())).map_err(|e| {
+                    log::error!("{e}");
+                    DecodeError::Header { offset: None }
+                })?;
+            Ok((target, entry.size()))
+        })
+        .collect()
+}
+
+/// Restores all objects of the archive below `dest`, configured by `options`.
+pub fn extract_with_options<R: Read + Seek>(
+    archive: &mut R,
+    dest: &Path,
+    options: &ExtractOptions,
+) -> io::Result<()> {
+    extract_with_progress(archive, dest, options, &mut |_, _, _| {})
+}
+
+/// Like [`extract_with_options`], but calls `progress` whenever a piece of a file's
+/// body has been read, with the restored path, the bytes of the body read so far and
+/// the size of the whole body.
+///
+/// Sizes are those of the archived bodies, which differ from the restored files
+/// for compressed, sparse and deduplicated entries. Deduplicated bodies are
+/// only reported once they are restored completely.
+pub fn extract_with_progress<R: Read + Seek>(
+    archive: &mut R,
+    dest: &Path,
+    options: &ExtractOptions,
+    progress: &mut dyn FnMut(&Path, u64, u64),
+) -> io::Result<()> {
+    let mut sink = RealFs::new(dest);
+    extract_into(archive, &mut sink, options, &mut |path, done, total| {
+        progress(&dest.join(path), done, total)
+    })
+}
+
+/// Restores all objects of the archive into `sink`, configured by `options`.
+/// `progress` is called as for [`extract_with_progress`], with the paths given to `sink`.
+pub(crate) fn extract_into<R: Read + Seek, S: FileSink>(
+    archive: &mut R,
+    sink: &mut S,
+    options: &ExtractOptions,
+    progress: &mut dyn FnMut(&Path, u64, u64),
+) -> io::Result<()> {
+    // directory permissions and mtimes are applied last, a read-only directory
+    // would otherwise prevent its children from being created and creating
+    // children updates the mtime.
+    let mut dirs = vec![];
+    let archive_start = archive.stream_position()?;
+
+    let mut first = true;
+    loop {
+        let start = archive.stream_position()?;
+        let header = match read_header(archive).map_err(|e| e.at(start)) {
+            Ok(header) => header,
+            Err(DecodeError::Exhausted) => break,
+            Err(e) if first => return Err(e.into()),
+            Err(e) => return Err(e.mid_archive().into()),
+        };
+        first = false;
+        let path = read_path(&header, archive).map_err(|e| e.at(start))?;
+        if header.flags.is_special() {
+            skip_body(archive, &header)?;
+            continue;
+        }
+        let target = target_path(Path::new(""), &path)?;
+        let xattrs = xattrs::read(&header, &path, archive)?;
+        if let Some(kinds) = &options.kinds {
+            if !kinds.contains(&header.kind()) {
+                skip_body(archive, &header)?;
+                continue;
+            }
+        }
+
+        if !header.flags.is_dir() && sink.exists(&target) {
+            match options.overwrite {
+                OverwritePolicy::Skip => {
+                    skip_body(archive, &header)?;
+                    continue;
+                }
+                // sinks replace existing objects.
+                OverwritePolicy::Overwrite => {}
+                OverwritePolicy::Error => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::AlreadyExists,
+                        format!("{} already exists", target.display()),
+                    ))
+                }
+            }
+        }
+
+        let mut body = crc32::Reader::for_body(archive.take(header.file_size), header.flags);
+        // file contents are streamed into the sink, other bodies are short.
+        let mut file = None;
+        let mut stored = vec![];
+        if header.flags.is_file() {
+            let mut out = sink.create_file(&target)?;
+            #[cfg(feature = "encrypt")]
+            let key = options.key.as_ref();
+            #[cfg(not(feature = "encrypt"))]
+            let key = None;
+            if header.flags.chunked() {
+                dedup::restore(&mut body, archive_start, &mut out)?;
+                progress(&target, header.file_size, header.file_size);
+            } else {
+                let body = Progress {
+                    inner: &mut body,
+                    path: &target,
+                    done: 0,
+                    total: header.file_size,
+                    progress: &mut *progress,
+                };
+                let decrypted = crypto::decryptor(&header, &path, body, key)?;
+                let mut contents = compression::decoder(header.flags, decrypted)?;
+                if header.flags.sparse() {
+                    let max_len = sink.max_sparse_len();
+                    sparse::restore(&mut contents, &mut out, max_len, |out, len| {
+                        sink.write_hole(out, len)
+                    })?;
+                } else {
+                    copy_buffered(&mut contents, &mut out, options.buffer_size)?;
+                }
+            }
+            file = Some(out);
+        } else {
+            body.read_to_end(&mut stored)?;
+        }
+        let body_checksum = finish_body(body)?;
+        read_footer(archive, &header, body_checksum)?;
+
+        match header.flags.kind_bits() {
+            flags::DIR => {
+                sink.create_dir(&target)?;
+                dirs.push((target.clone(), header.clone()));
+            }
+            flags::FILE => {
+                if let Some(file) = file {
+                    sink.finish_file(&target, file)?;
+                }
+                sink.set_permissions(&target, header.perms)?;
+            }
+            flags::SOFT_LINK => sink.create_symlink(&target, &platform::bytes_to_path(&stored))?,
+            flags::HARD_LINK => {
+                let original = target_path(Path::new(""), &stored)?;
+                sink.create_hard_link(&target, &original)?;
+            }
+            flags::FIFO | flags::CHAR_DEV | flags::BLOCK_DEV => {
+                let mut device = [0u8; 8];
+                let len = stored.len().min(device.len());
+                device[..len].copy_from_slice(&stored[..len]);
+                let device = u64::from_le_bytes(device);
+                sink.create_device(&target, header.kind(), header.perms, device)?;
+            }
+            _ => {
+                log::warn!(
+                    "Skipping unsupported {}: {}",
+                    header.kind(),
+                    target.display()
+                );
+            }
+        }
+
+        if let Err(e) = sink.set_xattrs(&target, &xattrs) {
+            log::warn!(
+                "Failed to restore extended attributes of {}: {e}",
+                target.display()
+            );
+        }
+        if options.preserve_owner && sink.exists(&target) {
+            let (owner, group) = options.owner_map.map(header.owner, header.group);
+            sink.set_owner(&target, owner, group)?;
+            // changing the owner clears the setuid and setgid bits of files.
+            if header.flags.is_file() {
+                sink.set_permissions(&target, header.perms)?;
+            }
+        }
+        if !header.flags.is_dir() && sink.exists(&target) {
+            sink.set_modified(&target, header.modified_at)?;
+        }
+    }
+
+    for (dir, header) in dirs.iter().rev() {
+        sink.set_permissions(dir, header.perms)?;
+        sink.set_modified(dir, header.modified_at)?;
+    }
+
+    Ok(())
+}
+
+/// Reader adapter reporting every read to the callback of [`extract_with_progress`].
+struct Progress<'a, R> {
+    inner: R,
+    path: &'a Path,
+    done: u64,
+    total: u64,
+    progress: &'a mut dyn FnMut(&Path, u64, u64),
+}
+
+impl<R: Read> Read for Progress<'_, R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        if n > 0 {
+            self.done += n as u64;
+            (self.progress)(self.path, self.done, self.total);
+        }
+        Ok(n)
+    }
+}
+
+/// Like [`io::copy`], but through a buffer of `buffer_size` bytes unless it's `0`.
+fn copy_buffered(
+    reader: &mut impl Read,
+    writer: &mut impl Write,
+    buffer_size: usize,
+) -> io::Result<u64> {
+    if buffer_size == 0 {
+        return io::copy(reader, writer);
+    }
+    let mut buf = vec![0u8; buffer_size];
+    let mut copied = 0;
+    loop {
+        let n = match reader.read(&mut buf) {
+            Ok(0) => return Ok(copied),
+            Ok(n) => n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        };
+        writer.write_all(&buf[..n])?;
+        copied += n as u64;
+    }
+}
+
+#[test]
+fn extract_with_buffer_sizes() {
+    let src = tempfile::tempdir().unwrap();
+    let mut contents = vec![0u8; 3 << 19];
+    let mut state = 0x9e37_79b9u32;
+    for byte in contents.iter_mut() {
+        state = state.wrapping_mul(1_103_515_245).wrapping_add(12345);
+        *byte = (state >> 24) as u8;
+    }
+    std::fs::write(src.path().join("large.bin"), &contents).unwrap();
+
+    let mut archive = vec![];
+    crate::recursive_archive(&mut archive, src.path()).unwrap();
+
+    for buffer_size in [0, 1, 4096, 1 << 20] {
+        let dest = tempfile::tempdir().unwrap();
+        let options = ExtractOptions {
+            buffer_size,
+            ..Default::default()
+        };
+        extract_with_options(&mut io::Cursor::new(&archive), dest.path(), &options).unwrap();
+        let target = target_path(
+            dest.path(),
+            src.path().join("large.bin").to_str().unwrap().as_bytes(),
+        )
+        .unwrap();
+        assert!(
+            std::fs::read(target).unwrap() == contents,
+            "buffer size {buffer_size}"
+        );
+    }
+}
+
+#[test]
+fn extract_reports_progress_within_files() {
+    let mut archive = vec![];
+    let large = vec![0x5au8; 1 << 20];
+    crate::append_reader(&mut archive, "large.bin", &mut large.as_slice(), 1 << 20, 0).unwrap();
+    crate::append_reader(&mut archive, "small.txt", &mut &b"abc"[..], 3, 0).unwrap();
+
+    let dest = tempfile::tempdir().unwrap();
+    let mut calls = vec![];
+    extract_with_progress(
+        &mut io::Cursor::new(&archive),
+        dest.path(),
+        &ExtractOptions::default(),
+        &mut |path, done, total| calls.push((path.to_path_buf(), done, total)),
+    )
+    .unwrap();
+
+    let (large_calls, small_calls): (Vec<_>, Vec<_>) = calls
+        .into_iter()
+        .partition(|(path, _, _)| path.ends_with("large.bin"));
+    assert!(large_calls.len() > 1);
+    assert!(large_calls.windows(2).all(|w| w[0].1 < w[1].1));
+    assert!(large_calls.iter().all(|&(_, _, total)| total == 1 << 20));
+    assert_eq!(large_calls.last().unwrap().1, 1 << 20);
+    assert_eq!(small_calls.last().unwrap().1, 3);
+    assert_eq!(std::fs::read(dest.path().join("large.bin")).unwrap(), large);
+}
+
+/// Seeks past the body of the entry whose path was just read and checks its footer.
+pub(crate) fn skip_body<R: Read + Seek>(archive: &mut R, header: &Metadata) -> io::Result<()> {
+    let footer_start = archive.seek(io::SeekFrom::Current(header.file_size as _))?;
+    let footer = crate::read_footer(archive).map_err(|e| e.at(footer_start))?;
+    check_footer(header, &footer).map_err(|e| e.at(footer_start))?;
+    Ok(())
+}
+
+/// Writes the contents of the file archived as `path` to `out`.
+///
+/// Other entries are skipped by seeking past their bodies.
+/// Returns whether the file was found. Encrypted files can't be read this way.
+///
+/// Contents are streamed to `out` as they are read, the checksum can only be
+/// checked at the end. On [`DecodeError::Checksum`] and [`DecodeError::Resized`]
+/// `out` has received corrupt contents, which callers have to discard, e.g. by
+/// writing to a temporary file first. Failures writing to `out` are reported as [`DecodeError::Crop`].
+pub fn extract_one<R: Read + Seek>(
+    archive: &mut R,
+    path: impl AsRef<Path>,
+    out: &mut impl Write,
+) -> Result<bool, DecodeError> {
+    let archive_start = archive.stream_position().map_err(decode_err)?;
+    extract_one_from(archive, archive_start, path.as_ref(), out).map_err(decode_err)
+}
+
+/// Like [`extract_one`], for an archive positioned past its start at `archive_start`.
+pub(crate) fn extract_one_from<R: Read + Seek>(
+    archive: &mut R,
+    archive_start: u64,
+    path: &Path,
+    out: &mut impl Write,
+) -> io::Result<bool> {
+    let path = platform::path_to_bytes(path);
+    let mut first = true;
+    loop {
+        let start = archive.stream_position()?;
+        let header = match read_header(archive).map_err(|e| e.at(start)) {
+            Ok(header) => header,
+            Err(DecodeError::Exhausted) => return Ok(false),
+            Err(e) if first => return Err(e.into()),
+            Err(e) => return Err(e.mid_archive().into()),
+        };
+        first = false;
+        let entry_path = read_path(&header, archive).map_err(|e| e.at(start))?;
+        xattrs::read(&header, &entry_path, archive)?;
+
+        if entry_path != path || !header.flags.is_file() || header.flags.is_special() {
+            skip_body(archive, &header)?;
+            continue;
+        }
+
+        let mut body = crc32::Reader::for_body(archive.take(header.file_size), header.flags);
+        decode_file(&mut body, &header, &entry_path, archive_start, None, out)?;
+        let body_checksum = finish_body(body)?;
+        read_footer(archive, &header, body_checksum)?;
+        return Ok(true);
+    }
+}
+
+/// Writes the contents of a file body to `out`, undoing deduplication,
+/// encryption, compression and the omission of zeros in sparse files.
+pub(crate) fn decode_file<R: Read + Seek>(
+    body: &mut crc32::Reader<io::Take<R>>,
+    header: &Metadata,
+    path: &[u8],
+    archive_start: u64,
+    key: Option<&crypto::Key>,
+    out: &mut impl Write,
+) -> io::Result<()> {
+    if header.flags.chunked() {
+        return dedup::restore(body, archive_start, out);
+    }
+    let decrypted = crypto::decryptor(header, path, body, key)?;
+    let mut contents = compression::decoder(header.flags, decrypted)?;
+    if header.flags.sparse() {
+        sparse::expand(&mut contents, out)?;
+    } else {
+        io::copy(&mut contents, out)?;
+    }
+    Ok(())
+}
+
+#[test]
+fn extract_one_writes_only_requested() {
+    let dir = tempfile::tempdir().unwrap();
+    for (name, contents) in [("a.txt", "first"), ("b.txt", "second"), ("c.txt", "third")] {
+        std::fs::write(dir.path().join(name), contents).unwrap();
+    }
+
+    let mut archive = vec![];
+    crate::recursive_archive(&mut archive, dir.path()).unwrap();
+
+    let wanted = dir.path().join("b.txt");
+    let mut out = vec![];
+    let found = extract_one(
+        &mut io::Cursor::new(&archive),
+        wanted.to_str().unwrap(),
+        &mut out,
+    )
+    .unwrap();
+    assert!(found);
+    assert_eq!(out, b"second");
+
+    let mut out = vec![];
+    let found = extract_one(&mut io::Cursor::new(&archive), "missing.txt", &mut out).unwrap();
+    assert!(!found);
+    assert!(out.is_empty());
+
+    let cut = &archive[..archive.len() - 1];
+    let err = extract_one(&mut io::Cursor::new(cut), "missing.txt", &mut out).unwrap_err();
+    assert!(matches!(err, DecodeError::Crop { .. }));
+}
+
+#[test]
+fn extract_one_rejects_huge_holes() {
+    // a single 2 TiB hole, beyond what's written out as zeros.
+    let mut body = (2u64 << 40).to_le_bytes().to_vec();
+    body.extend(0u64.to_le_bytes());
+    let path = b"huge.bin";
+    let meta = Metadata::new(flags::FILE | flags::SPARSE, body.len() as _, 8, 0, 0, 0, 0);
+    let mut archive = meta.header(path, &[]).to_vec();
+    archive.extend(path);
+    archive.extend(&body);
+    archive.extend(meta.footer(crc32::digest(&body)).to_vec());
+
+    // which is told apart from a truncated archive.
+    let mut out = vec![];
+    let err = extract_one(&mut io::Cursor::new(&archive), "huge.bin", &mut out).unwrap_err();
+    assert!(matches!(err, DecodeError::TooLarge { .. }));
+    assert!(out.is_empty());
+}
+
+#[cfg(unix)]
+#[test]
+fn fifo_roundtrip() {
+    use std::os::unix::fs::FileTypeExt;
+
+    let src = tempfile::tempdir().unwrap();
+    let fifo = src.path().join("pipe");
+    let c_path = std::ffi::CString::new(fifo.as_os_str().as_bytes()).unwrap();
+    assert_eq!(unsafe { libc::mkfifo(c_path.as_ptr(), 0o640) }, 0);
+
+    let mut archive = vec![];
+    crate::recursive_archive(&mut archive, src.path()).unwrap();
+    let entries = crate::list(&mut io::Cursor::new(&archive)).unwrap();
+    assert_eq!(entries[1].kind, crate::EntryKind::Fifo);
+
+    let dest = tempfile::tempdir().unwrap();
+    extract(&mut io::Cursor::new(&archive), dest.path()).unwrap();
+    let target = target_path(dest.path(), fifo.as_os_str().as_bytes()).unwrap();
+    let metadata = target.symlink_metadata().unwrap();
+    assert!(metadata.file_type().is_fifo());
+    assert_eq!(metadata.permissions().mode() & 0o777, 0o640);
+}
+
+#[cfg(unix)]
+#[test]
+fn extract_roundtrip() {
+    let src = tempfile::tempdir().unwrap();
+    let dest = tempfile::tempdir().unwrap();
+    std::fs::create_dir(src.path().join("dir")).unwrap();
+    std::fs::write(src.path().join("dir/a.txt"), b"hello bitumen").unwrap();
+    std::fs::write(src.path().join("b.txt"), b"").unwrap();
+
+    let mut archive = vec![];
+    crate::recursive_archive(&mut archive, src.path()).unwrap();
+    extract(&mut io::Cursor::new(archive), dest.path()).unwrap();
+
+    let restored = target_path(dest.path(), src.path().as_os_str().as_bytes()).unwrap();
+    assert!(restored.join("dir").is_dir());
+    assert_eq!(
+        std::fs::read(restored.join("dir/a.txt")).unwrap(),
+        b"hello bitumen"
+    );
+    assert_eq!(std::fs::read(restored.join("b.txt")).unwrap(), b"");
+}
+
+#[test]
+fn dry_run_matches_extraction() {
+    let src = tempfile::tempdir().unwrap();
+    std::fs::create_dir(src.path().join("dir")).unwrap();
+    std::fs::write(src.path().join("dir/a.txt"), b"hello bitumen").unwrap();
+    std::fs::write(src.path().join("b.txt"), b"").unwrap();
+    let mut archive = vec![];
+    crate::recursive_archive(&mut archive, src.path()).unwrap();
+
+    let dest = tempfile::tempdir().unwrap();
+    let planned = extract_dry_run(&mut io::Cursor::new(&archive), dest.path()).unwrap();
+    assert_eq!(planned.len(), 4);
+    assert_eq!(std::fs::read_dir(dest.path()).unwrap().count(), 0);
+
+    extract(&mut io::Cursor::new(&archive), dest.path()).unwrap();
+    for (target, size) in planned {
+        let metadata = target.symlink_metadata().unwrap();
+        if metadata.is_file() {
+            assert_eq!(metadata.len(), size);
+        }
+    }
+}
+
+#[cfg(unix)]
+#[test]
+fn non_utf8_names_roundtrip() {
+    use std::{ffi::OsString, os::unix::ffi::OsStringExt};
+
+    let src = tempfile::tempdir().unwrap();
+    let name = OsString::from_vec(b"caf\xFFe.txt".to_vec());
+    std::fs::write(src.path().join(&name), b"not utf-8").unwrap();
+
+    let mut archiver = crate::Archiver::new(vec![])
+        .strip_prefix(src.path())
+        .build();
+    archiver.add_path(src.path()).unwrap();
+    let archive = archiver.finish().unwrap();
+    let entries = crate::list(&mut io::Cursor::new(&archive)).unwrap();
+    assert_eq!(entries[1].path.as_os_str(), name);
+
+    let dest = tempfile::tempdir().unwrap();
+    extract(&mut io::Cursor::new(&archive), dest.path()).unwrap();
+    assert_eq!(
+        std::fs::read(dest.path().join(&name)).unwrap(),
+        b"not utf-8"
+    );
+}
+
+#[cfg(unix)]
+#[test]
+fn extract_soft_link() {
+    let src = tempfile::tempdir().unwrap();
+    let dest = tempfile::tempdir().unwrap();
+    std::fs::write(src.path().join("a.txt"), b"link me").unwrap();
+    std::os::unix::fs::symlink("a.txt", src.path().join("link")).unwrap();
+
+    let mut archive = vec![];
+    crate::recursive_archive(&mut archive, src.path()).unwrap();
+    extract(&mut io::Cursor::new(archive), dest.path()).unwrap();
+
+    let restored = target_path(dest.path(), src.path().as_os_str().as_bytes()).unwrap();
+    let link = restored.join("link");
+    assert!(link.symlink_metadata().unwrap().file_type().is_symlink());
+    assert_eq!(std::fs::read_link(&link).unwrap(), Path::new("a.txt"));
+    assert_eq!(std::fs::read(&link).unwrap(), b"link me");
+}
+
+#[cfg(unix)]
+#[test]
+fn extract_perms() {
+    let src = tempfile::tempdir().unwrap();
+    let dest = tempfile::tempdir().unwrap();
+    let script = src.path().join("run.sh");
+    std::fs::write(&script, b"#!/bin/sh").unwrap();
+    std::fs::set_permissions(&script, std::fs::Permissions::from_mode(0o755)).unwrap();
+
+    let mut archive = vec![];
+    crate::recursive_archive(&mut archive, src.path()).unwrap();
+    extract(&mut io::Cursor::new(archive), dest.path()).unwrap();
+
+    let restored = target_path(dest.path(), script.as_os_str().as_bytes()).unwrap();
+    let mode = restored.metadata().unwrap().permissions().mode();
+    assert_eq!(mode & 0o7777, 0o755);
+}
+
+#[cfg(unix)]
+#[test]
+fn archived_owner_is_current_user() {
+    let src = tempfile::tempdir().unwrap();
+    let file = src.path().join("mine.txt");
+    std::fs::write(&file, b"owned").unwrap();
+
+    let mut archive = vec![];
+    crate::append_to_archive(&mut archive, &file).unwrap();
+    let header = crate::read_meta("Header", &mut io::Cursor::new(archive)).unwrap();
+
+    let (uid, gid) = unsafe { (libc::geteuid(), libc::getegid()) };
+    assert_eq!(header.owner, uid as u16);
+    assert_eq!(header.group, gid as u16);
+}
+
+#[cfg(unix)]
+#[test]
+fn extract_preserving_owner() {
+    use std::os::unix::fs::MetadataExt;
+
+    let src = tempfile::tempdir().unwrap();
+    let dest = tempfile::tempdir().unwrap();
+    std::fs::write(src.path().join("mine.txt"), b"owned").unwrap();
+
+    let mut archive = vec![];
+    crate::recursive_archive(&mut archive, src.path()).unwrap();
+    let options = ExtractOptions {
+        preserve_owner: true,
+        ..Default::default()
+    };
+    extract_with_options(&mut io::Cursor::new(archive), dest.path(), &options).unwrap();
+
+    let restored = target_path(dest.path(), src.path().as_os_str().as_bytes()).unwrap();
+    let uid = unsafe { libc::geteuid() };
+    assert_eq!(restored.join("mine.txt").metadata().unwrap().uid(), uid);
+}
+
+#[cfg(unix)]
+#[test]
+fn extract_mapping_owner() {
+    use std::os::unix::fs::MetadataExt;
+
+    let src = tempfile::tempdir().unwrap();
+    let file = src.path().join("theirs.txt");
+    std::fs::write(&file, b"owned elsewhere").unwrap();
+    let mut archive = vec![];
+    crate::append_to_archive(&mut archive, &file).unwrap();
+
+    // as archived on a machine where the file belongs to uid and gid 12345.
+    let path_len = Metadata::from_bytes(&archive).unwrap().path_len as usize;
+    let path = archive[Metadata::SIZE..Metadata::SIZE + path_len].to_vec();
+    let mut header = Metadata::from_bytes(&archive).unwrap();
+    (header.owner, header.group) = (12345, 12345);
+    archive[..Metadata::SIZE].copy_from_slice(&header.header(&path, &[]).encode());
+    let footer_start = archive.len() - Metadata::SIZE;
+    let mut footer = Metadata::from_bytes(&archive[footer_start..]).unwrap();
+    (footer.owner, footer.group) = (12345, 12345);
+    archive[footer_start..].copy_from_slice(&footer.footer(footer.body_checksum).to_bytes());
+
+    let (uid, gid) = platform::current_owner();
+    let options = ExtractOptions {
+        preserve_owner: true,
+        owner_map: OwnerMap::custom(move |owner, group| match (owner, group) {
+            (12345, 12345) => (uid, gid),
+            other => other,
+        }),
+        ..Default::default()
+    };
+    let dest = tempfile::tempdir().unwrap();
+    extract_with_options(&mut io::Cursor::new(&archive), dest.path(), &options).unwrap();
+    let restored = target_path(dest.path(), file.as_os_str().as_bytes()).unwrap();
+    let metadata = restored.metadata().unwrap();
+    assert_eq!((metadata.uid() as u16, metadata.gid() as u16), (uid, gid));
+
+    assert_eq!(OwnerMap::Preserve.map(12345, 12345), (12345, 12345));
+    assert_eq!(OwnerMap::CurrentUser.map(12345, 12345), (uid, gid));
+}
+
+#[cfg(unix)]
+#[test]
+fn special_permission_bits_roundtrip() {
+    let src = tempfile::tempdir().unwrap();
+    let dir = src.path().join("shared");
+    std::fs::create_dir(&dir).unwrap();
+    std::fs::create_dir(src.path().join("tmp")).unwrap();
+    std::fs::write(dir.join("tool"), b"#!/bin/sh").unwrap();
+    let set_mode = |path: &Path, mode| {
+        std::fs::set_permissions(path, std::fs::Permissions::from_mode(mode)).unwrap()
+    };
+    set_mode(&dir.join("tool"), 0o4755);
+    set_mode(&src.path().join("tmp"), 0o1777);
+    set_mode(&dir, 0o2755);
+
+    let mut archive = vec![];
+    crate::recursive_archive(&mut archive, src.path()).unwrap();
+    let perms = crate::list(&mut io::Cursor::new(&archive))
+        .unwrap()
+        .into_iter()
+        .map(|entry| (entry.path, entry.perms & 0o7777))
+        .collect::<std::collections::HashMap<_, _>>();
+    assert_eq!(perms[&dir], 0o2755);
+    assert_eq!(perms[&dir.join("tool")], 0o4755);
+
+    for preserve_owner in [false, true] {
+        let dest = tempfile::tempdir().unwrap();
+        let options = ExtractOptions {
+            preserve_owner,
+            ..Default::default()
+        };
+        extract_with_options(&mut io::Cursor::new(&archive), dest.path(), &options).unwrap();
+        let restored = target_path(dest.path(), src.path().as_os_str().as_bytes()).unwrap();
+        let mode = |path: &str| {
+            let metadata = restored.join(path).symlink_metadata().unwrap();
+            metadata.permissions().mode() & 0o7777
+        };
+        assert_eq!(mode("shared"), 0o2755);
+        assert_eq!(mode("shared/tool"), 0o4755);
+        assert_eq!(mode("tmp"), 0o1777);
+    }
+}
+
+#[cfg(unix)]
+#[test]
+fn extract_detects_corrupt_body() {
+    let src = tempfile::tempdir().unwrap();
+    let dest = tempfile::tempdir().unwrap();
+    let file = src.path().join("data.bin");
+    std::fs::write(&file, b"precious contents").unwrap();
+
+    let mut archive = vec![];
+    crate::append_to_archive(&mut archive, &file).unwrap();
+    let body_start = crate::Metadata::SIZE + file.as_os_str().len();
+    archive[body_start + 3] ^= 0x01;
+
+    let err = extract(&mut io::Cursor::new(archive), dest.path()).unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    let err = err.into_inner().unwrap().downcast::<DecodeError>().unwrap();
+    assert_eq!(
+        *err,
+        DecodeError::Checksum {
+            offset: Some(body_start as u64)
+        }
+    );
+    // neither the corrupt contents nor the file they were written to are kept.
+    let restored = target_path(dest.path(), file.as_os_str().as_bytes()).unwrap();
+    assert!(!restored.exists());
+    assert_eq!(
+        std::fs::read_dir(restored.parent().unwrap())
+            .unwrap()
+            .count(),
+        0
+    );
+}
+
+#[test]
+fn extract_overwrite_policies() {
+    let src = tempfile::tempdir().unwrap();
+    std::fs::write(src.path().join("a.txt"), b"archived").unwrap();
+    std::fs::write(src.path().join("b.txt"), b"archived").unwrap();
+    let mut archive = vec![];
+    crate::recursive_archive(&mut archive, src.path()).unwrap();
+
+    let extract_over = |policy| {
+        let dest = tempfile::tempdir().unwrap();
+        let restored = target_path(dest.path(), &platform::path_to_bytes(src.path())).unwrap();
+        std::fs::create_dir_all(&restored).unwrap();
+        std::fs::write(restored.join("a.txt"), b"existing").unwrap();
+
+        let options = ExtractOptions {
+            overwrite: policy,
+            ..Default::default()
+        };
+        let result = extract_with_options(&mut io::Cursor::new(&archive), dest.path(), &options);
+        let read = |name| std::fs::read(restored.join(name)).ok();
+        (result, read("a.txt"), read("b.txt"))
+    };
+
+    let (result, a, b) = extract_over(OverwritePolicy::Skip);
+    result.unwrap();
+    assert_eq!(a.unwrap(), b"existing");
+    assert_eq!(b.unwrap(), b"archived");
+
+    let (result, a, b) = extract_over(OverwritePolicy::Overwrite);
+    result.unwrap();
+    assert_eq!(a.unwrap(), b"archived");
+    assert_eq!(b.unwrap(), b"archived");
+
+    let (result, a, _) = extract_over(OverwritePolicy::Error);
+    assert_eq!(result.unwrap_err().kind(), io::ErrorKind::AlreadyExists);
+    assert_eq!(a.unwrap(), b"existing");
+}
+
+#[cfg(feature = "encrypt")]
+#[test]
+fn extract_encrypted() {
+    let src = tempfile::tempdir().unwrap();
+    std::fs::write(src.path().join("secret.txt"), b"attack at dawn").unwrap();
+    let key = [7u8; 32];
+
+    let mut archive = vec![];
+    crate::recursive_archive_encrypted(&mut archive, src.path(), &key).unwrap();
+    assert!(!archive.windows(6).any(|w| w == b"attack"));
+
+    let options = ExtractOptions {
+        key: Some(Key(key)),
+        ..Default::default()
+    };
+    let dest = tempfile::tempdir().unwrap();
+    extract_with_options(&mut io::Cursor::new(&archive), dest.path(), &options).unwrap();
+    let restored = target_path(dest.path(), &platform::path_to_bytes(src.path())).unwrap();
+    assert_eq!(
+        std::fs::read(restored.join("secret.txt")).unwrap(),
+        b"attack at dawn"
+    );
+
+    let dest = tempfile::tempdir().unwrap();
+    let err = extract(&mut io::Cursor::new(&archive), dest.path()).unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+
+    let wrong = ExtractOptions {
+        key: Some(Key([8u8; 32])),
+        ..Default::default()
+    };
+    let err = extract_with_options(&mut io::Cursor::new(&archive), dest.path(), &wrong);
+    assert_eq!(err.unwrap_err().kind(), io::ErrorKind::InvalidData);
+}
+
+#[cfg(feature = "encrypt")]
+#[test]
+fn extract_detects_tampering() {
+    let src = tempfile::tempdir().unwrap();
+    std::fs::write(src.path().join("secret.txt"), b"attack at dawn").unwrap();
+    let key = [7u8; 32];
+
+    let mut archive = vec![];
+    crate::recursive_archive_encrypted(&mut archive, src.path(), &key).unwrap();
+    let trailer = crate::trailer::find(&mut io::Cursor::new(&archive)).unwrap();
+    archive.truncate(trailer.unwrap() as usize);
+
+    // flip a ciphertext bit and fix up the body checksum, so only the tag can notice.
+    let footer_start = archive.len() - Metadata::SIZE;
+    archive[footer_start - 20] ^= 0x01;
+    let mut footer = Metadata::from_bytes(&archive[footer_start..]).unwrap();
+    let body_start = footer_start - footer.file_size as usize;
+    footer.body_checksum = crc32::digest(&archive[body_start..footer_start]);
+    footer.set_checksum();
+    archive[footer_start..].copy_from_slice(&footer.to_bytes());
+
+    let options = ExtractOptions {
+        key: Some(Key(key)),
+        ..Default::default()
+    };
+    let dest = tempfile::tempdir().unwrap();
+    let err = extract_with_options(&mut io::Cursor::new(&archive), dest.path(), &options);
+    assert!(err.unwrap_err().to_string().contains("authentication"));
+}
+
+#[cfg(feature = "encrypt")]
+#[test]
+fn extract_encrypted_across_chunks() {
+    let src = tempfile::tempdir().unwrap();
+    // spans a few of the 64 KiB chunks bodies are sealed in, the last one partial.
+    let contents: Vec<u8> = (0..200_000u32).map(|i| (i % 251) as u8).collect();
+    std::fs::write(src.path().join("big.bin"), &contents).unwrap();
+    std::fs::write(src.path().join("empty.bin"), b"").unwrap();
+    let key = [7u8; 32];
+
+    let mut archive = vec![];
+    crate::recursive_archive_encrypted(&mut archive, src.path(), &key).unwrap();
+
+    let options = ExtractOptions {
+        key: Some(Key(key)),
+        ..Default::default()
+    };
+    let dest = tempfile::tempdir().unwrap();
+    extract_with_options(&mut io::Cursor::new(&archive), dest.path(), &options).unwrap();
+    let restored = target_path(dest.path(), &platform::path_to_bytes(src.path())).unwrap();
+    assert_eq!(std::fs::read(restored.join("big.bin")).unwrap(), contents);
+    assert_eq!(std::fs::read(restored.join("empty.bin")).unwrap(), b"");
+}
+
+#[cfg(feature = "encrypt")]
+#[test]
+fn extract_rejects_body_of_another_entry() {
+    let src = tempfile::tempdir().unwrap();
+    for name in ["a.txt", "b.txt"] {
+        let path = src.path().join(name);
+        std::fs::write(&path, name.repeat(3)).unwrap();
+        let mtime = filetime::FileTime::from_unix_time(1_000_000_000, 0);
+        filetime::set_file_mtime(&path, mtime).unwrap();
+    }
+    let key = [7u8; 32];
+
+    let mut archive = vec![];
+    crate::recursive_archive_encrypted(&mut archive, src.path(), &key).unwrap();
+    let trailer = crate::trailer::find(&mut io::Cursor::new(&archive)).unwrap();
+    archive.truncate(trailer.unwrap() as usize);
+
+    // the footers only differ in their body checksum, so swapping bodies along
+    // with their footers leaves every checksum intact.
+    let body_of = |name: &str| {
+        let path = platform::path_to_bytes(&src.path().join(name));
+        let start = archive.windows(path.len()).position(|w| w == path).unwrap() + path.len();
+        let footer = Metadata::from_bytes(&archive[start - path.len() - Metadata::SIZE..]).unwrap();
+        start..start + footer.file_size as usize + Metadata::SIZE
+    };
+    let (a, b) = (body_of("a.txt"), body_of("b.txt"));
+    let a_bytes = archive[a.clone()].to_vec();
+    let b_bytes = archive[b.clone()].to_vec();
+    archive[a].copy_from_slice(&b_bytes);
+    archive[b].copy_from_slice(&a_bytes);
+    crate::verify(&mut io::Cursor::new(&archive)).unwrap();
+
+    let options = ExtractOptions {
+        key: Some(Key(key)),
+        ..Default::default()
+    };
+    let dest = tempfile::tempdir().unwrap();
+    let err = extract_with_options(&mut io::Cursor::new(&archive), dest.path(), &options);
+    assert!(err.unwrap_err().to_string().contains("authentication"));
+}
+
+#[cfg(feature = "lz4")]
+#[test]
+fn extract_lz4_compressed() {
+    let src = tempfile::tempdir().unwrap();
+    let text = "all work and no play makes jack a dull boy\n".repeat(100);
+    std::fs::write(src.path().join("story.txt"), &text).unwrap();
+
+    let mut archiver = crate::Archiver::new(vec![])
+        .strip_prefix(src.path())
+        .compress_lz4(true)
+        .build();
+    archiver.add_path(src.path()).unwrap();
+    let archive = archiver.finish().unwrap();
+
+    let file = crate::Entries::new(io::Cursor::new(&archive))
+        .map(Result::unwrap)
+        .find(|e| e.path().ends_with("story.txt"))
+        .unwrap();
+    assert!(file.size() < text.len() as u64);
+    assert!(file.meta.flags.contains(flags::COMPRESSED | flags::LZ4));
+
+    let dest = tempfile::tempdir().unwrap();
+    extract(&mut io::Cursor::new(&archive), dest.path()).unwrap();
+    assert_eq!(
+        std::fs::read_to_string(dest.path().join("story.txt")).unwrap(),
+        text
+    );
+    let mut restored = vec![];
+    crate::extract_one(&mut io::Cursor::new(&archive), "story.txt", &mut restored).unwrap();
+    assert_eq!(restored, text.as_bytes());
+}
+
+#[cfg(feature = "flate")]
+#[test]
+fn extract_compressed() {
+    let src = tempfile::tempdir().unwrap();
+    let dest = tempfile::tempdir().unwrap();
+    let text = "all work and no play makes jack a dull boy\n".repeat(100);
+    std::fs::write(src.path().join("story.txt"), &text).unwrap();
+
+    let mut plain = vec![];
+    crate::recursive_archive(&mut plain, src.path()).unwrap();
+    let mut archive = vec![];
+    crate::recursive_archive_compressed(&mut archive, src.path()).unwrap();
+    assert!(archive.len() < plain.len());
+
+    let file = crate::Entries::new(io::Cursor::new(&archive))
+        .map(Result::unwrap)
+        .find(|e| e.path().ends_with("story.txt"))
+        .unwrap();
+    assert!(file.size() < text.len() as u64);
+
+    extract(&mut io::Cursor::new(archive), dest.path()).unwrap();
+    let restored = target_path(dest.path(), &platform::path_to_bytes(src.path())).unwrap();
+    assert_eq!(
+        std::fs::read_to_string(restored.join("story.txt")).unwrap(),
+        text
+    );
+}
+
+#[cfg(feature = "flate")]
+#[test]
+fn compression_levels() {
+    let mut text = String::new();
+    let mut state = 0x2545_f491u32;
+    for _ in 0..5000 {
+        state = state.wrapping_mul(1_103_515_245).wrapping_add(12345);
+        text.push_str(["bitumen ", "tar ", "pitch ", "asphalt\n"][(state >> 30) as usize]);
+    }
+    let archive = |level| {
+        let mut archiver = crate::Archiver::new(vec![])
+            .compress(true)
+            .compression_level(level)
+            .build();
+        archiver
+            .add_reader("text.txt", &mut text.as_bytes(), text.len() as u64, 0)
+            .unwrap();
+        archiver.finish().unwrap()
+    };
+
+    let stored = archive(0);
+    assert!(stored.len() > text.len());
+    let (fast, best) = (archive(1), archive(9));
+    assert!(best.len() <= fast.len());
+    assert!(fast.len() < stored.len());
+    assert_eq!(archive(42), best);
+
+    for archive in [stored, best] {
+        let mut restored = vec![];
+        assert!(extract_one(&mut io::Cursor::new(&archive), "text.txt", &mut restored).unwrap());
+        assert_eq!(restored, text.as_bytes());
+    }
+}
+
+#[test]
+fn extract_empty_directories() {
+    let src = tempfile::tempdir().unwrap();
+    let dest = tempfile::tempdir().unwrap();
+    std::fs::create_dir_all(src.path().join("a/b/empty")).unwrap();
+    std::fs::create_dir(src.path().join("also_empty")).unwrap();
+
+    let mut archive = vec![];
+    crate::recursive_archive(&mut archive, src.path()).unwrap();
+    let dirs = crate::list(&mut io::Cursor::new(&archive)).unwrap();
+    assert_eq!(dirs.len(), 5);
+    assert!(dirs.iter().all(|e| e.kind == crate::EntryKind::Directory));
+
+    extract(&mut io::Cursor::new(archive), dest.path()).unwrap();
+    let restored = target_path(dest.path(), &platform::path_to_bytes(src.path())).unwrap();
+    assert!(restored.join("a/b/empty").is_dir());
+    assert!(restored.join("also_empty").is_dir());
+    assert_eq!(restored.join("a/b/empty").read_dir().unwrap().count(), 0);
+}
+
+#[test]
+fn extract_restores_mtime() {
+    let src = tempfile::tempdir().unwrap();
+    let dest = tempfile::tempdir().unwrap();
+    let dir_mtime = filetime::FileTime::from_unix_time(1_500_000_000, 0);
+    let file_mtime = filetime::FileTime::from_unix_time(1_600_000_000, 0);
+
+    std::fs::create_dir(src.path().join("sub")).unwrap();
+    std::fs::write(src.path().join("sub/old.txt"), b"old").unwrap();
+    filetime::set_file_mtime(src.path().join("sub/old.txt"), file_mtime).unwrap();
+    filetime::set_file_mtime(src.path().join("sub"), dir_mtime).unwrap();
+
+    let mut archive = vec![];
+    crate::recursive_archive(&mut archive, src.path()).unwrap();
+    extract(&mut io::Cursor::new(archive), dest.path()).unwrap();
+
+    let restored = target_path(dest.path(), &platform::path_to_bytes(src.path())).unwrap();
+    let mtime_of =
+        |path: PathBuf| filetime::FileTime::from_last_modification_time(&path.metadata().unwrap());
+    let file_diff =
+        mtime_of(restored.join("sub/old.txt")).unix_seconds() - file_mtime.unix_seconds();
+    let dir_diff = mtime_of(restored.join("sub")).unix_seconds() - dir_mtime.unix_seconds();
+    assert!(file_diff.abs() <= 1);
+    assert!(dir_diff.abs() <= 1);
+}
+
+#[cfg(unix)]
+#[test]
+fn target_path_stays_below_dest() {
+    let dest = Path::new("/tmp/dest");
+    assert_eq!(
+        target_path(dest, b"/etc/passwd").unwrap(),
+        Path::new("/tmp/dest/etc/passwd")
+    );
+    assert!(target_path(dest, b"a/../../escape").is_err());
+}
+
+#[test]
+fn extract_contains_archived_paths() {
+    let mut escaping = vec![];
+    crate::append_reader(&mut escaping, "../escape", &mut &b"evil"[..], 4, 0).unwrap();
+    let dest = tempfile::tempdir().unwrap();
+    let inner = dest.path().join("inner");
+    let err = extract(&mut io::Cursor::new(&escaping), &inner).unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    assert!(!dest.path().join("escape").exists());
+
+    let mut absolute = vec![];
+    crate::append_reader(&mut absolute, "/absolute", &mut &b"abs"[..], 3, 0).unwrap();
+    extract(&mut io::Cursor::new(&absolute), &inner).unwrap();
+    assert_eq!(std::fs::read(inner.join("absolute")).unwrap(), b"abs");
+}
+
+#[cfg(unix)]
+#[test]
+fn extract_only_selected_kinds() {
+    let src = tempfile::tempdir().unwrap();
+    std::fs::create_dir(src.path().join("dir")).unwrap();
+    std::fs::write(src.path().join("dir/a.txt"), b"abc").unwrap();
+    std::os::unix::fs::symlink("/etc/passwd", src.path().join("link")).unwrap();
+    let mut archiver = crate::Archiver::new(vec![])
+        .strip_prefix(src.path())
+        .build();
+    archiver.add_path(src.path()).unwrap();
+    let archive = archiver.finish().unwrap();
+
+    let dest = tempfile::tempdir().unwrap();
+    let options = ExtractOptions {
+        kinds: Some([EntryKind::File, EntryKind::Directory].into()),
+        ..Default::default()
+    };
+    extract_with_options(&mut io::Cursor::new(&archive), dest.path(), &options).unwrap();
+    assert_eq!(
+        std::fs::read(dest.path().join("dir/a.txt")).unwrap(),
+        b"abc"
+    );
+    assert!(dest.path().join("link").symlink_metadata().is_err());
+}
+
+#[cfg(unix)]
+#[test]
+fn extract_rejects_writing_through_symlinks() {
+    let src = tempfile::tempdir().unwrap();
+    let outside = tempfile::tempdir().unwrap();
+    std::os::unix::fs::symlink(outside.path(), src.path().join("link")).unwrap();
+
+    let mut archiver = crate::Archiver::new(vec![])
+        .strip_prefix(src.path())
+        .build();
+    archiver.add_path(&src.path().join("link")).unwrap();
+    let mut archive = archiver.finish().unwrap();
+    crate::append_reader(&mut archive, "link/passwd", &mut &b"evil"[..], 4, 0).unwrap();
+
+    let dest = tempfile::tempdir().unwrap();
+    let err = extract(&mut io::Cursor::new(&archive), dest.path()).unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    assert!(!outside.path().join("passwd").exists());
+}
+
+#[cfg(unix)]
+#[test]
+fn extract_rejects_directory_over_symlink() {
+    let src = tempfile::tempdir().unwrap();
+    let outside = tempfile::tempdir().unwrap();
+    std::fs::set_permissions(outside.path(), std::fs::Permissions::from_mode(0o700)).unwrap();
+    std::os::unix::fs::symlink(outside.path(), src.path().join("d")).unwrap();
+
+    let mut archiver = crate::Archiver::new(vec![])
+        .strip_prefix(src.path())
+        .build();
+    archiver.add_path(&src.path().join("d")).unwrap();
+    let mut archive = archiver.finish().unwrap();
+    // a directory entry of the same name, opening it up for everyone.
+    let dir = Metadata::new(flags::DIR, 0, 1, 0o777, 0, 0, 0);
+    archive.extend_from_slice(&dir.header(b"d", &[]).encode());
+    archive.extend_from_slice(b"d");
+    archive.extend_from_slice(&dir.footer(crc32::digest(b"")).to_bytes());
+
+    let dest = tempfile::tempdir().unwrap();
+    assert!(extract(&mut io::Cursor::new(&archive), dest.path()).is_err());
+    let mode = std::fs::metadata(outside.path())
+        .unwrap()
+        .permissions()
+        .mode();
+    assert_eq!(mode & 0o777, 0o700);
+}
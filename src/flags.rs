@@ -1,3 +1,5 @@
+use crate::EntryKind;
+
 /// Indicates that the archived object is a physical file.
 pub const FILE: u32 = 0x0;
 
@@ -10,6 +12,213 @@ pub const SOFT_LINK: u32 = 0x2;
 /// Indicates that the archived object is a soft link
 pub const HARD_LINK: u32 = 0x3;
 
+/// Indicates that the archived object is a named pipe.
+pub const FIFO: u32 = 0x4;
+
+/// Indicates that the archived object is a character device,
+/// its device number stored as the body.
+pub const CHAR_DEV: u32 = 0x5;
+
+/// Indicates that the archived object is a block device,
+/// its device number stored as the body.
+pub const BLOCK_DEV: u32 = 0x6;
+
+/// Bits holding the kind of the object, one of the constants above.
+pub const KIND_MASK: u32 = 0x7;
+
 /// Indicates that the metadata is the header of the object.
 /// If this bit is unset this means it is the footer.
 pub const HEADER: u32 = 0x8;
+
+/// Indicates that the body of the object is compressed, with gzip unless [`LZ4`] is set.
+pub const COMPRESSED: u32 = 0x10;
+
+/// Indicates that a blob of extended attributes follows the path.
+pub const HAS_XATTR: u32 = 0x20;
+
+/// Indicates that the body of a file leaves out runs of zeros.
+pub const SPARSE: u32 = 0x40;
+
+/// Indicates that the body of a file is encrypted.
+pub const ENCRYPTED: u32 = 0x80;
+
+/// Indicates that the entry is the index of the archive rather than an archived object.
+pub const INDEX: u32 = 0x100;
+
+/// Indicates that the body of a file is a list of chunks, some referring to
+/// identical chunks stored earlier in the archive.
+pub const CHUNKED: u32 = 0x200;
+
+/// Together with [`COMPRESSED`], indicates that the body of a file is
+/// LZ4 block compressed, prefixed by its uncompressed size as a little-endian `u32`.
+pub const LZ4: u32 = 0x400;
+
+/// Indicates that the entry is the comment of the archive rather than an archived object,
+/// its body holding the UTF-8 text. It can only be the first entry.
+pub const COMMENT: u32 = 0x800;
+
+/// Bits holding the algorithm of the body checksum, CRC-32 if none is set.
+pub const CHECKSUM_MASK: u32 = 0x3000;
+
+/// Indicates that the body checksum is CRC-32C (Castagnoli) rather than CRC-32.
+pub const CRC32C: u32 = 0x1000;
+
+/// Indicates that the body checksum is xxHash32 with a seed of `0` rather than CRC-32.
+pub const XXHASH: u32 = 0x2000;
+
+/// Only set on footers, indicates that the file changed size while it was
+/// archived. Its body was cut off or padded with zeros to the size in the header,
+/// which was written before the change could be noticed. Extraction rejects such entries.
+pub const RESIZED: u32 = 0x4000;
+
+/// The flags of an archived object, wrapping the constants above.
+///
+/// ```
+/// # use bitumen::{flags::{self, Flags}, EntryKind};
+/// let flags = Flags::new(flags::FILE).with(flags::COMPRESSED);
+/// assert!(flags.compressed());
+/// assert_eq!(flags.with_header().kind(), Some(EntryKind::File));
+/// assert_eq!(Flags::new(0x7).kind(), None);
+/// ```
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub struct Flags(u32);
+
+impl Flags {
+    /// Wraps raw bits, as combined from the constants above.
+    pub const fn new(bits: u32) -> Self {
+        Self(bits)
+    }
+
+    /// The raw bits, as combined from the constants above.
+    pub const fn bits(self) -> u32 {
+        self.0
+    }
+
+    /// Whether all bits of `flag` are set.
+    pub const fn contains(self, flag: u32) -> bool {
+        self.0 & flag == flag
+    }
+
+    /// Whether any bit of `flags` is set.
+    pub const fn intersects(self, flags: u32) -> bool {
+        self.0 & flags != 0
+    }
+
+    /// These flags with the bits of `flag` set.
+    #[must_use]
+    pub const fn with(self, flag: u32) -> Self {
+        Self(self.0 | flag)
+    }
+
+    /// These flags with the bits of `flag` cleared.
+    #[must_use]
+    pub const fn without(self, flag: u32) -> Self {
+        Self(self.0 & !flag)
+    }
+
+    /// The kind bits, one of [`FILE`], [`DIR`], [`SOFT_LINK`], [`HARD_LINK`],
+    /// [`FIFO`], [`CHAR_DEV`] and [`BLOCK_DEV`] for valid flags.
+    pub const fn kind_bits(self) -> u32 {
+        self.0 & KIND_MASK
+    }
+
+    /// The kind of the object, `None` for the reserved kind bits.
+    pub fn kind(self) -> Option<EntryKind> {
+        EntryKind::try_from(self.0).ok()
+    }
+
+    /// The checksum bits, `0` for CRC-32, [`CRC32C`] or [`XXHASH`] for valid flags.
+    pub const fn checksum_bits(self) -> u32 {
+        self.0 & CHECKSUM_MASK
+    }
+
+    /// Whether the object is a physical file.
+    pub const fn is_file(self) -> bool {
+        self.kind_bits() == FILE
+    }
+
+    /// Whether the object is a directory.
+    pub const fn is_dir(self) -> bool {
+        self.kind_bits() == DIR
+    }
+
+    /// See [`HEADER`].
+    pub const fn is_header(self) -> bool {
+        self.intersects(HEADER)
+    }
+
+    /// These flags as written to a header.
+    #[must_use]
+    pub const fn with_header(self) -> Self {
+        self.with(HEADER)
+    }
+
+    /// These flags as written to a footer.
+    #[must_use]
+    pub const fn without_header(self) -> Self {
+        self.without(HEADER)
+    }
+
+    /// See [`COMPRESSED`].
+    pub const fn compressed(self) -> bool {
+        self.intersects(COMPRESSED)
+    }
+
+    /// See [`LZ4`].
+    pub const fn lz4(self) -> bool {
+        self.intersects(LZ4)
+    }
+
+    /// See [`HAS_XATTR`].
+    pub const fn has_xattr(self) -> bool {
+        self.intersects(HAS_XATTR)
+    }
+
+    /// See [`SPARSE`].
+    pub const fn sparse(self) -> bool {
+        self.intersects(SPARSE)
+    }
+
+    /// See [`ENCRYPTED`].
+    pub const fn encrypted(self) -> bool {
+        self.intersects(ENCRYPTED)
+    }
+
+    /// See [`RESIZED`].
+    pub const fn resized(self) -> bool {
+        self.intersects(RESIZED)
+    }
+
+    /// See [`INDEX`].
+    pub const fn is_index(self) -> bool {
+        self.intersects(INDEX)
+    }
+
+    /// See [`CHUNKED`].
+    pub const fn chunked(self) -> bool {
+        self.intersects(CHUNKED)
+    }
+
+    /// See [`COMMENT`].
+    pub const fn is_comment(self) -> bool {
+        self.intersects(COMMENT)
+    }
+
+    /// Whether the entry describes the archive rather than an archived object,
+    /// i.e. is its index or comment.
+    pub const fn is_special(self) -> bool {
+        self.intersects(INDEX | COMMENT)
+    }
+}
+
+impl From<u32> for Flags {
+    fn from(bits: u32) -> Self {
+        Self(bits)
+    }
+}
+
+impl From<Flags> for u32 {
+    fn from(flags: Flags) -> Self {
+        flags.0
+    }
+}
@@ -0,0 +1,299 @@
+//! Optional directory of all entries, stored as the last entry of an archive
+//! and flagged with [`flags::INDEX`](crate::flags::INDEX).
+//!
+//! Its footer ends the archive, so the index can be found by reading
+//! the last [`Metadata::SIZE`] bytes before the trailer, if there is one. The body holds one record per entry:
+//!
+//! | bytes | content                             |
+//! |-------|-------------------------------------|
+//! | 8     | offset of the entry's header        |
+//! | 8     | size of the entry's body            |
+//! | 4     | flags of the entry                  |
+//! | 2     | path length, followed by the path   |
+//!
+//! all little-endian. Sequential readers skip the index like any other entry.
+
+use std::{
+    collections::HashMap,
+    io::{self, Read, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    check_footer, checksum, extract::extract_one_from, flags::Flags, read_header, read_meta,
+    read_path, DecodeError, EntryKind, Metadata,
+};
+
+/// Location and description of an entry, as recorded in the index.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct IndexEntry {
+    pub path: PathBuf,
+    /// Offset of the entry's header from the start of the archive.
+    pub offset: u64,
+    /// Size of the archived body in bytes.
+    pub size: u64,
+    pub kind: EntryKind,
+}
+
+/// Record of an entry about to be written, see [`encode`].
+pub struct Record {
+    pub path: Vec<u8>,
+    pub offset: u64,
+    pub size: u64,
+    pub flags: Flags,
+}
+
+pub fn encode(records: &[Record]) -> Vec<u8> {
+    let mut body = vec![];
+    for record in records {
+        body.extend_from_slice(&record.offset.to_le_bytes());
+        body.extend_from_slice(&record.size.to_le_bytes());
+        body.extend_from_slice(&record.flags.without_header().bits().to_le_bytes());
+        body.extend_from_slice(&(record.path.len() as u16).to_le_bytes());
+        body.extend_from_slice(&record.path);
+    }
+    body
+}
+
+fn decode(mut body: &[u8]) -> Option<Vec<IndexEntry>> {
+    fn take<'a>(body: &mut &'a [u8], n: usize) -> Option<&'a [u8]> {
+        if body.len() < n {
+            return None;
+        }
+        let (head, tail) = body.split_at(n);
+        *body = tail;
+        Some(head)
+    }
+
+    let mut entries = vec![];
+    while !body.is_empty() {
+        let offset = u64::from_le_bytes(take(&mut body, 8)?.try_into().ok()?);
+        let size = u64::from_le_bytes(take(&mut body, 8)?.try_into().ok()?);
+        let flags = u32::from_le_bytes(take(&mut body, 4)?.try_into().ok()?);
+        let path_len = u16::from_le_bytes(take(&mut body, 2)?.try_into().ok()?);
+        let path = take(&mut body, path_len as usize)?;
+
+        let meta = Metadata {
+            flags: Flags::new(flags),
+            ..Default::default()
+        };
+        meta.validate_kind().ok()?;
+        entries.push(IndexEntry {
+            path: crate::platform::bytes_to_path(path),
+            offset,
+            size,
+            kind: meta.kind(),
+        });
+    }
+    Some(entries)
+}
+
+/// An archive with a trailing index, allowing lookups without reading every entry.
+pub struct IndexedArchive<R> {
+    archive: R,
+    /// position of the archive's first byte in `archive`.
+    archive_start: u64,
+    entries: Vec<IndexEntry>,
+    by_path: HashMap<PathBuf, usize>,
+}
+
+/// Reads the index at the end of `archive`, which is positioned at its first byte.
+///
+/// Fails with [`DecodeError::Footer`] if the archive doesn't end with an index.
+pub fn open_indexed<R: Read + Seek>(mut archive: R) -> Result<IndexedArchive<R>, DecodeError> {
+    let io_err = |e: io::Error| {
+        log::error!("Failed to read index: {e:?}");
+        DecodeError::Crop { offset: None }
+    };
+
+    let archive_start = archive.stream_position().map_err(io_err)?;
+    let end = match crate::trailer::find(&mut archive).map_err(io_err)? {
+        Some(trailer_start) => trailer_start,
+        None => archive.seek(SeekFrom::End(0)).map_err(io_err)?,
+    };
+    let Some(footer_start) = end
+        .checked_sub(Metadata::SIZE as u64)
+        .filter(|&footer_start| footer_start >= archive_start)
+    else {
+        return Err(DecodeError::Footer {
+            offset: Some(archive_start),
+        });
+    };
+    archive
+        .seek(SeekFrom::Start(footer_start))
+        .map_err(io_err)?;
+    let at_footer = |e: DecodeError| e.at(footer_start);
+    let footer = read_meta("Index footer", &mut archive).map_err(at_footer)?;
+    if !footer.flags.is_index() {
+        log::error!("Archive doesn't end with an index");
+        return Err(at_footer(DecodeError::Footer { offset: None }));
+    }
+
+    let entry_len = Metadata::SIZE as u64 + footer.path_len as u64 + footer.file_size;
+    let Some(start) = footer_start
+        .checked_sub(entry_len)
+        .filter(|&start| start >= archive_start)
+    else {
+        return Err(at_footer(DecodeError::Footer { offset: None }));
+    };
+    archive.seek(SeekFrom::Start(start)).map_err(io_err)?;
+    let header = read_header(&mut archive).map_err(|e| e.mid_archive().at(start))?;
+    check_footer(&header, &footer).map_err(at_footer)?;
+    read_path(&header, &mut archive).map_err(|e| e.at(start))?;
+
+    let body_start = start + Metadata::SIZE as u64 + header.path_len as u64;
+    let mut body = vec![];
+    (&mut archive)
+        .take(header.file_size)
+        .read_to_end(&mut body)
+        .map_err(|e| io_err(e).at(body_start))?;
+    if checksum::digest(footer.flags, &body) != footer.body_checksum {
+        log::error!("Index checksum mismatch");
+        return Err(DecodeError::Checksum {
+            offset: Some(body_start),
+        });
+    }
+
+    let entries = decode(&body).ok_or_else(|| {
+        log::error!("Malformed index");
+        DecodeError::Footer {
+            offset: Some(body_start),
+        }
+    })?;
+    let by_path = entries
+        .iter()
+        .enumerate()
+        .map(|(i, entry)| (entry.path.clone(), i))
+        .collect();
+
+    Ok(IndexedArchive {
+        archive,
+        archive_start,
+        entries,
+        by_path,
+    })
+}
+
+impl<R: Read + Seek> IndexedArchive<R> {
+    /// All entries in archive order.
+    pub fn entries(&self) -> &[IndexEntry] {
+        &self.entries
+    }
+
+    /// Looks up the entry archived as `path`.
+    pub fn find(&self, path: impl AsRef<Path>) -> Option<&IndexEntry> {
+        self.by_path.get(path.as_ref()).map(|&i| &self.entries[i])
+    }
+
+    /// Writes the contents of the file archived as `path` to `out`,
+    /// seeking directly to its entry. Returns whether the file was found.
+    pub fn extract_file(
+        &mut self,
+        path: impl AsRef<Path>,
+        out: &mut impl Write,
+    ) -> io::Result<bool> {
+        let path = path.as_ref();
+        let Some(entry) = self.find(path) else {
+            return Ok(false);
+        };
+        if entry.kind != EntryKind::File {
+            return Ok(false);
+        }
+        let start = self.archive_start + entry.offset;
+        self.archive.seek(SeekFrom::Start(start))?;
+        extract_one_from(&mut self.archive, self.archive_start, path, out)
+    }
+
+    /// Returns the underlying archive.
+    pub fn into_inner(self) -> R {
+        self.archive
+    }
+}
+
+#[test]
+fn index_roundtrip() {
+    use crate::flags;
+
+    let records = [
+        Record {
+            path: b"a".to_vec(),
+            offset: 0,
+            size: 3,
+            flags: Flags::new(flags::DIR | flags::HEADER),
+        },
+        Record {
+            path: b"a/b.txt".to_vec(),
+            offset: 81,
+            size: 1 << 40,
+            flags: Flags::new(flags::FILE | flags::COMPRESSED),
+        },
+    ];
+    let entries = decode(&encode(&records)).unwrap();
+    assert_eq!(entries[0].kind, EntryKind::Directory);
+    assert_eq!(entries[1].path, Path::new("a/b.txt"));
+    assert_eq!(entries[1].offset, 81);
+    assert_eq!(entries[1].size, 1 << 40);
+    assert!(decode(&encode(&records)[..30]).is_none());
+}
+
+#[test]
+fn random_access_through_index() {
+    let dir = tempfile::tempdir().unwrap();
+    for i in 0..20 {
+        std::fs::write(
+            dir.path().join(format!("{i}.txt")),
+            i.to_string().repeat(i + 1),
+        )
+        .unwrap();
+    }
+
+    let mut archiver = crate::Archiver::new(vec![]).index(true).build();
+    archiver.add_path(dir.path()).unwrap();
+    let archive = archiver.finish().unwrap();
+
+    // sequential readers don't see the index.
+    let listed = crate::list(&mut io::Cursor::new(&archive)).unwrap();
+    assert_eq!(listed.len(), 21);
+    let report = crate::verify(&mut io::Cursor::new(&archive)).unwrap();
+    assert_eq!((report.ok, report.corrupt), (21, 0));
+    assert_eq!(
+        crate::read_stream(&mut archive.as_slice(), &mut crate::LogObserver),
+        Ok(())
+    );
+    let dest = tempfile::tempdir().unwrap();
+    crate::extract(&mut io::Cursor::new(&archive), dest.path()).unwrap();
+
+    let mut indexed = open_indexed(io::Cursor::new(&archive)).unwrap();
+    assert_eq!(indexed.entries().len(), 21);
+    for i in [13, 2, 19, 0] {
+        let path = dir.path().join(format!("{i}.txt"));
+        let contents = i.to_string().repeat(i + 1);
+        assert_eq!(indexed.find(&path).unwrap().size, contents.len() as u64);
+
+        let mut body = vec![];
+        assert!(indexed.extract_file(&path, &mut body).unwrap());
+        assert_eq!(body, contents.as_bytes());
+    }
+    assert!(!indexed.extract_file("missing", &mut vec![]).unwrap());
+
+    // offsets in the index are relative to the start of the archive.
+    let mut shifted = b"leading bytes".to_vec();
+    shifted.extend_from_slice(&archive);
+    let mut reader = io::Cursor::new(shifted);
+    reader.set_position(13);
+    let mut indexed = open_indexed(reader).unwrap();
+    let path = dir.path().join("7.txt");
+    let mut body = vec![];
+    assert!(indexed.extract_file(&path, &mut body).unwrap());
+    assert_eq!(body, b"77777777");
+
+    let mut plain = vec![];
+    crate::recursive_archive(&mut plain, dir.path()).unwrap();
+    let last_footer = (plain.len() - crate::trailer::LEN - Metadata::SIZE) as u64;
+    assert_eq!(
+        open_indexed(io::Cursor::new(plain)).err(),
+        Some(DecodeError::Footer {
+            offset: Some(last_footer)
+        })
+    );
+}
@@ -0,0 +1,213 @@
+use std::io::{self, Read, Seek, Write};
+
+use crate::{
+    check_footer, flags, platform, read_footer, read_header, read_path, xattrs, DecodeError,
+    EntryInfo, Metadata, MAX_PATH_LEN,
+};
+
+/// Writes the entries of `src` to `dst`, passing each through `f` on the way.
+///
+/// `f` drops an entry by returning `None`, otherwise its path, permissions and
+/// modification time are rewritten to those returned, the body is copied as it
+/// is. Changing the kind or size of an entry is an error, as is a deduplicated
+/// entry, as its chunks are found by their offset in `src`. Encrypted entries
+/// can only be kept or dropped, their path, permissions and modification time are
+/// authenticated along with the body. The comment is kept, the index and trailer
+/// dropped, archive the result again to get them.
+///
+/// Hard links store the path of their target, renaming it breaks them.
+pub fn map_archive<R: Read + Seek, W: Write>(
+    src: &mut R,
+    dst: &mut W,
+    mut f: impl FnMut(EntryInfo) -> Option<EntryInfo>,
+) -> io::Result<()> {
+    let mut first = true;
+    loop {
+        let start = src.stream_position()?;
+        let header = match read_header(src).map_err(|e| e.at(start)) {
+            Ok(header) => header,
+            Err(DecodeError::Exhausted) => break,
+            Err(e) if first => return Err(e.into()),
+            Err(e) => return Err(e.mid_archive().into()),
+        };
+        first = false;
+        let path = read_path(&header, src).map_err(|e| e.at(start))?;
+        let xattrs = xattrs::read(&header, &path, src).map_err(|e| e.at(start))?;
+
+        let mapped = if header.flags.is_index() {
+            None
+        } else if header.flags.is_comment() {
+            Some((header.clone(), path.clone()))
+        } else {
+            let info = EntryInfo::from_header(&header, platform::bytes_to_path(&path));
+            f(info.clone())
+                .map(|mapped| rewrite(&header, &info, mapped))
+                .transpose()?
+        };
+        if mapped.is_some() && header.flags.chunked() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "can't move deduplicated entry {} to another archive",
+                    String::from_utf8_lossy(&path)
+                ),
+            ));
+        }
+        if let Some((meta, path)) = &mapped {
+            let xattrs = match header.flags.has_xattr() {
+                true => xattrs::encode(&xattrs),
+                false => vec![],
+            };
+            dst.write_all(&meta.header(path, &xattrs).encode())?;
+            dst.write_all(path)?;
+            dst.write_all(&xattrs)?;
+        }
+
+        let mut body = src.take(header.file_size);
+        let copied = if mapped.is_some() {
+            io::copy(&mut body, dst)?
+        } else {
+            io::copy(&mut body, &mut io::sink())?
+        };
+        if copied != header.file_size {
+            log::error!("File contents cut off after {copied}B");
+            return Err(DecodeError::Crop { offset: None }.into());
+        }
+
+        let footer_start = src.stream_position()?;
+        let footer = read_footer(src).map_err(|e| e.at(footer_start))?;
+        check_footer(&header, &footer).map_err(|e| e.at(footer_start))?;
+        if let Some((meta, _)) = &mapped {
+            let mut copy = meta.footer(footer.body_checksum);
+            if footer.flags.resized() {
+                copy.flags = copy.flags.with(flags::RESIZED);
+                copy.set_checksum();
+            }
+            dst.write_all(&copy.to_bytes())?;
+        }
+    }
+    Ok(())
+}
+
+/// Metadata and stored path of the entry described by `header` and `info`, once `mapped`.
+fn rewrite(
+    header: &Metadata,
+    info: &EntryInfo,
+    mapped: EntryInfo,
+) -> io::Result<(Metadata, Vec<u8>)> {
+    if mapped.kind != info.kind || mapped.size != info.size {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!(
+                "can't change kind or size of {}, only its body is copied",
+                info.path.display()
+            ),
+        ));
+    }
+    let path = platform::path_to_bytes(&mapped.path);
+    if path.len() > MAX_PATH_LEN {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("path too long ({}B): {}", path.len(), mapped.path.display()),
+        ));
+    }
+    if header.flags.encrypted() && mapped != *info {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!(
+                "can't change encrypted entry {}, its metadata is authenticated",
+                info.path.display()
+            ),
+        ));
+    }
+    let mut meta = header.clone();
+    meta.path_len = path.len() as u16;
+    meta.perms = mapped.perms;
+    meta.modified_at = mapped.modified_at_secs();
+    Ok((meta, path))
+}
+
+#[test]
+fn map_archive_renames_entries() {
+    use std::path::{Path, PathBuf};
+
+    let mut archive = vec![];
+    crate::append_reader(&mut archive, "a.txt", &mut &b"abc"[..], 3, 0).unwrap();
+    crate::append_reader(&mut archive, "dir/b.txt", &mut &b"defg"[..], 4, 0).unwrap();
+    crate::append_reader(&mut archive, "drop.txt", &mut &b"gone"[..], 4, 0).unwrap();
+
+    let mut renamed = vec![];
+    map_archive(&mut io::Cursor::new(&archive), &mut renamed, |mut entry| {
+        if entry.path == Path::new("drop.txt") {
+            return None;
+        }
+        entry.path = Path::new("renamed").join(&entry.path);
+        entry.set_modified_at_secs(42);
+        Some(entry)
+    })
+    .unwrap();
+
+    let entries = crate::list(&mut io::Cursor::new(&renamed)).unwrap();
+    let paths = entries.iter().map(|e| e.path.clone()).collect::<Vec<_>>();
+    assert_eq!(
+        paths,
+        [PathBuf::from("renamed/a.txt"), "renamed/dir/b.txt".into()]
+    );
+    assert!(entries.iter().all(|e| e.modified_at_secs() == 42));
+    let report = crate::verify(&mut io::Cursor::new(&renamed)).unwrap();
+    assert!(report.corrupt_paths.is_empty());
+
+    let dest = tempfile::tempdir().unwrap();
+    crate::extract(&mut io::Cursor::new(&renamed), dest.path()).unwrap();
+    let restored = std::fs::read(dest.path().join("renamed/dir/b.txt")).unwrap();
+    assert_eq!(restored, b"defg");
+
+    let resized = map_archive(&mut io::Cursor::new(&archive), &mut vec![], |mut entry| {
+        entry.size += 1;
+        Some(entry)
+    });
+    assert_eq!(resized.unwrap_err().kind(), io::ErrorKind::InvalidInput);
+}
+
+#[cfg(feature = "encrypt")]
+#[test]
+fn map_archive_keeps_encrypted_entries_unchanged() {
+    let src = tempfile::tempdir().unwrap();
+    std::fs::write(src.path().join("secret.txt"), b"attack at dawn").unwrap();
+    let mut archive = vec![];
+    crate::recursive_archive_encrypted(&mut archive, src.path(), &[7u8; 32]).unwrap();
+
+    let mut copy = vec![];
+    map_archive(&mut io::Cursor::new(&archive), &mut copy, Some).unwrap();
+    // the trailer is dropped along with the index.
+    assert_eq!(copy, archive[..archive.len() - crate::trailer::LEN]);
+
+    let err = map_archive(&mut io::Cursor::new(&archive), &mut vec![], |mut entry| {
+        entry.set_modified_at_secs(entry.modified_at_secs() + 1);
+        Some(entry)
+    })
+    .unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+}
+
+#[test]
+fn map_archive_keeps_resized_mark() {
+    let mut archive = vec![];
+    let size = crate::archive::MAX_BUFFERED + 1;
+    crate::append_reader(&mut archive, "short.bin", &mut &[1u8; 100][..], size, 0).unwrap();
+
+    let mut mapped = vec![];
+    map_archive(&mut io::Cursor::new(&archive), &mut mapped, |mut entry| {
+        entry.path = "renamed.bin".into();
+        Some(entry)
+    })
+    .unwrap();
+    let report = crate::verify(&mut io::Cursor::new(&mapped)).unwrap();
+    assert_eq!(report.resized_paths, ["renamed.bin"]);
+    let extracted = crate::extract_one(
+        &mut io::Cursor::new(&mapped),
+        "renamed.bin",
+        &mut io::sink(),
+    );
+    assert!(matches!(extracted, Err(DecodeError::Resized { .. })));
+}
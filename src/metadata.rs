@@ -0,0 +1,784 @@
+//! Encoding of the metadata surrounding every archived object,
+//! which only depends on `core`.
+
+use core::fmt;
+
+use crate::{
+    crc32,
+    flags::{self, Flags},
+    ChecksumAlgorithm,
+};
+
+/// Randomly generated, every byte is unique
+pub(crate) const MAGIC: u32 = 0x2f_96_8b_6a;
+
+/// Version of the archive format written by this crate.
+///
+/// Version 2 extends the header checksum over the path, version 3 also over the
+/// extended attributes, see [`Metadata::header`].
+pub(crate) const FORMAT_VERSION: u16 = 3;
+
+/// Oldest format version that can still be read.
+pub(crate) const MIN_FORMAT_VERSION: u16 = 1;
+
+/// Longest path in bytes an entry can be archived under, matching `PATH_MAX` on Linux.
+///
+/// The `path_len` field could hold longer ones, but readers reject them
+/// to protect against corrupt lengths.
+pub const MAX_PATH_LEN: usize = 4096;
+
+/// Header and footer of an archived object.
+///
+/// On disk the fields are stored in declaration order without padding,
+/// every integer encoded as little-endian regardless of the host:
+///
+/// | offset | field           | type  |
+/// |--------|-----------------|-------|
+/// | 0      | `modified_at`   | `u64` |
+/// | 8      | `file_size`     | `u64` |
+/// | 16     | `path_len`      | `u16` |
+/// | 18     | `perms`         | `u16` |
+/// | 20     | `owner`         | `u16` |
+/// | 22     | `group`         | `u16` |
+/// | 24     | `magic`         | `u32` |
+/// | 28     | `flags`         | `u16` |
+/// | 30     | `version`       | `u16` |
+/// | 32     | `body_checksum` | `u32` |
+/// | 36     | `checksum`      | `u32` |
+///
+/// [`Metadata::decode`] and [`Metadata::verify_path`] check a header without `std`,
+/// e.g. to parse archives on embedded targets.
+#[derive(Clone, Default, Debug)]
+pub struct Metadata {
+    pub(crate) modified_at: u64,
+    pub(crate) file_size: u64,
+    pub(crate) path_len: u16,
+    pub(crate) perms: u16,
+    pub(crate) owner: u16,
+    pub(crate) group: u16,
+    pub(crate) magic: u32,
+    /// only the low 16 bits are stored.
+    pub(crate) flags: Flags,
+    /// format version, see [`FORMAT_VERSION`].
+    pub(crate) version: u16,
+    /// checksum of the file body.
+    /// Only known once the body is written, so the header leaves it at `0`.
+    pub(crate) body_checksum: u32,
+    /// metadata checksum
+    pub(crate) checksum: u32,
+}
+
+// writing and reading archives is left to `std`.
+#[cfg_attr(not(feature = "std"), allow(dead_code))]
+impl Metadata {
+    /// Size of the encoded metadata in bytes.
+    pub const SIZE: usize = 40;
+
+    /// Offset of the `magic` field.
+    pub(crate) const MAGIC_OFFSET: usize = 24;
+
+    /// Offset of the `checksum` field, which covers all bytes before it.
+    const CHECKSUM_OFFSET: usize = 36;
+
+    /// Describes an object, with `magic`, `version` and `checksum` filled in.
+    pub(crate) fn new(
+        flags: u32,
+        file_size: u64,
+        path_len: u16,
+        perms: u16,
+        owner: u16,
+        group: u16,
+        modified_at: u64,
+    ) -> Self {
+        let mut meta = Self {
+            modified_at,
+            file_size,
+            path_len,
+            perms,
+            owner,
+            group,
+            magic: MAGIC,
+            flags: Flags::new(flags),
+            version: FORMAT_VERSION,
+            body_checksum: 0,
+            checksum: 0,
+        };
+        meta.set_checksum();
+        meta
+    }
+
+    /// The metadata written before `path` and the encoded extended attributes
+    /// `xattrs`, its checksum also covering both.
+    pub(crate) fn header(&self, path: &[u8], xattrs: &[u8]) -> Self {
+        let mut header = self.clone();
+        header.flags = header.flags.with_header();
+        header.body_checksum = 0;
+        header.checksum = header.compute_header_checksum(path, xattrs);
+        header
+    }
+
+    /// The metadata written after the body, once its checksum is known.
+    pub(crate) fn footer(&self, body_checksum: u32) -> Self {
+        let mut footer = self.clone();
+        footer.flags = footer.flags.without_header();
+        footer.body_checksum = body_checksum;
+        footer.set_checksum();
+        footer
+    }
+
+    /// Decodes and validates metadata read from an archive.
+    ///
+    /// The checksum of a header also covers its path and extended attributes, so it
+    /// is only checked by [`verify_path`](Self::verify_path) and [`verify_xattrs`](Self::verify_xattrs).
+    pub fn decode(bytes: &[u8; Self::SIZE]) -> Result<Self, DecodeError> {
+        let meta = Self::from_bytes(bytes).ok_or(DecodeError::Header { offset: None })?;
+        if meta.magic != MAGIC {
+            return Err(DecodeError::NotAnArchive { offset: None });
+        }
+        if !meta.is_header() && meta.checksum != meta.compute_checksum() {
+            return Err(DecodeError::Checksum { offset: None });
+        }
+        if !(MIN_FORMAT_VERSION..=FORMAT_VERSION).contains(&meta.version) {
+            log::error!("Unknown format version {}", meta.version);
+            return Err(DecodeError::Version { offset: None });
+        }
+        meta.validate_kind()?;
+        if ChecksumAlgorithm::from_flags(meta.flags).is_none() {
+            log::error!(
+                "Unknown checksum algorithm {:#x}",
+                meta.flags.checksum_bits()
+            );
+            return Err(DecodeError::Header { offset: None });
+        }
+        Ok(meta)
+    }
+
+    /// Checks the `path` following a decoded header against the header checksum.
+    ///
+    /// If the checksum also covers extended attributes, it can only be checked
+    /// once they are read, by [`verify_xattrs`](Self::verify_xattrs).
+    pub fn verify_path(&self, path: &[u8]) -> Result<(), DecodeError> {
+        if path.len() > MAX_PATH_LEN || path.len() != self.path_len as usize {
+            return Err(DecodeError::Header { offset: None });
+        }
+        if !self.covers_xattrs() && self.checksum != self.compute_header_checksum(path, &[]) {
+            return Err(DecodeError::Checksum { offset: None });
+        }
+        Ok(())
+    }
+
+    /// Checks the `path` and the encoded extended attributes `xattrs` following
+    /// a decoded header against the header checksum, if it covers them.
+    pub fn verify_xattrs(&self, path: &[u8], xattrs: &[u8]) -> Result<(), DecodeError> {
+        if self.covers_xattrs() && self.checksum != self.compute_header_checksum(path, xattrs) {
+            return Err(DecodeError::Checksum { offset: None });
+        }
+        Ok(())
+    }
+
+    /// Whether the header checksum covers extended attributes following the path.
+    fn covers_xattrs(&self) -> bool {
+        self.version >= 3 && self.flags.has_xattr()
+    }
+
+    /// Whether this is the header preceding the path rather than the footer.
+    pub fn is_header(&self) -> bool {
+        self.flags.is_header()
+    }
+
+    /// All flags, see the [`flags`] module.
+    pub fn flags(&self) -> u32 {
+        self.flags.bits()
+    }
+
+    /// All flags, wrapped for inspecting them.
+    pub fn flag_set(&self) -> Flags {
+        self.flags
+    }
+
+    /// Size of the archived body in bytes.
+    pub fn file_size(&self) -> u64 {
+        self.file_size
+    }
+
+    /// Length of the path following the header.
+    pub fn path_len(&self) -> u16 {
+        self.path_len
+    }
+
+    pub fn perms(&self) -> u16 {
+        self.perms
+    }
+
+    pub fn owner(&self) -> u16 {
+        self.owner
+    }
+
+    pub fn group(&self) -> u16 {
+        self.group
+    }
+
+    /// Modification time in seconds since the unix epoch.
+    pub fn modified_at(&self) -> u64 {
+        self.modified_at
+    }
+
+    /// Checksum of the body, `0` in headers.
+    pub fn body_checksum(&self) -> u32 {
+        self.body_checksum
+    }
+
+    /// Bytes the entry takes up in the archive: header, path, body and footer.
+    /// Extended attributes aren't accounted for.
+    pub fn size_on_disk(&self) -> u64 {
+        2 * Self::SIZE as u64 + self.path_len as u64 + self.file_size
+    }
+
+    /// Whether `self` and `other` describe the same object, as a header and its footer do.
+    ///
+    /// Compares everything but the header and resized flags, the body checksum and the checksum.
+    pub fn matches(&self, other: &Metadata) -> bool {
+        self.file_size == other.file_size
+            && self.path_len == other.path_len
+            && self.perms == other.perms
+            && self.owner == other.owner
+            && self.group == other.group
+            && self.flags.without_header().without(flags::RESIZED)
+                == other.flags.without_header().without(flags::RESIZED)
+            && self.modified_at == other.modified_at
+    }
+
+    /// Rejects flags whose kind bits don't name a known kind.
+    pub(crate) fn validate_kind(&self) -> Result<(), DecodeError> {
+        EntryKind::try_from(self.flags.bits())
+            .map(|_| ())
+            .inspect_err(|_| log::error!("Unknown entry kind {}", self.flags.kind_bits()))
+    }
+
+    /// The kind of the archived object.
+    ///
+    /// Metadata is only handed out once its kind was validated, e.g. by [`decode`](Self::decode).
+    pub fn kind(&self) -> EntryKind {
+        self.flags
+            .kind()
+            .expect("the kind of decoded metadata is validated")
+    }
+
+    pub(crate) fn compute_checksum(&self) -> u32 {
+        let bytes = self.encode();
+        crc32::digest(&bytes[..Self::CHECKSUM_OFFSET])
+    }
+
+    /// Checksum of a header followed by `path` and the encoded extended attributes `xattrs`.
+    ///
+    /// Before version 2 the path wasn't covered, before version 3 the attributes.
+    pub(crate) fn compute_header_checksum(&self, path: &[u8], xattrs: &[u8]) -> u32 {
+        if self.version < 2 {
+            return self.compute_checksum();
+        }
+        let bytes = self.encode();
+        let mut crc = crc32::update(crc32::INIT, &bytes[..Self::CHECKSUM_OFFSET]);
+        crc = crc32::update(crc, path);
+        if self.version >= 3 {
+            crc = crc32::update(crc, xattrs);
+        }
+        crc32::finalize(crc)
+    }
+
+    pub(crate) fn set_checksum(&mut self) {
+        self.checksum = self.compute_checksum();
+        self.assert_checksum_valid();
+    }
+
+    /// Only possible for footers, as a header's checksum depends on its path.
+    pub(crate) fn assert_checksum_valid(&self) {
+        if !self.flags.is_header() {
+            assert_eq!(self.checksum, self.compute_checksum())
+        }
+    }
+
+    pub(crate) fn encode(&self) -> [u8; Self::SIZE] {
+        let mut bytes = [0u8; Self::SIZE];
+        bytes[0..8].copy_from_slice(&self.modified_at.to_le_bytes());
+        bytes[8..16].copy_from_slice(&self.file_size.to_le_bytes());
+        bytes[16..18].copy_from_slice(&self.path_len.to_le_bytes());
+        bytes[18..20].copy_from_slice(&self.perms.to_le_bytes());
+        bytes[20..22].copy_from_slice(&self.owner.to_le_bytes());
+        bytes[22..24].copy_from_slice(&self.group.to_le_bytes());
+        bytes[24..28].copy_from_slice(&self.magic.to_le_bytes());
+        debug_assert!(self.flags.bits() <= u16::MAX as u32, "flags exceed 16 bits");
+        bytes[28..30].copy_from_slice(&(self.flags.bits() as u16).to_le_bytes());
+        bytes[30..32].copy_from_slice(&self.version.to_le_bytes());
+        bytes[32..36].copy_from_slice(&self.body_checksum.to_le_bytes());
+        bytes[36..40].copy_from_slice(&self.checksum.to_le_bytes());
+        bytes
+    }
+
+    pub(crate) fn to_bytes(&self) -> [u8; Self::SIZE] {
+        self.assert_checksum_valid();
+        self.encode()
+    }
+
+    /// Encodes the metadata as stored in an archive, the reverse of [`decode`](Self::decode).
+    #[cfg(feature = "std")]
+    pub fn to_vec(&self) -> Vec<u8> {
+        self.to_bytes().to_vec()
+    }
+
+    pub(crate) fn from_bytes(bytes: &[u8]) -> Option<Metadata> {
+        let bytes: &[u8; Self::SIZE] = bytes.get(..Self::SIZE)?.try_into().ok()?;
+        let u16_at = |i: usize| u16::from_le_bytes([bytes[i], bytes[i + 1]]);
+        let u32_at = |i: usize| u32::from_le_bytes(bytes[i..i + 4].try_into().unwrap());
+        let u64_at = |i: usize| u64::from_le_bytes(bytes[i..i + 8].try_into().unwrap());
+
+        Some(Metadata {
+            modified_at: u64_at(0),
+            file_size: u64_at(8),
+            path_len: u16_at(16),
+            perms: u16_at(18),
+            owner: u16_at(20),
+            group: u16_at(22),
+            magic: u32_at(24),
+            flags: Flags::new(u16_at(28) as u32),
+            version: u16_at(30),
+            body_checksum: u32_at(32),
+            checksum: u32_at(36),
+        })
+    }
+}
+
+/// Decodes the header or footer at the start of `bytes` like [`Metadata::decode`],
+/// failing with [`DecodeError::Crop`] if fewer than [`Metadata::SIZE`] bytes are given.
+///
+/// The checksum of a header covers its path, so it's only checked if the path
+/// follows in `bytes`, as it does in an archive, see [`Metadata::verify_path`].
+impl TryFrom<&[u8]> for Metadata {
+    type Error = DecodeError;
+
+    fn try_from(bytes: &[u8]) -> Result<Self, Self::Error> {
+        let meta = bytes
+            .get(..Self::SIZE)
+            .ok_or(DecodeError::Crop { offset: None })?;
+        let meta = Self::decode(meta.try_into().unwrap())?;
+        if meta.is_header() {
+            let path = bytes.get(Self::SIZE..Self::SIZE + meta.path_len as usize);
+            if let Some(path) = path {
+                meta.verify_path(path)?;
+            }
+        }
+        Ok(meta)
+    }
+}
+
+#[test]
+/// Header is 40 bytes in size.
+fn header_size_test() {
+    assert_eq!(40, Metadata::SIZE);
+    assert_eq!(40, Metadata::default().encode().len());
+}
+
+#[test]
+fn checksum_excludes_checksum_field() {
+    let mut meta = Metadata {
+        file_size: 34343,
+        flags: Flags::new(23232),
+        ..Default::default()
+    };
+
+    let c1 = meta.compute_checksum();
+    meta.checksum = 0xAA_BB_AA_BB;
+    let c2 = meta.compute_checksum();
+
+    assert_eq!(c1, c2);
+}
+
+#[test]
+fn metadata_roundtrip() {
+    let mut meta = Metadata {
+        modified_at: 1_700_000_000,
+        file_size: 34343,
+        path_len: 12,
+        perms: 0o100644,
+        owner: 1000,
+        group: 100,
+        magic: MAGIC,
+        flags: Flags::new(flags::DIR | flags::HEADER),
+        version: FORMAT_VERSION,
+        body_checksum: 0xDEAD_BEEF,
+        checksum: 0,
+    };
+    meta.set_checksum();
+
+    let decoded = Metadata::from_bytes(&meta.to_bytes()).unwrap();
+    assert_eq!(decoded.to_bytes(), meta.to_bytes());
+    assert!(Metadata::from_bytes(&[0u8; 39]).is_none());
+}
+
+#[test]
+fn header_and_footer_differ_in_flag_and_body_checksum() {
+    let meta = Metadata::new(flags::FILE, 3, 5, 0o644, 1000, 100, 1_700_000_000);
+    let header = meta.header(b"a.txt", &[]);
+    let footer = meta.footer(0xDEAD_BEEF);
+
+    assert_eq!(header.flags, Flags::new(flags::FILE | flags::HEADER));
+    assert_eq!(header.body_checksum, 0);
+    assert_eq!(footer.flags, Flags::new(flags::FILE));
+    assert_eq!(footer.body_checksum, 0xDEAD_BEEF);
+    assert_eq!(
+        header.checksum,
+        header.compute_header_checksum(b"a.txt", &[])
+    );
+    assert_ne!(
+        header.checksum,
+        header.compute_header_checksum(b"b.txt", &[])
+    );
+    for meta in [meta, header, footer] {
+        assert_eq!(meta.magic, MAGIC);
+        assert_eq!(meta.version, FORMAT_VERSION);
+    }
+}
+
+#[test]
+fn footer_matches_header() {
+    let meta = Metadata::new(flags::FILE, 3, 5, 0o644, 1000, 100, 1_700_000_000);
+    let header = meta.header(b"a.txt", &[]);
+    let footer = meta.footer(0xDEAD_BEEF);
+    assert!(header.matches(&footer));
+    assert!(footer.matches(&header));
+    assert!(header.matches(&header));
+
+    let mismatches = [
+        Metadata::new(flags::FILE, 4, 5, 0o644, 1000, 100, 1_700_000_000),
+        Metadata::new(flags::FILE, 3, 6, 0o644, 1000, 100, 1_700_000_000),
+        Metadata::new(flags::FILE, 3, 5, 0o755, 1000, 100, 1_700_000_000),
+        Metadata::new(flags::FILE, 3, 5, 0o644, 0, 100, 1_700_000_000),
+        Metadata::new(flags::FILE, 3, 5, 0o644, 1000, 0, 1_700_000_000),
+        Metadata::new(flags::DIR, 3, 5, 0o644, 1000, 100, 1_700_000_000),
+        Metadata::new(flags::FILE, 3, 5, 0o644, 1000, 100, 1_700_000_001),
+    ];
+    for other in mismatches {
+        assert!(!header.matches(&other.footer(0xDEAD_BEEF)), "{other:?}");
+    }
+}
+
+#[test]
+fn metadata_is_little_endian() {
+    let mut meta = Metadata {
+        modified_at: 0x0102_0304_0506_0708,
+        file_size: 0x1112_1314_1516_1718,
+        path_len: 0x2122,
+        perms: 0x2324,
+        owner: 0x2526,
+        group: 0x2728,
+        magic: MAGIC,
+        flags: Flags::new(0x3334),
+        version: 0x3132,
+        body_checksum: 0x4142_4344,
+        checksum: 0,
+    };
+    meta.set_checksum();
+
+    // every field least significant byte first, whatever the host's byte order.
+    #[rustfmt::skip]
+    let expected: [u8; 36] = [
+        0x08, 0x07, 0x06, 0x05, 0x04, 0x03, 0x02, 0x01,
+        0x18, 0x17, 0x16, 0x15, 0x14, 0x13, 0x12, 0x11,
+        0x22, 0x21,
+        0x24, 0x23,
+        0x26, 0x25,
+        0x28, 0x27,
+        0x6a, 0x8b, 0x96, 0x2f,
+        0x34, 0x33,
+        0x32, 0x31,
+        0x44, 0x43, 0x42, 0x41,
+    ];
+    let bytes = meta.to_bytes();
+    assert_eq!(bytes[..36], expected);
+    let checksum = crc32::digest(&expected);
+    assert_eq!(bytes[36..], checksum.to_le_bytes());
+
+    let mut literal = [0u8; Metadata::SIZE];
+    literal[..36].copy_from_slice(&expected);
+    literal[36..].copy_from_slice(&checksum.to_le_bytes());
+    let decoded = Metadata::from_bytes(&literal).unwrap();
+    assert_eq!(decoded.modified_at, 0x0102_0304_0506_0708);
+    assert_eq!(decoded.file_size, 0x1112_1314_1516_1718);
+    assert_eq!(decoded.path_len, 0x2122);
+    assert_eq!(decoded.perms, 0x2324);
+    assert_eq!(decoded.owner, 0x2526);
+    assert_eq!(decoded.group, 0x2728);
+    assert_eq!(decoded.magic, MAGIC);
+    assert_eq!(decoded.flags, Flags::new(0x3334));
+    assert_eq!(decoded.version, 0x3132);
+    assert_eq!(decoded.body_checksum, 0x4142_4344);
+    assert_eq!(decoded.checksum, checksum);
+}
+
+#[test]
+fn decode_checks_header_and_path() {
+    let path = b"dir/a.txt";
+    let header = Metadata::new(flags::FILE, 3, path.len() as _, 0o644, 0, 0, 0).header(path, &[]);
+    let bytes = header.encode();
+
+    let decoded = Metadata::decode(&bytes).unwrap();
+    assert!(decoded.is_header());
+    assert_eq!(decoded.kind(), EntryKind::File);
+    assert_eq!(decoded.file_size(), 3);
+    assert_eq!(decoded.verify_path(path), Ok(()));
+    assert_eq!(
+        decoded.verify_path(b"dir/b.txt"),
+        Err(DecodeError::Checksum { offset: None })
+    );
+    assert_eq!(
+        decoded.verify_path(b"a.txt"),
+        Err(DecodeError::Header { offset: None })
+    );
+
+    let footer = header.footer(0x1234).encode();
+    assert_eq!(Metadata::decode(&footer).unwrap().body_checksum(), 0x1234);
+    assert_eq!(
+        Metadata::decode(&[0; Metadata::SIZE]).err(),
+        Some(DecodeError::NotAnArchive { offset: None })
+    );
+}
+
+#[cfg(feature = "std")]
+#[test]
+fn try_from_slice_validates() {
+    let footer = Metadata::new(flags::DIR, 0, 3, 0o755, 0, 0, 0).footer(0);
+    let bytes = footer.to_vec();
+    assert_eq!(
+        Metadata::try_from(&bytes[..]).unwrap().kind(),
+        EntryKind::Directory
+    );
+    let trailing = [bytes.as_slice(), b"trailing"].concat();
+    assert_eq!(Metadata::try_from(&trailing[..]).unwrap().to_vec(), bytes);
+
+    assert_eq!(
+        Metadata::try_from(&bytes[..Metadata::SIZE - 1]).err(),
+        Some(DecodeError::Crop { offset: None })
+    );
+
+    let mut bad_magic = bytes.clone();
+    bad_magic[24] ^= 0xFF;
+    assert_eq!(
+        Metadata::try_from(&bad_magic[..]).err(),
+        Some(DecodeError::NotAnArchive { offset: None })
+    );
+
+    let mut bad_checksum = bytes.clone();
+    bad_checksum[0] ^= 0x01;
+    assert_eq!(
+        Metadata::try_from(&bad_checksum[..]).err(),
+        Some(DecodeError::Checksum { offset: None })
+    );
+
+    // headers are accepted, their checksum is checked along with a following path.
+    let header = Metadata::new(flags::DIR, 0, 3, 0o755, 0, 0, 0).header(b"dir", &[]);
+    let decoded = Metadata::try_from(&header.to_vec()[..]).unwrap();
+    assert!(decoded.is_header());
+    let mut framed = header.to_vec();
+    framed.extend_from_slice(b"dir");
+    assert_eq!(
+        Metadata::try_from(&framed[..]).unwrap().to_vec(),
+        decoded.to_vec()
+    );
+    framed[Metadata::SIZE] = b'D';
+    assert_eq!(
+        Metadata::try_from(&framed[..]).err(),
+        Some(DecodeError::Checksum { offset: None })
+    );
+}
+
+/// Errors encountered while decoding an archive.
+///
+/// Errors other than [`Exhausted`](Self::Exhausted) carry the byte offset of the
+/// structure that failed to decode, if it is known: the stream position for
+/// archives that can seek, otherwise counted from where reading began.
+/// Decoding metadata on its own, e.g. with [`Metadata::decode`], has no position.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DecodeError {
+    /// No further entries, the clean end of an archive.
+    ///
+    /// Iterating functions such as [`read`](crate::read) and [`Entries`](crate::Entries) treat this as
+    /// success rather than returning it.
+    Exhausted,
+    /// Generic Header Error
+    Header { offset: Option<u64> },
+    /// Generic Footer Error
+    Footer { offset: Option<u64> },
+    /// Faulty checksum
+    Checksum { offset: Option<u64> },
+    /// Cut off mid-file
+    Crop { offset: Option<u64> },
+    /// Written in an unknown format version
+    Version { offset: Option<u64> },
+    /// The first header lacks the magic number, so the data isn't an archive at all.
+    NotAnArchive { offset: Option<u64> },
+    /// The file changed size while it was archived, so its body was padded
+    /// or cut off, see [`flags::RESIZED`](crate::flags::RESIZED).
+    Resized { offset: Option<u64> },
+    /// The contents exceed what the reader restores, e.g. a sparse file whose
+    /// holes add up to more than 1 TiB when written out as zeros.
+    TooLarge { offset: Option<u64> },
+}
+
+#[cfg_attr(not(feature = "std"), allow(dead_code))]
+impl DecodeError {
+    /// A wrong magic past the first header means corruption rather than a foreign file.
+    pub(crate) fn mid_archive(self) -> Self {
+        match self {
+            DecodeError::NotAnArchive { offset } => DecodeError::Header { offset },
+            e => e,
+        }
+    }
+
+    /// Byte offset of the failure from the start of the archive, if known.
+    pub fn offset(&self) -> Option<u64> {
+        match *self {
+            DecodeError::Exhausted => None,
+            DecodeError::Header { offset }
+            | DecodeError::Footer { offset }
+            | DecodeError::Checksum { offset }
+            | DecodeError::Crop { offset }
+            | DecodeError::Version { offset }
+            | DecodeError::NotAnArchive { offset }
+            | DecodeError::Resized { offset }
+            | DecodeError::TooLarge { offset } => offset,
+        }
+    }
+
+    /// Records that the failure happened at `at`, unless a more precise offset is known.
+    pub(crate) fn at(mut self, at: u64) -> Self {
+        match &mut self {
+            DecodeError::Exhausted => {}
+            DecodeError::Header { offset }
+            | DecodeError::Footer { offset }
+            | DecodeError::Checksum { offset }
+            | DecodeError::Crop { offset }
+            | DecodeError::Version { offset }
+            | DecodeError::NotAnArchive { offset }
+            | DecodeError::Resized { offset }
+            | DecodeError::TooLarge { offset } => {
+                offset.get_or_insert(at);
+            }
+        }
+        self
+    }
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let msg = match self {
+            DecodeError::Exhausted => "no further entries",
+            DecodeError::Header { .. } => "malformed header",
+            DecodeError::Footer { .. } => "malformed footer",
+            DecodeError::Checksum { .. } => "checksum mismatch",
+            DecodeError::Crop { .. } => "archive truncated mid-file",
+            DecodeError::Version { .. } => "unsupported archive format version",
+            DecodeError::NotAnArchive { .. } => "not a bitumen archive",
+            DecodeError::Resized { .. } => "file changed size while it was archived",
+            DecodeError::TooLarge { .. } => "file exceeds the size limit",
+        };
+        f.write_str(msg)?;
+        match self.offset() {
+            Some(offset) => write!(f, " at offset {offset}"),
+            None => Ok(()),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for DecodeError {}
+
+#[cfg(feature = "std")]
+#[test]
+fn decode_error_is_an_error() {
+    fn decode() -> Result<(), Box<dyn std::error::Error>> {
+        Err(DecodeError::Crop { offset: None })?
+    }
+    assert_eq!(
+        decode().unwrap_err().to_string(),
+        "archive truncated mid-file"
+    );
+
+    let err = std::io::Error::from(DecodeError::Checksum { offset: None });
+    assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+    assert_eq!(err.to_string(), "checksum mismatch");
+    assert_eq!(
+        DecodeError::Checksum { offset: None }
+            .at(4096)
+            .at(0)
+            .to_string(),
+        "checksum mismatch at offset 4096"
+    );
+}
+
+#[cfg(feature = "std")]
+impl From<DecodeError> for std::io::Error {
+    fn from(err: DecodeError) -> Self {
+        std::io::Error::new(std::io::ErrorKind::InvalidData, err)
+    }
+}
+
+/// The kind of an archived object.
+///
+/// Displays as a human readable name, e.g. `"Soft Link"`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum EntryKind {
+    File,
+    Directory,
+    SoftLink,
+    HardLink,
+    Fifo,
+    CharDevice,
+    BlockDevice,
+}
+
+impl TryFrom<u32> for EntryKind {
+    type Error = DecodeError;
+
+    /// Extracts the kind from archived flags, rejecting the reserved kind bits.
+    fn try_from(flags: u32) -> Result<Self, DecodeError> {
+        match flags & flags::KIND_MASK {
+            flags::FILE => Ok(EntryKind::File),
+            flags::DIR => Ok(EntryKind::Directory),
+            flags::SOFT_LINK => Ok(EntryKind::SoftLink),
+            flags::HARD_LINK => Ok(EntryKind::HardLink),
+            flags::FIFO => Ok(EntryKind::Fifo),
+            flags::CHAR_DEV => Ok(EntryKind::CharDevice),
+            flags::BLOCK_DEV => Ok(EntryKind::BlockDevice),
+            _ => Err(DecodeError::Header { offset: None }),
+        }
+    }
+}
+
+impl From<EntryKind> for u32 {
+    /// The kind bits of `kind`, see [`flags::KIND_MASK`].
+    fn from(kind: EntryKind) -> Self {
+        match kind {
+            EntryKind::File => flags::FILE,
+            EntryKind::Directory => flags::DIR,
+            EntryKind::SoftLink => flags::SOFT_LINK,
+            EntryKind::HardLink => flags::HARD_LINK,
+            EntryKind::Fifo => flags::FIFO,
+            EntryKind::CharDevice => flags::CHAR_DEV,
+            EntryKind::BlockDevice => flags::BLOCK_DEV,
+        }
+    }
+}
+
+impl fmt::Display for EntryKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            EntryKind::File => "File",
+            EntryKind::Directory => "Directory",
+            EntryKind::SoftLink => "Soft Link",
+            EntryKind::HardLink => "Hard Link",
+            EntryKind::Fifo => "FIFO",
+            EntryKind::CharDevice => "Char Device",
+            EntryKind::BlockDevice => "Block Device",
+        };
+        f.pad(name)
+    }
+}
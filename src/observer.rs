@@ -0,0 +1,71 @@
+use crate::{DecodeError, EntryInfo};
+
+/// Receives the events of reading an archive, see [`read`](crate::read).
+///
+/// Entries and the error that stopped reading are only reported here.
+/// Details of a failure beyond the [`DecodeError`], e.g. which sizes or
+/// checksums disagreed, are still logged through the `log` facade while decoding.
+pub trait ArchiveObserver {
+    /// Called for every entry, in archive order.
+    fn on_entry(&mut self, info: &EntryInfo);
+
+    /// Called once with the error that stopped reading.
+    /// Reaching the end of the archive is not an error.
+    fn on_error(&mut self, err: &DecodeError);
+}
+
+/// Logs entries and errors through the `log` facade.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct LogObserver;
+
+impl ArchiveObserver for LogObserver {
+    fn on_entry(&mut self, info: &EntryInfo) {
+        log::info!(
+            "{kind: <9} : {path} : {size}B",
+            kind = info.kind,
+            path = info.path.display(),
+            size = info.size
+        );
+    }
+
+    fn on_error(&mut self, err: &DecodeError) {
+        log::error!("Failed to read archive: {err}");
+    }
+}
+
+#[test]
+fn observer_receives_entries_and_errors() {
+    #[derive(Default)]
+    struct Collect {
+        paths: Vec<std::path::PathBuf>,
+        errors: Vec<DecodeError>,
+    }
+
+    impl ArchiveObserver for Collect {
+        fn on_entry(&mut self, info: &EntryInfo) {
+            self.paths.push(info.path.clone());
+        }
+
+        fn on_error(&mut self, err: &DecodeError) {
+            self.errors.push(*err);
+        }
+    }
+
+    let dir = tempfile::tempdir().unwrap();
+    std::fs::write(dir.path().join("a.txt"), b"abc").unwrap();
+    let mut archive = vec![];
+    crate::recursive_archive(&mut archive, dir.path()).unwrap();
+
+    let mut observer = Collect::default();
+    crate::read(&mut std::io::Cursor::new(&archive), &mut observer).unwrap();
+    assert_eq!(observer.paths.len(), 2);
+    assert!(observer.paths[1].ends_with("a.txt"));
+    assert!(observer.errors.is_empty());
+
+    let mut observer = Collect::default();
+    // cuts the footer of the last entry, not just the trailer.
+    archive.truncate(archive.len() - crate::trailer::LEN - 1);
+    let result = crate::read(&mut std::io::Cursor::new(&archive), &mut observer);
+    assert_eq!(observer.errors, [result.unwrap_err()]);
+    assert_eq!(observer.paths.len(), 1);
+}
@@ -0,0 +1,209 @@
+//! Platform specific glue between archived metadata and the filesystem.
+//!
+//! On unix paths are stored as their raw bytes and permissions and ownership are
+//! recorded. Other platforms store paths as UTF-8 and leave the unix-only fields empty.
+
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+#[cfg(unix)]
+use std::{
+    ffi::OsStr,
+    os::unix::prelude::{FileTypeExt, MetadataExt, OsStrExt, PermissionsExt},
+};
+
+#[cfg(unix)]
+use crate::flags;
+
+/// Encodes a path for storage in the archive.
+#[cfg(unix)]
+pub fn path_to_bytes(path: &Path) -> Vec<u8> {
+    path.as_os_str().as_bytes().to_vec()
+}
+
+/// Encodes a path for storage in the archive.
+///
+/// Unpaired surrogates are replaced, as the archive stores UTF-8 paths.
+#[cfg(not(unix))]
+pub fn path_to_bytes(path: &Path) -> Vec<u8> {
+    path.to_string_lossy().replace('\\', "/").into_bytes()
+}
+
+/// Decodes a path stored in the archive.
+#[cfg(unix)]
+pub fn bytes_to_path(bytes: &[u8]) -> PathBuf {
+    PathBuf::from(OsStr::from_bytes(bytes))
+}
+
+/// Decodes a path stored in the archive.
+#[cfg(not(unix))]
+pub fn bytes_to_path(bytes: &[u8]) -> PathBuf {
+    PathBuf::from(String::from_utf8_lossy(bytes).into_owned())
+}
+
+/// Permission bits to record for a file.
+///
+/// `mode` is the full `st_mode`, of which only the low 16 bits (file type
+/// and permission bits) carry meaning. Anything above is dropped. The
+/// setuid, setgid and sticky bits are among the kept ones.
+#[cfg(unix)]
+pub fn perms(metadata: &fs::Metadata) -> u16 {
+    (metadata.permissions().mode() & 0xFFFF) as u16
+}
+
+#[cfg(not(unix))]
+pub fn perms(_metadata: &fs::Metadata) -> u16 {
+    0
+}
+
+/// Owner and group to record for a file.
+///
+/// uids and gids above `u16::MAX` don't fit the header and get truncated,
+/// restoring ownership of such entries yields the wrong owner.
+#[cfg(unix)]
+pub fn owner(metadata: &fs::Metadata) -> (u16, u16) {
+    (metadata.uid() as u16, metadata.gid() as u16)
+}
+
+#[cfg(not(unix))]
+pub fn owner(_metadata: &fs::Metadata) -> (u16, u16) {
+    (0, 0)
+}
+
+/// Effective uid and gid of the running process, truncated like [`owner`].
+#[cfg(unix)]
+pub fn current_owner() -> (u16, u16) {
+    // SAFETY: geteuid and getegid always succeed.
+    let (uid, gid) = unsafe { (libc::geteuid(), libc::getegid()) };
+    (uid as u16, gid as u16)
+}
+
+#[cfg(not(unix))]
+pub fn current_owner() -> (u16, u16) {
+    (0, 0)
+}
+
+/// Device and inode number of a file that has multiple hard links.
+#[cfg(unix)]
+pub fn hard_link_inode(metadata: &fs::Metadata) -> Option<(u64, u64)> {
+    (metadata.is_file() && metadata.nlink() > 1).then(|| (metadata.dev(), metadata.ino()))
+}
+
+#[cfg(not(unix))]
+pub fn hard_link_inode(_metadata: &fs::Metadata) -> Option<(u64, u64)> {
+    None
+}
+
+/// Device and inode number identifying the object, if the platform has them.
+#[cfg(unix)]
+pub fn identity(metadata: &fs::Metadata) -> Option<(u64, u64)> {
+    Some((metadata.dev(), metadata.ino()))
+}
+
+#[cfg(not(unix))]
+pub fn identity(_metadata: &fs::Metadata) -> Option<(u64, u64)> {
+    None
+}
+
+/// Kind and body of a FIFO or device node, `None` for any other object.
+///
+/// The body of a device is its device number.
+#[cfg(unix)]
+pub fn special(metadata: &fs::Metadata) -> Option<(u32, Vec<u8>)> {
+    let file_type = metadata.file_type();
+    let device = || metadata.rdev().to_le_bytes().to_vec();
+    if file_type.is_fifo() {
+        Some((flags::FIFO, vec![]))
+    } else if file_type.is_char_device() {
+        Some((flags::CHAR_DEV, device()))
+    } else if file_type.is_block_device() {
+        Some((flags::BLOCK_DEV, device()))
+    } else {
+        None
+    }
+}
+
+#[cfg(not(unix))]
+pub fn special(_metadata: &fs::Metadata) -> Option<(u32, Vec<u8>)> {
+    None
+}
+
+/// Creates a FIFO or device node of the archived `kind` at `target`.
+///
+/// Creating devices requires privilege. Nodes without recorded permissions
+/// are created readable and writable for everyone, subject to the umask.
+#[cfg(unix)]
+pub fn mknod(target: &Path, kind: u32, perms: u16, device: u64) -> io::Result<()> {
+    use std::ffi::CString;
+
+    let file_type = match kind {
+        flags::FIFO => libc::S_IFIFO,
+        flags::CHAR_DEV => libc::S_IFCHR,
+        flags::BLOCK_DEV => libc::S_IFBLK,
+        _ => return Err(io::ErrorKind::InvalidInput.into()),
+    };
+    let perms = match perms as libc::mode_t & 0o7777 {
+        0 => 0o666,
+        perms => perms,
+    };
+    let path = CString::new(target.as_os_str().as_bytes())?;
+    let res = unsafe { libc::mknod(path.as_ptr(), file_type | perms, device as libc::dev_t) };
+    if res != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+#[cfg(not(unix))]
+pub fn mknod(_target: &Path, _kind: u32, _perms: u16, _device: u64) -> io::Result<()> {
+    Err(io::ErrorKind::Unsupported.into())
+}
+
+/// Sets the permission bits of `target`, refusing to follow it if it's a symlink,
+/// as an archived symlink may have replaced it with one pointing anywhere.
+#[cfg(unix)]
+pub fn set_perms(target: &Path, perms: u16) -> io::Result<()> {
+    if target.symlink_metadata()?.file_type().is_symlink() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!(
+                "refusing to change permissions through symlink {}",
+                target.display()
+            ),
+        ));
+    }
+    let mode = perms as u32 & 0o7777;
+    fs::set_permissions(target, fs::Permissions::from_mode(mode))
+}
+
+#[cfg(not(unix))]
+pub fn set_perms(_target: &Path, _perms: u16) -> io::Result<()> {
+    Ok(())
+}
+
+#[cfg(unix)]
+pub fn set_owner(target: &Path, owner: u16, group: u16) -> io::Result<()> {
+    std::os::unix::fs::lchown(target, Some(owner as u32), Some(group as u32))
+}
+
+#[cfg(not(unix))]
+pub fn set_owner(_target: &Path, _owner: u16, _group: u16) -> io::Result<()> {
+    Err(io::ErrorKind::Unsupported.into())
+}
+
+#[cfg(unix)]
+pub fn symlink(target: &[u8], link: &Path) -> io::Result<()> {
+    std::os::unix::fs::symlink(bytes_to_path(target), link)
+}
+
+#[cfg(windows)]
+pub fn symlink(target: &[u8], link: &Path) -> io::Result<()> {
+    std::os::windows::fs::symlink_file(bytes_to_path(target), link)
+}
+
+#[cfg(not(any(unix, windows)))]
+pub fn symlink(_target: &[u8], _link: &Path) -> io::Result<()> {
+    Err(io::ErrorKind::Unsupported.into())
+}
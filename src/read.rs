@@ -0,0 +1,961 @@
+//! Sequential decoding of whole archives.
+
+use std::{
+    io::{self, Read, Seek},
+    path::Path,
+};
+
+#[cfg(test)]
+use crate::{
+    append_to_archive, extract,
+    flags::{self, Flags},
+    metadata::FORMAT_VERSION,
+    recursive_archive, verify, EntryKind, LogObserver,
+};
+use crate::{
+    crc32, platform, trailer, xattrs, ArchiveObserver, DecodeError, Entries, Entry, EntryInfo,
+    Metadata, StreamEntries, MAGIC, MAX_PATH_LEN,
+};
+
+/// Reads and decodes the next metadata.
+///
+/// Only running out of data before its first byte is the end of an archive,
+/// metadata that is cut off is [`DecodeError::Crop`].
+pub(crate) fn read_meta<R: Read>(name: &str, archive: &mut R) -> Result<Metadata, DecodeError> {
+    let mut bytes = [0u8; Metadata::SIZE];
+    let mut filled = 0;
+    while filled < bytes.len() {
+        match archive.read(&mut bytes[filled..]) {
+            Ok(0) => break,
+            Ok(n) => filled += n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(read_failed(name, e)),
+        }
+    }
+    decode_meta(name, &bytes, filled)
+}
+
+/// Decodes metadata of which only the first `filled` bytes could be read,
+/// see [`read_meta`].
+pub(crate) fn decode_meta(
+    name: &str,
+    bytes: &[u8; Metadata::SIZE],
+    filled: usize,
+) -> Result<Metadata, DecodeError> {
+    match filled {
+        // the normal end of an archive, no need to shout.
+        0 => {
+            log::debug!("No {name} left");
+            Err(DecodeError::Exhausted)
+        }
+        n if n < Metadata::SIZE && trailer::is_trailer(&bytes[..n]) => {
+            log::debug!("Reached the archive trailer");
+            Err(DecodeError::Exhausted)
+        }
+        n if n < Metadata::SIZE => {
+            log::error!("{name} cut off after {n}B");
+            Err(DecodeError::Crop { offset: None })
+        }
+        // a header's checksum also covers its path, so [`read_path`] checks it.
+        _ => Metadata::decode(bytes).inspect_err(|e| log::error!("Failed to decode {name}: {e}")),
+    }
+}
+
+/// Reading `what` failed for another reason than the archive ending.
+pub(crate) fn read_failed(what: &str, e: io::Error) -> DecodeError {
+    log::error!("Failed to read {what}: {e:?}");
+    DecodeError::Crop { offset: None }
+}
+
+/// Reads metadata that has to be a header.
+pub(crate) fn read_header<R: Read>(archive: &mut R) -> Result<Metadata, DecodeError> {
+    as_header(read_meta("Header", archive))
+}
+
+/// Checks that the metadata just `read` is a header.
+pub(crate) fn as_header(read: Result<Metadata, DecodeError>) -> Result<Metadata, DecodeError> {
+    let header = read?;
+    if !header.flags.is_header() {
+        log::error!("Expected a header, found a footer");
+        return Err(DecodeError::Header { offset: None });
+    }
+    Ok(header)
+}
+
+/// Reads metadata that has to be a footer, which never starts an archive.
+pub(crate) fn read_footer<R: Read>(archive: &mut R) -> Result<Metadata, DecodeError> {
+    as_footer(read_meta("Footer", archive))
+}
+
+/// Checks that the metadata just `read` is a footer.
+pub(crate) fn as_footer(read: Result<Metadata, DecodeError>) -> Result<Metadata, DecodeError> {
+    let footer = read.map_err(|e| match e {
+        // an entry without footer is cut off, not the end of the archive.
+        DecodeError::Exhausted => DecodeError::Crop { offset: None },
+        e => e.mid_archive(),
+    })?;
+    if footer.flags.is_header() {
+        log::error!("Expected a footer, found a header");
+        return Err(DecodeError::Footer { offset: None });
+    }
+    Ok(footer)
+}
+
+/// Reads the path following `header` and verifies the header checksum.
+pub(crate) fn read_path<R: Read>(
+    header: &Metadata,
+    archive: &mut R,
+) -> Result<Vec<u8>, DecodeError> {
+    let mut path = vec![];
+    read_path_into(header, archive, &mut path)?;
+    Ok(path)
+}
+
+/// Like [`read_path`], but reuses the allocation of `path`.
+pub(crate) fn read_path_into<R: Read>(
+    header: &Metadata,
+    archive: &mut R,
+    path: &mut Vec<u8>,
+) -> Result<(), DecodeError> {
+    check_path_len(header)?;
+    path.clear();
+    path.resize(header.path_len as usize, 0);
+    archive
+        .read_exact(path)
+        .map_err(|e| read_failed("path", e))?;
+    verify_path(header, path)
+}
+
+/// Rejects a path length no archiver writes, before allocating for it.
+pub(crate) fn check_path_len(header: &Metadata) -> Result<(), DecodeError> {
+    if header.path_len as usize > MAX_PATH_LEN {
+        log::error!("Path length of {}B exceeds the maximum", header.path_len);
+        return Err(DecodeError::Header { offset: None });
+    }
+    Ok(())
+}
+
+/// Checks the `path` just read against the header checksum.
+pub(crate) fn verify_path(header: &Metadata, path: &[u8]) -> Result<(), DecodeError> {
+    header
+        .verify_path(path)
+        .inspect_err(|e| log::error!("Failed to verify path: {e}"))
+}
+
+/// Ensures the body read for the entry at `path` has the checksum recorded in its footer.
+pub(crate) fn check_body(
+    footer: &Metadata,
+    body_checksum: u32,
+    path: &Path,
+) -> Result<(), DecodeError> {
+    if footer.body_checksum != body_checksum {
+        log::error!("Body checksum mismatch: {}", path.display());
+        return Err(DecodeError::Checksum { offset: None });
+    }
+    Ok(())
+}
+
+/// Ensures the footer describes the same object as its header.
+pub(crate) fn check_footer(header: &Metadata, footer: &Metadata) -> Result<(), DecodeError> {
+    if !footer.matches(header) || footer.is_header() {
+        log::error!("Footer does not match header: {header:?} {footer:?}");
+        return Err(DecodeError::Footer { offset: None });
+    }
+    Ok(())
+}
+
+/// Number of bytes between the current position and the end of the stream.
+pub(crate) fn remaining_len<R: Seek>(archive: &mut R) -> io::Result<u64> {
+    let pos = archive.stream_position()?;
+    let end = archive.seek(io::SeekFrom::End(0))?;
+    archive.seek(io::SeekFrom::Start(pos))?;
+    Ok(end.saturating_sub(pos))
+}
+
+/// The decoding error behind `e`, or [`DecodeError::Crop`] for any other
+/// failure to read the archive or write what was read from it.
+pub(crate) fn decode_err(e: io::Error) -> DecodeError {
+    match e
+        .get_ref()
+        .and_then(|inner| inner.downcast_ref::<DecodeError>())
+    {
+        Some(e) => *e,
+        None => {
+            log::error!("Failed to read archive: {e:?}");
+            DecodeError::Crop { offset: None }
+        }
+    }
+}
+
+/// Current position in `archive`, for the offsets of errors.
+fn position<R: Seek>(archive: &mut R) -> Result<u64, DecodeError> {
+    archive.stream_position().map_err(|e| {
+        log::error!("Failed to determine archive position: {e:?}");
+        DecodeError::Crop { offset: None }
+    })
+}
+
+/// Reads the next entry, `path_buf` holding its raw path afterwards.
+pub(crate) fn read1<R: Read + Seek>(
+    archive: &mut R,
+    path_buf: &mut Vec<u8>,
+) -> Result<Entry, DecodeError> {
+    let start = position(archive)?;
+    let header = read_header(archive).map_err(|e| e.at(start))?;
+    log::trace!("{header:?}");
+
+    // the header checksum covers the path, so a mismatch is at the header.
+    read_path_into(&header, archive, path_buf).map_err(|e| e.at(start))?;
+    let path = platform::bytes_to_path(path_buf);
+    let xattrs_start = start + Metadata::SIZE as u64 + header.path_len as u64;
+    xattrs::read(&header, path_buf, archive).map_err(|e| e.at(xattrs_start))?;
+
+    // a corrupt size must not send the seek beyond the end of the archive.
+    let remaining = remaining_len(archive).map_err(|e| {
+        log::error!("Failed to determine archive length: {e:?}");
+        DecodeError::Crop {
+            offset: Some(start),
+        }
+    })?;
+    if header.file_size > remaining.saturating_sub(Metadata::SIZE as u64) {
+        log::error!(
+            "File size of {}B exceeds the remaining {remaining}B",
+            header.file_size
+        );
+        return Err(DecodeError::Header {
+            offset: Some(start),
+        });
+    }
+
+    let footer_start = archive
+        .seek(io::SeekFrom::Current(header.file_size as _))
+        .map_err(|e| {
+            log::error!("Failed to seek past file contents: {e:?}");
+            DecodeError::Crop {
+                offset: Some(start),
+            }
+        })?;
+
+    let footer = read_footer(archive).map_err(|e| e.at(footer_start))?;
+    check_footer(&header, &footer).map_err(|e| e.at(footer_start))?;
+
+    let xattrs_len = footer_start - header.file_size - xattrs_start;
+    Ok(Entry {
+        meta: header,
+        path,
+        xattrs_len,
+    })
+}
+
+/// Reader adapter counting the bytes read through it,
+/// for the offsets of errors in archives that can't seek.
+pub(crate) struct Counting<R> {
+    inner: R,
+    /// bytes read so far.
+    pub(crate) pos: u64,
+}
+
+impl<R> Counting<R> {
+    pub(crate) fn new(inner: R) -> Self {
+        Self { inner, pos: 0 }
+    }
+}
+
+impl<R: Read> Read for Counting<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.pos += n as u64;
+        Ok(n)
+    }
+}
+
+/// Like [`read1`], but reads past the body instead of seeking,
+/// which also allows verifying the body checksum.
+pub(crate) fn read1_stream<R: Read>(
+    archive: &mut Counting<R>,
+    path_buf: &mut Vec<u8>,
+) -> Result<Entry, DecodeError> {
+    let start = archive.pos;
+    let header = read_header(archive).map_err(|e| e.at(start))?;
+    log::trace!("{header:?}");
+
+    read_path_into(&header, archive, path_buf).map_err(|e| e.at(start))?;
+    let path = platform::bytes_to_path(path_buf);
+    let xattrs_start = archive.pos;
+    xattrs::read(&header, path_buf, archive).map_err(|e| e.at(xattrs_start))?;
+
+    let body_start = archive.pos;
+    let xattrs_len = body_start - xattrs_start;
+    let mut body = crc32::Reader::for_body(archive.take(header.file_size), header.flags);
+    let copied = io::copy(&mut body, &mut io::sink())
+        .map_err(|e| read_failed("file contents", e).at(body_start))?;
+    let body_checksum = body.digest();
+    if copied != header.file_size {
+        log::error!("File contents cut off after {copied}B");
+        return Err(DecodeError::Crop {
+            offset: Some(archive.pos),
+        });
+    }
+
+    let footer_start = archive.pos;
+    let footer = read_footer(archive).map_err(|e| e.at(footer_start))?;
+    check_footer(&header, &footer).map_err(|e| e.at(footer_start))?;
+    check_body(&footer, body_checksum, &path).map_err(|e| e.at(body_start))?;
+
+    Ok(Entry {
+        meta: header,
+        path,
+        xattrs_len,
+    })
+}
+
+/// Reports every entry of the archive to `observer`, use [`LogObserver`](crate::LogObserver) to log them.
+///
+/// Reaching the end of the archive is a success, any other decoding error
+/// is reported and returned. What led to the error is logged as it is found,
+/// see [`ArchiveObserver`].
+pub fn read<R: Read + Seek>(
+    archive: &mut R,
+    observer: &mut impl ArchiveObserver,
+) -> Result<(), DecodeError> {
+    for entry in Entries::new(archive) {
+        match entry {
+            Ok(entry) => observer.on_entry(&entry.into()),
+            Err(e) => {
+                observer.on_error(&e);
+                return Err(e);
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Like [`read`], for an archive that can only be read sequentially, e.g. a pipe.
+///
+/// Bodies are read and discarded rather than skipped by seeking, see [`StreamEntries`].
+pub fn read_stream<R: Read>(
+    archive: &mut R,
+    observer: &mut impl ArchiveObserver,
+) -> Result<(), DecodeError> {
+    for entry in StreamEntries::new(archive) {
+        match entry {
+            Ok(entry) => observer.on_entry(&entry.into()),
+            Err(e) => {
+                observer.on_error(&e);
+                return Err(e);
+            }
+        }
+    }
+    Ok(())
+}
+
+#[test]
+fn read_stream_without_seek() {
+    let dir = tempfile::tempdir().unwrap();
+    std::fs::write(dir.path().join("a.txt"), b"abc").unwrap();
+    std::fs::write(dir.path().join("b.txt"), b"def").unwrap();
+
+    let mut archive = vec![];
+    recursive_archive(&mut archive, dir.path()).unwrap();
+    // a slice only implements `Read`, just like a pipe.
+    assert_eq!(
+        read_stream(&mut archive.as_slice(), &mut LogObserver),
+        Ok(())
+    );
+
+    // both bodies are 3B long, so this holds whichever file comes last.
+    let body_start = archive.len() - trailer::LEN - Metadata::SIZE - 3;
+    archive[body_start + 2] ^= 0x01;
+    assert_eq!(
+        read_stream(&mut archive.as_slice(), &mut LogObserver),
+        Err(DecodeError::Checksum {
+            offset: Some(body_start as u64)
+        })
+    );
+}
+
+/// The comment leading the archive, see [`Archiver::set_comment`](crate::Archiver::set_comment).
+///
+/// Afterwards `archive` is positioned at the first entry, whether there is a comment or not.
+pub fn read_comment<R: Read + Seek>(archive: &mut R) -> Result<Option<String>, DecodeError> {
+    let io_err = |e: io::Error| {
+        log::error!("Failed to read comment: {e:?}");
+        DecodeError::Crop { offset: None }
+    };
+
+    let start = archive.stream_position().map_err(io_err)?;
+    let header = match read_header(archive) {
+        Ok(header) => header,
+        Err(DecodeError::Exhausted) => return Ok(None),
+        Err(e) => return Err(e.at(start)),
+    };
+    if !header.flags.is_comment() {
+        archive.seek(io::SeekFrom::Start(start)).map_err(io_err)?;
+        return Ok(None);
+    }
+    read_path(&header, archive).map_err(|e| e.at(start))?;
+
+    let body_start = start + Metadata::SIZE as u64 + header.path_len as u64;
+    let mut comment = vec![];
+    let mut body = crc32::Reader::for_body(archive.take(header.file_size), header.flags);
+    body.read_to_end(&mut comment)
+        .map_err(|e| io_err(e).at(body_start))?;
+    if comment.len() as u64 != header.file_size {
+        log::error!("Comment cut off after {}B", comment.len());
+        return Err(DecodeError::Crop {
+            offset: Some(body_start + comment.len() as u64),
+        });
+    }
+    let body_checksum = body.digest();
+
+    let footer_start = body_start + header.file_size;
+    let footer = read_footer(archive).map_err(|e| e.at(footer_start))?;
+    check_footer(&header, &footer).map_err(|e| e.at(footer_start))?;
+    if footer.body_checksum != body_checksum {
+        log::error!("Comment checksum mismatch");
+        return Err(DecodeError::Checksum {
+            offset: Some(body_start),
+        });
+    }
+    Ok(Some(String::from_utf8_lossy(&comment).into_owned()))
+}
+
+#[test]
+fn comment_roundtrips() {
+    let mut archiver = crate::Archiver::new(vec![]).index(true).build();
+    archiver.set_comment("nightly backup 2024-01").unwrap();
+    archiver
+        .add_reader("a.txt", &mut &b"abc"[..], 3, 0)
+        .unwrap();
+    assert_eq!(
+        archiver.set_comment("too late").unwrap_err().kind(),
+        io::ErrorKind::InvalidInput
+    );
+    let archive = archiver.finish().unwrap();
+
+    let mut cursor = io::Cursor::new(&archive);
+    assert_eq!(
+        read_comment(&mut cursor),
+        Ok(Some("nightly backup 2024-01".into()))
+    );
+    assert_eq!(Entries::new(&mut cursor).count(), 1);
+
+    let entries = list(&mut io::Cursor::new(&archive)).unwrap();
+    assert_eq!(entries.len(), 1);
+    assert!(entries[0].path.ends_with("a.txt"));
+    assert_eq!(
+        read_stream(&mut archive.as_slice(), &mut LogObserver),
+        Ok(())
+    );
+    assert_eq!(verify(&mut io::Cursor::new(&archive)).unwrap().ok, 1);
+    let indexed = crate::open_indexed(io::Cursor::new(&archive)).unwrap();
+    assert_eq!(indexed.entries().len(), 1);
+
+    let mut plain = vec![];
+    crate::append_reader(&mut plain, "a.txt", &mut &b"abc"[..], 3, 0).unwrap();
+    let mut cursor = io::Cursor::new(&plain);
+    assert_eq!(read_comment(&mut cursor), Ok(None));
+    assert_eq!(cursor.position(), 0);
+}
+
+/// Describes the first entry of the archive from its header and path alone,
+/// e.g. to recognize a file as an archive or to preview it.
+///
+/// Neither the body nor the footer are read, so the entry is only checked
+/// against its header checksum. A leading comment is skipped. Afterwards
+/// `archive` is back where it was, also on failure, and an archive without
+/// entries fails with [`DecodeError::Exhausted`].
+pub fn peek<R: Read + Seek>(archive: &mut R) -> Result<EntryInfo, DecodeError> {
+    let start = position(archive)?;
+    let entry = peek_from(archive, start);
+    archive.seek(io::SeekFrom::Start(start)).map_err(|e| {
+        log::error!("Failed to seek back to the start: {e:?}");
+        DecodeError::Crop {
+            offset: Some(start),
+        }
+    })?;
+    entry
+}
+
+fn peek_from<R: Read + Seek>(archive: &mut R, start: u64) -> Result<EntryInfo, DecodeError> {
+    let mut offset = start;
+    loop {
+        let at = |e: DecodeError| {
+            let e = e.at(offset);
+            if offset == start {
+                e
+            } else {
+                e.mid_archive()
+            }
+        };
+        let header = read_header(archive).map_err(at)?;
+        let path = read_path(&header, archive).map_err(at)?;
+        xattrs::read(&header, &path, archive).map_err(at)?;
+        if !header.flags.is_special() {
+            return Ok(EntryInfo::from_header(
+                &header,
+                platform::bytes_to_path(&path),
+            ));
+        }
+
+        let skip = header.file_size + Metadata::SIZE as u64;
+        offset = archive
+            .seek(io::SeekFrom::Current(skip as _))
+            .map_err(|e| {
+                log::error!("Failed to seek past the comment: {e:?}");
+                DecodeError::Crop {
+                    offset: Some(offset),
+                }
+            })?;
+    }
+}
+
+#[test]
+fn peek_restores_position() {
+    let mut archiver = crate::Archiver::new(vec![0u8; 7]).index(true).build();
+    archiver.set_comment("preview").unwrap();
+    archiver
+        .add_reader("a.txt", &mut &b"abc"[..], 3, 1_700_000_000)
+        .unwrap();
+    archiver
+        .add_reader("b.txt", &mut &b"defg"[..], 4, 0)
+        .unwrap();
+    let archive = archiver.finish().unwrap();
+
+    let mut cursor = io::Cursor::new(&archive);
+    cursor.set_position(7);
+    let first = peek(&mut cursor).unwrap();
+    assert_eq!(first.path, Path::new("a.txt"));
+    assert_eq!((first.size, first.modified_at_secs()), (3, 1_700_000_000));
+    assert_eq!(cursor.position(), 7);
+
+    // a subsequent full read sees the whole archive.
+    assert_eq!(list(&mut cursor).unwrap().len(), 2);
+
+    let mut cursor = io::Cursor::new(&archive[..7]);
+    cursor.set_position(7);
+    assert_eq!(peek(&mut cursor).unwrap_err(), DecodeError::Exhausted);
+    let mut noise = io::Cursor::new(vec![0xA5u8; 100]);
+    noise.set_position(3);
+    assert_eq!(
+        peek(&mut noise).unwrap_err(),
+        DecodeError::NotAnArchive { offset: Some(3) }
+    );
+    assert_eq!(noise.position(), 3);
+}
+
+/// Whether `archive` starts with the header of a bitumen archive, e.g. to
+/// auto-detect the format of an arbitrary file.
+///
+/// The magic number isn't at the very start of a header, so the bytes up to
+/// and including it are read. Nothing else is checked, use [`peek`] to decode
+/// the first entry. Afterwards `archive` is back where it was.
+pub fn is_bitumen_archive<R: Read + Seek>(archive: &mut R) -> io::Result<bool> {
+    let start = archive.stream_position()?;
+    let mut bytes = [0u8; Metadata::MAGIC_OFFSET + 4];
+    let found = match archive.read_exact(&mut bytes) {
+        Ok(()) => bytes[Metadata::MAGIC_OFFSET..] == MAGIC.to_le_bytes(),
+        Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => false,
+        Err(e) => return Err(e),
+    };
+    archive.seek(io::SeekFrom::Start(start))?;
+    Ok(found)
+}
+
+#[test]
+fn detects_archives() {
+    let mut archive = vec![];
+    crate::append_reader(&mut archive, "a.txt", &mut &b"abc"[..], 3, 0).unwrap();
+    let mut cursor = io::Cursor::new(&archive);
+    assert!(is_bitumen_archive(&mut cursor).unwrap());
+    assert_eq!(cursor.position(), 0);
+
+    let mut state = 0x2545_f491u32;
+    let noise = (0..1000)
+        .map(|_| {
+            state = state.wrapping_mul(1_103_515_245).wrapping_add(12345);
+            (state >> 24) as u8
+        })
+        .collect::<Vec<_>>();
+    let mut cursor = io::Cursor::new(&noise);
+    cursor.set_position(10);
+    assert!(!is_bitumen_archive(&mut cursor).unwrap());
+    assert_eq!(cursor.position(), 10);
+
+    assert!(!is_bitumen_archive(&mut io::Cursor::new(&archive[..20])).unwrap());
+    assert!(!is_bitumen_archive(&mut io::Cursor::new(vec![])).unwrap());
+}
+
+/// Collects a description of every entry in the archive.
+pub fn list<R: Read + Seek>(archive: &mut R) -> Result<Vec<EntryInfo>, DecodeError> {
+    Entries::new(archive)
+        .map(|entry| entry.map(EntryInfo::from))
+        .collect()
+}
+
+/// Whether any entry of the archive is stored as `path`.
+///
+/// Stops at the first match, bodies are skipped by seeking.
+pub fn contains<R: Read + Seek>(
+    archive: &mut R,
+    path: impl AsRef<Path>,
+) -> Result<bool, DecodeError> {
+    for entry in Entries::new(archive) {
+        if entry?.path() == path.as_ref() {
+            return Ok(true);
+        }
+    }
+    Ok(false)
+}
+
+#[test]
+fn contains_finds_stored_paths() {
+    let mut archive = vec![];
+    crate::append_reader(&mut archive, "dir/a.txt", &mut &b"abc"[..], 3, 0).unwrap();
+    crate::append_reader(&mut archive, "dir/b.txt", &mut &b"defg"[..], 4, 0).unwrap();
+
+    assert_eq!(
+        contains(&mut io::Cursor::new(&archive), "dir/b.txt"),
+        Ok(true)
+    );
+    assert_eq!(
+        contains(&mut io::Cursor::new(&archive), "dir/c.txt"),
+        Ok(false)
+    );
+    assert_eq!(contains(&mut io::Cursor::new(&archive), "b.txt"), Ok(false));
+}
+
+#[test]
+fn list_describes_entries() {
+    let dir = tempfile::tempdir().unwrap();
+    std::fs::write(dir.path().join("a.txt"), b"abc").unwrap();
+
+    let mut archive = vec![];
+    recursive_archive(&mut archive, dir.path()).unwrap();
+    let entries = list(&mut io::Cursor::new(archive)).unwrap();
+
+    assert_eq!(entries.len(), 2);
+    assert_eq!(entries[0].kind, EntryKind::Directory);
+    assert_eq!(entries[0].path, dir.path());
+    assert_eq!(entries[1].kind, EntryKind::File);
+    assert_eq!(entries[1].size, 3);
+    assert!(entries[1].path.ends_with("a.txt"));
+}
+
+#[test]
+fn read_rejects_mismatched_footer() {
+    let dir = tempfile::tempdir().unwrap();
+    let file = dir.path().join("a.txt");
+    std::fs::write(&file, b"abc").unwrap();
+
+    let mut archive = vec![];
+    append_to_archive(&mut archive, &file).unwrap();
+
+    let footer_start = archive.len() - Metadata::SIZE;
+    let mut footer = read_meta("Footer", &mut io::Cursor::new(&archive[footer_start..])).unwrap();
+    footer.file_size += 1;
+    footer.set_checksum();
+    archive.truncate(footer_start);
+    archive.extend_from_slice(&footer.to_bytes());
+
+    assert_eq!(
+        read(&mut io::Cursor::new(&archive), &mut LogObserver),
+        Err(DecodeError::Footer {
+            offset: Some(footer_start as u64)
+        })
+    );
+}
+
+#[test]
+fn read_rejects_absurd_sizes() {
+    let path = b"huge.bin";
+    let mut header =
+        Metadata::new(flags::FILE, u64::MAX, path.len() as _, 0, 0, 0, 0).header(path, &[]);
+    let mut archive = header.to_bytes().to_vec();
+    archive.extend_from_slice(path);
+    assert_eq!(
+        read(&mut io::Cursor::new(&archive), &mut LogObserver),
+        Err(DecodeError::Header { offset: Some(0) })
+    );
+
+    header.file_size = 0;
+    header.path_len = u16::MAX;
+    header.set_checksum();
+    assert_eq!(
+        read(&mut io::Cursor::new(header.to_bytes()), &mut LogObserver),
+        Err(DecodeError::Header { offset: Some(0) })
+    );
+}
+
+#[test]
+fn read_checks_format_version() {
+    let dir = tempfile::tempdir().unwrap();
+    let file = dir.path().join("a.txt");
+    std::fs::write(&file, b"abc").unwrap();
+
+    let mut archive = vec![];
+    append_to_archive(&mut archive, &file).unwrap();
+    let entries = list(&mut io::Cursor::new(&archive)).unwrap();
+    assert_eq!(entries.len(), 1);
+
+    let mut header = read_meta("Header", &mut archive.as_slice()).unwrap();
+    assert_eq!(header.version, FORMAT_VERSION);
+    header.version = FORMAT_VERSION + 1;
+    let path = &archive[Metadata::SIZE..][..header.path_len as usize];
+    header.checksum = header.compute_header_checksum(path, &[]);
+    archive[..Metadata::SIZE].copy_from_slice(&header.to_bytes());
+    assert_eq!(
+        read(&mut io::Cursor::new(&archive), &mut LogObserver),
+        Err(DecodeError::Version { offset: Some(0) })
+    );
+}
+
+#[test]
+fn read_rejects_unknown_kind() {
+    let mut header = Metadata {
+        magic: MAGIC,
+        flags: Flags::new(0x7 | flags::HEADER),
+        version: FORMAT_VERSION,
+        ..Default::default()
+    };
+    header.set_checksum();
+    assert_eq!(
+        header.validate_kind(),
+        Err(DecodeError::Header { offset: None })
+    );
+    assert_eq!(
+        read(&mut io::Cursor::new(header.to_bytes()), &mut LogObserver),
+        Err(DecodeError::Header { offset: Some(0) })
+    );
+
+    header.flags = Flags::new(flags::HARD_LINK | flags::HEADER);
+    assert_eq!(header.validate_kind(), Ok(()));
+}
+
+#[test]
+fn read_detects_foreign_data() {
+    let mut noise = vec![0u8; 1000];
+    let mut state = 0x1234_5678u32;
+    for byte in noise.iter_mut() {
+        state = state.wrapping_mul(1_103_515_245).wrapping_add(12345);
+        *byte = (state >> 24) as u8;
+    }
+    assert_eq!(
+        read(&mut io::Cursor::new(&noise), &mut LogObserver),
+        Err(DecodeError::NotAnArchive { offset: Some(0) })
+    );
+    assert_eq!(
+        read_stream(&mut noise.as_slice(), &mut LogObserver),
+        Err(DecodeError::NotAnArchive { offset: Some(0) })
+    );
+    let dest = tempfile::tempdir().unwrap();
+    let err = extract(&mut io::Cursor::new(&noise), dest.path()).unwrap_err();
+    assert_eq!(err.to_string(), "not a bitumen archive at offset 0");
+
+    // a wrong magic further in is plain corruption.
+    let dir = tempfile::tempdir().unwrap();
+    std::fs::write(dir.path().join("a.txt"), b"abc").unwrap();
+    let mut archive = vec![];
+    recursive_archive(&mut archive, dir.path()).unwrap();
+    let trailer_start = archive.len() - trailer::LEN;
+    archive.extend_from_slice(&noise);
+    assert_eq!(
+        read(&mut io::Cursor::new(&archive), &mut LogObserver),
+        Err(DecodeError::Header {
+            offset: Some(trailer_start as u64)
+        })
+    );
+}
+
+#[test]
+fn read_reports_corruption() {
+    let dir = tempfile::tempdir().unwrap();
+    let file = dir.path().join("a.txt");
+    std::fs::write(&file, b"abc").unwrap();
+
+    let mut archive = vec![];
+    append_to_archive(&mut archive, &file).unwrap();
+    assert_eq!(
+        read(&mut io::Cursor::new(&archive), &mut LogObserver),
+        Ok(())
+    );
+
+    archive[0] ^= 0x01;
+    assert_eq!(
+        read(&mut io::Cursor::new(&archive), &mut LogObserver),
+        Err(DecodeError::Checksum { offset: Some(0) })
+    );
+}
+
+#[test]
+fn read_distinguishes_end_from_truncation() {
+    let mut archive = vec![];
+    crate::append_reader(&mut archive, "a.txt", &mut &b"abc"[..], 3, 0).unwrap();
+    crate::append_reader(&mut archive, "b.txt", &mut &b"defg"[..], 4, 0).unwrap();
+    let first_len = 2 * Metadata::SIZE + "a.txt".len() + 3;
+    assert_eq!(list(&mut io::Cursor::new(&archive)).unwrap().len(), 2);
+
+    // the second header stops short of its 40 bytes.
+    let cut = &archive[..first_len + 17];
+    assert_eq!(
+        read(&mut io::Cursor::new(cut), &mut LogObserver),
+        Err(DecodeError::Crop {
+            offset: Some(first_len as u64)
+        })
+    );
+    // the second entry lacks its footer entirely.
+    let cut = &archive[..archive.len() - Metadata::SIZE];
+    assert_eq!(
+        read_stream(&mut &cut[..], &mut LogObserver),
+        Err(DecodeError::Crop {
+            offset: Some(cut.len() as u64)
+        })
+    );
+    assert_eq!(
+        read(
+            &mut io::Cursor::new(&archive[..first_len]),
+            &mut LogObserver
+        ),
+        Ok(())
+    );
+}
+
+#[test]
+fn read_rejects_swapped_metadata() {
+    let dir = tempfile::tempdir().unwrap();
+    let file = dir.path().join("a.txt");
+    std::fs::write(&file, b"abc").unwrap();
+
+    let mut archive = vec![];
+    append_to_archive(&mut archive, &file).unwrap();
+    let footer_start = archive.len() - Metadata::SIZE;
+    let header = archive[..Metadata::SIZE].to_vec();
+    let footer = archive[footer_start..].to_vec();
+
+    let mut swapped = archive.clone();
+    swapped[..Metadata::SIZE].copy_from_slice(&footer);
+    swapped[footer_start..].copy_from_slice(&header);
+    assert_eq!(
+        read(&mut io::Cursor::new(&swapped), &mut LogObserver),
+        Err(DecodeError::Header { offset: Some(0) })
+    );
+
+    let mut duplicated = archive;
+    duplicated[footer_start..].copy_from_slice(&header);
+    assert_eq!(
+        read(&mut io::Cursor::new(&duplicated), &mut LogObserver),
+        Err(DecodeError::Footer {
+            offset: Some(footer_start as u64)
+        })
+    );
+    assert_eq!(
+        read_stream(&mut duplicated.as_slice(), &mut LogObserver),
+        Err(DecodeError::Footer {
+            offset: Some(footer_start as u64)
+        })
+    );
+}
+
+#[test]
+fn read_detects_corrupt_path() {
+    let dir = tempfile::tempdir().unwrap();
+    let file = dir.path().join("a.txt");
+    std::fs::write(&file, b"abc").unwrap();
+
+    let mut archive = vec![];
+    append_to_archive(&mut archive, &file).unwrap();
+    let mut corrupt = archive.clone();
+    corrupt[Metadata::SIZE + 1] ^= 0x01;
+    assert_eq!(
+        read(&mut io::Cursor::new(&corrupt), &mut LogObserver),
+        Err(DecodeError::Checksum { offset: Some(0) })
+    );
+    assert_eq!(
+        read_stream(&mut corrupt.as_slice(), &mut LogObserver),
+        Err(DecodeError::Checksum { offset: Some(0) })
+    );
+    assert_eq!(verify(&mut io::Cursor::new(&corrupt)).unwrap().corrupt, 1);
+
+    // version 1 headers don't cover the path and remain readable.
+    let mut header = read_meta("Header", &mut archive.as_slice()).unwrap();
+    header.version = 1;
+    header.set_checksum();
+    archive[..Metadata::SIZE].copy_from_slice(&header.to_bytes());
+    let mut footer = Metadata::from_bytes(&archive[archive.len() - Metadata::SIZE..]).unwrap();
+    footer.version = 1;
+    footer.set_checksum();
+    let footer_start = archive.len() - Metadata::SIZE;
+    archive[footer_start..].copy_from_slice(&footer.to_bytes());
+    assert_eq!(
+        read(&mut io::Cursor::new(&archive), &mut LogObserver),
+        Ok(())
+    );
+}
+
+#[test]
+fn errors_report_offset_of_corruption() {
+    let mut archive = vec![];
+    for (name, body) in [("a.txt", "abc"), ("b.txt", "defgh"), ("c.txt", "ij")] {
+        crate::append_reader(
+            &mut archive,
+            name,
+            &mut body.as_bytes(),
+            body.len() as u64,
+            0,
+        )
+        .unwrap();
+    }
+    let second = (2 * Metadata::SIZE + "a.txt".len() + 3) as u64;
+    let third = second + (2 * Metadata::SIZE + "b.txt".len() + 5) as u64;
+
+    // a body is found corrupt at its footer, but reported where it starts.
+    let mut corrupt = archive.clone();
+    let body_start = second + (Metadata::SIZE + "b.txt".len()) as u64;
+    corrupt[body_start as usize + 2] ^= 0x01;
+    let err = read_stream(&mut corrupt.as_slice(), &mut LogObserver).unwrap_err();
+    assert_eq!(err.offset(), Some(body_start));
+    assert_eq!(
+        err.to_string(),
+        format!("checksum mismatch at offset {body_start}")
+    );
+    let err = crate::extract_one(&mut io::Cursor::new(&corrupt), "b.txt", &mut vec![]).unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        format!("checksum mismatch at offset {body_start}")
+    );
+
+    let mut corrupt = archive.clone();
+    corrupt[third as usize + 5] ^= 0x01;
+    let err = read(&mut io::Cursor::new(&corrupt), &mut LogObserver).unwrap_err();
+    assert_eq!(err.offset(), Some(third));
+
+    let mut corrupt = archive.clone();
+    let footer_start = archive.len() as u64 - Metadata::SIZE as u64;
+    corrupt[footer_start as usize] ^= 0x01;
+    let err = read(&mut io::Cursor::new(&corrupt), &mut LogObserver).unwrap_err();
+    assert_eq!(err.offset(), Some(footer_start));
+
+    // verifying reports the entry that couldn't be copied, or the trailer.
+    struct Full;
+    impl io::Write for Full {
+        fn write(&mut self, _: &[u8]) -> io::Result<usize> {
+            Err(io::ErrorKind::StorageFull.into())
+        }
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+    let err = crate::repack(&mut io::Cursor::new(&archive), &mut Full).unwrap_err();
+    assert_eq!(err.offset(), Some(0));
+
+    let mut archiver = crate::Archiver::new(vec![]).trailer(true).build();
+    archiver
+        .add_reader("a.txt", &mut &b"abc"[..], 3, 0)
+        .unwrap();
+    let mut corrupt = archiver.finish().unwrap();
+    assert!(crate::verify(&mut io::Cursor::new(&corrupt)).is_ok());
+    let trailer_start = corrupt.len() - crate::trailer::LEN;
+    corrupt[trailer_start - 1] ^= 0x01;
+    let err = crate::verify(&mut io::Cursor::new(&corrupt)).unwrap_err();
+    assert_eq!(err.offset(), Some(trailer_start as u64));
+}
@@ -0,0 +1,485 @@
+//! Destinations of extraction, a directory or any other, see [`FileSink`].
+
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    ffi::OsString,
+    fs::File,
+    io::{self, Read, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    extract::{
+        check_contained, extract_into, restore_mtime, restore_owner, restore_perms, ExtractOptions,
+    },
+    flags, platform, sparse, xattrs, EntryKind,
+};
+
+/// Destination of [`extract_to_sink`], and of [`extract`](crate::extract)
+/// through [`RealFs`].
+///
+/// Paths are the archived ones made relative, with root components dropped
+/// and parent directory components rejected beforehand. Objects are replaced
+/// if they exist, the [`OverwritePolicy`](crate::OverwritePolicy) is applied
+/// before.
+///
+/// Only directories, files and symlinks have to be supported, other objects are
+/// skipped with a warning and metadata isn't restored unless a sink overrides
+/// the provided methods.
+pub trait FileSink {
+    /// Where the contents of a file are written.
+    type File: Write;
+
+    /// Creates the directory at `path` and any missing parents.
+    fn create_dir(&mut self, path: &Path) -> io::Result<()>;
+
+    /// Creates the file at `path` to write its contents to.
+    fn create_file(&mut self, path: &Path) -> io::Result<Self::File>;
+
+    /// Completes the file created at `path`, once all of its contents are
+    /// written and their checksum is verified.
+    fn finish_file(&mut self, path: &Path, file: Self::File) -> io::Result<()>;
+
+    /// Creates a symlink at `path` pointing to `target`.
+    fn create_symlink(&mut self, path: &Path, target: &Path) -> io::Result<()>;
+
+    /// Whether an object exists at `path`.
+    fn exists(&mut self, path: &Path) -> bool;
+
+    /// Skips `len` zero bytes of a sparse file, writing them unless the sink
+    /// supports holes.
+    fn write_hole(&mut self, file: &mut Self::File, len: u64) -> io::Result<()> {
+        io::copy(&mut io::repeat(0).take(len), file)?;
+        Ok(())
+    }
+
+    /// Largest original size of a sparse file that is restored, holes included.
+    ///
+    /// Holes come straight from the archive and [`write_hole`](Self::write_hole)
+    /// writes them out by default, so files beyond 1 TiB are rejected unless a
+    /// sink supporting holes allows more.
+    fn max_sparse_len(&self) -> u64 {
+        sparse::MAX_EXPANDED_LEN
+    }
+
+    /// Creates a hard link at `path` to the object at `original`.
+    fn create_hard_link(&mut self, path: &Path, original: &Path) -> io::Result<()> {
+        let _ = original;
+        log::warn!("Skipping unsupported hard link: {}", path.display());
+        Ok(())
+    }
+
+    /// Creates a FIFO or device of `kind` at `path`.
+    fn create_device(
+        &mut self,
+        path: &Path,
+        kind: EntryKind,
+        perms: u16,
+        device: u64,
+    ) -> io::Result<()> {
+        let _ = (perms, device);
+        log::warn!("Skipping unsupported {kind}: {}", path.display());
+        Ok(())
+    }
+
+    /// Sets the permission bits of the object at `path`, `0` if they weren't archived.
+    fn set_permissions(&mut self, path: &Path, perms: u16) -> io::Result<()> {
+        let _ = (path, perms);
+        Ok(())
+    }
+
+    /// Sets the modification time of the object at `path`, in seconds since the epoch.
+    fn set_modified(&mut self, path: &Path, modified_at: u64) -> io::Result<()> {
+        let _ = (path, modified_at);
+        Ok(())
+    }
+
+    /// Changes the owner and group of the object at `path`.
+    fn set_owner(&mut self, path: &Path, owner: u16, group: u16) -> io::Result<()> {
+        let _ = (path, owner, group);
+        Ok(())
+    }
+
+    /// Sets the extended attributes of the object at `path`, as names and values.
+    fn set_xattrs(&mut self, path: &Path, xattrs: &[(Vec<u8>, Vec<u8>)]) -> io::Result<()> {
+        let _ = (path, xattrs);
+        Ok(())
+    }
+}
+
+/// Writes below a directory of the real filesystem, as [`extract`](crate::extract) does.
+#[derive(Clone, Debug)]
+pub struct RealFs {
+    root: PathBuf,
+}
+
+impl RealFs {
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        Self { root: root.into() }
+    }
+
+    /// Location of `path` below the root, refusing to follow archived symlinks out of it.
+    fn located(&self, path: &Path) -> io::Result<PathBuf> {
+        let target = self.root.join(path);
+        check_contained(&self.root, &target)?;
+        Ok(target)
+    }
+
+    /// Location of a new object at `path`, with its parents created.
+    fn target(&self, path: &Path) -> io::Result<PathBuf> {
+        let target = self.located(path)?;
+        if let Some(parent) = target.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        Ok(target)
+    }
+
+    /// Like [`target`](Self::target), removing an existing object.
+    fn replace(&self, path: &Path) -> io::Result<PathBuf> {
+        let target = self.target(path)?;
+        // removing rather than truncating doesn't write through symlinks.
+        if target.symlink_metadata().is_ok() {
+            std::fs::remove_file(&target)?;
+        }
+        Ok(target)
+    }
+}
+
+/// A file being extracted by [`RealFs`].
+///
+/// Its contents are written to a temporary file next to it, which only takes
+/// its place once they are verified and is removed if that never happens.
+#[derive(Debug)]
+pub struct PartialFile {
+    file: File,
+    /// the temporary file, until it's moved into place.
+    temp: Option<PathBuf>,
+    target: PathBuf,
+}
+
+impl PartialFile {
+    fn create(target: PathBuf) -> io::Result<Self> {
+        let name = target.file_name().unwrap_or_default();
+        let mut attempt = 0u32;
+        loop {
+            let mut temp = OsString::from(".");
+            temp.push(name);
+            temp.push(format!(".{attempt}.partial"));
+            let temp = target.with_file_name(temp);
+            match File::options().write(true).create_new(true).open(&temp) {
+                Ok(file) => {
+                    return Ok(Self {
+                        file,
+                        temp: Some(temp),
+                        target,
+                    })
+                }
+                Err(e) if e.kind() == io::ErrorKind::AlreadyExists => attempt += 1,
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    /// Moves the contents into place, replacing but not following what's there.
+    fn persist(mut self) -> io::Result<()> {
+        // a trailing hole isn't written, so it has to be added explicitly.
+        let len = self.file.stream_position()?;
+        self.file.set_len(len)?;
+        if let Some(temp) = &self.temp {
+            std::fs::rename(temp, &self.target)?;
+            self.temp = None;
+        }
+        Ok(())
+    }
+}
+
+impl Write for PartialFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.file.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
+
+impl Drop for PartialFile {
+    fn drop(&mut self) {
+        if let Some(temp) = &self.temp {
+            if let Err(e) = std::fs::remove_file(temp) {
+                log::warn!("Failed to remove {}: {e}", temp.display());
+            }
+        }
+    }
+}
+
+impl FileSink for RealFs {
+    type File = PartialFile;
+
+    /// Refuses to use anything but a real directory at `path`, `create_dir_all`
+    /// would go through an archived symlink.
+    fn create_dir(&mut self, path: &Path) -> io::Result<()> {
+        let target = self.target(path)?;
+        match target.symlink_metadata() {
+            Ok(meta) if meta.is_dir() => Ok(()),
+            Ok(_) => Err(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                format!("{} exists and isn't a directory", target.display()),
+            )),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => std::fs::create_dir(target),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Existing objects are only replaced once the file is finished.
+    fn create_file(&mut self, path: &Path) -> io::Result<PartialFile> {
+        PartialFile::create(self.target(path)?)
+    }
+
+    fn finish_file(&mut self, _path: &Path, file: PartialFile) -> io::Result<()> {
+        file.persist()
+    }
+
+    fn create_symlink(&mut self, path: &Path, target: &Path) -> io::Result<()> {
+        let link = self.replace(path)?;
+        platform::symlink(&platform::path_to_bytes(target), &link)
+    }
+
+    fn exists(&mut self, path: &Path) -> bool {
+        self.root.join(path).symlink_metadata().is_ok()
+    }
+
+    fn write_hole(&mut self, file: &mut PartialFile, len: u64) -> io::Result<()> {
+        file.file.seek(SeekFrom::Current(len as _))?;
+        Ok(())
+    }
+
+    /// Holes are skipped, so any size a file can have is allowed.
+    fn max_sparse_len(&self) -> u64 {
+        sparse::MAX_LEN
+    }
+
+    fn create_hard_link(&mut self, path: &Path, original: &Path) -> io::Result<()> {
+        let original = self.located(original)?;
+        std::fs::hard_link(original, self.replace(path)?)
+    }
+
+    fn create_device(
+        &mut self,
+        path: &Path,
+        kind: EntryKind,
+        perms: u16,
+        device: u64,
+    ) -> io::Result<()> {
+        let target = self.replace(path)?;
+        let kind = match kind {
+            EntryKind::Fifo => flags::FIFO,
+            EntryKind::CharDevice => flags::CHAR_DEV,
+            EntryKind::BlockDevice => flags::BLOCK_DEV,
+            _ => return Err(io::ErrorKind::InvalidInput.into()),
+        };
+        match platform::mknod(&target, kind, perms, device) {
+            Ok(()) => restore_perms(&target, perms),
+            // only privileged processes may create devices.
+            Err(e) if e.kind() == io::ErrorKind::PermissionDenied => {
+                log::warn!("Not permitted to create {}", target.display());
+                Ok(())
+            }
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Refuses to follow a symlink that has taken the place of the object since.
+    fn set_permissions(&mut self, path: &Path, perms: u16) -> io::Result<()> {
+        restore_perms(&self.located(path)?, perms)
+    }
+
+    fn set_modified(&mut self, path: &Path, modified_at: u64) -> io::Result<()> {
+        restore_mtime(&self.located(path)?, modified_at)
+    }
+
+    fn set_owner(&mut self, path: &Path, owner: u16, group: u16) -> io::Result<()> {
+        restore_owner(&self.located(path)?, owner, group)
+    }
+
+    fn set_xattrs(&mut self, path: &Path, xattrs: &[(Vec<u8>, Vec<u8>)]) -> io::Result<()> {
+        xattrs::set(&self.located(path)?, xattrs)
+    }
+}
+
+/// Keeps extracted objects in memory, e.g. for tests or platforms without a filesystem.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct MemFs {
+    dirs: BTreeSet<PathBuf>,
+    files: BTreeMap<PathBuf, Vec<u8>>,
+    symlinks: BTreeMap<PathBuf, PathBuf>,
+}
+
+impl MemFs {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Whether a directory was created at `path`.
+    pub fn is_dir(&self, path: impl AsRef<Path>) -> bool {
+        self.dirs.contains(path.as_ref())
+    }
+
+    /// Contents of the file at `path`.
+    pub fn file(&self, path: impl AsRef<Path>) -> Option<&[u8]> {
+        self.files.get(path.as_ref()).map(Vec::as_slice)
+    }
+
+    /// Target of the symlink at `path`.
+    pub fn symlink(&self, path: impl AsRef<Path>) -> Option<&Path> {
+        self.symlinks.get(path.as_ref()).map(PathBuf::as_path)
+    }
+
+    /// Paths of all files, in lexical order.
+    pub fn files(&self) -> impl Iterator<Item = &Path> {
+        self.files.keys().map(PathBuf::as_path)
+    }
+}
+
+impl FileSink for MemFs {
+    type File = Vec<u8>;
+
+    fn create_dir(&mut self, path: &Path) -> io::Result<()> {
+        self.dirs.extend(path.ancestors().map(Path::to_path_buf));
+        Ok(())
+    }
+
+    /// Files only appear once finished, so corrupt contents are never kept.
+    fn create_file(&mut self, _path: &Path) -> io::Result<Vec<u8>> {
+        Ok(vec![])
+    }
+
+    fn finish_file(&mut self, path: &Path, file: Vec<u8>) -> io::Result<()> {
+        self.symlinks.remove(path);
+        self.files.insert(path.into(), file);
+        Ok(())
+    }
+
+    fn create_symlink(&mut self, path: &Path, target: &Path) -> io::Result<()> {
+        self.files.remove(path);
+        self.symlinks.insert(path.into(), target.into());
+        Ok(())
+    }
+
+    fn exists(&mut self, path: &Path) -> bool {
+        self.dirs.contains(path)
+            || self.files.contains_key(path)
+            || self.symlinks.contains_key(path)
+    }
+}
+
+/// Restores the objects of the archive into `sink`, like [`extract`](crate::extract)
+/// does below a directory.
+///
+/// Encrypted files can't be restored this way, see
+/// [`extract_with_options`](crate::extract_with_options).
+pub fn extract_to_sink<R: Read + Seek>(
+    archive: &mut R,
+    sink: &mut impl FileSink,
+) -> io::Result<()> {
+    extract_into(archive, sink, &ExtractOptions::default(), &mut |_, _, _| {})
+}
+
+#[cfg(unix)]
+#[test]
+fn extract_into_memory() {
+    let src = tempfile::tempdir().unwrap();
+    std::fs::create_dir(src.path().join("sub")).unwrap();
+    std::fs::write(src.path().join("sub/a.txt"), b"hello sink").unwrap();
+    std::fs::write(src.path().join("empty.txt"), b"").unwrap();
+    std::os::unix::fs::symlink("sub/a.txt", src.path().join("link")).unwrap();
+
+    let mut archiver = crate::Archiver::new(vec![])
+        .strip_prefix(src.path())
+        .sparse(true)
+        .build();
+    archiver.add_path(src.path()).unwrap();
+    let archive = archiver.finish().unwrap();
+
+    let mut fs = MemFs::new();
+    extract_to_sink(&mut io::Cursor::new(&archive), &mut fs).unwrap();
+    assert!(fs.is_dir("sub"));
+    assert_eq!(fs.file("sub/a.txt"), Some(&b"hello sink"[..]));
+    assert_eq!(fs.file("empty.txt"), Some(&b""[..]));
+    assert_eq!(fs.symlink("link"), Some(Path::new("sub/a.txt")));
+    assert_eq!(fs.files().count(), 2);
+
+    let dest = tempfile::tempdir().unwrap();
+    extract_to_sink(
+        &mut io::Cursor::new(&archive),
+        &mut RealFs::new(dest.path()),
+    )
+    .unwrap();
+    assert_eq!(
+        std::fs::read(dest.path().join("link")).unwrap(),
+        b"hello sink"
+    );
+    // the real filesystem gets the metadata extract restores as well.
+    let restored = std::fs::metadata(dest.path().join("sub/a.txt")).unwrap();
+    let archived = std::fs::metadata(src.path().join("sub/a.txt")).unwrap();
+    assert_eq!(
+        restored
+            .modified()
+            .unwrap()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap()
+            .as_secs(),
+        archived
+            .modified()
+            .unwrap()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap()
+            .as_secs(),
+    );
+
+    // a directory entry doesn't follow an archived symlink of the same name.
+    let outside = tempfile::tempdir().unwrap();
+    let dest = tempfile::tempdir().unwrap();
+    std::os::unix::fs::symlink(outside.path(), dest.path().join("sub")).unwrap();
+    let mut fs = RealFs::new(dest.path());
+    assert_eq!(
+        fs.create_dir(Path::new("sub")).unwrap_err().kind(),
+        io::ErrorKind::AlreadyExists
+    );
+    assert!(fs.set_permissions(Path::new("sub"), 0o777).is_err());
+    let err = extract_to_sink(&mut io::Cursor::new(&archive), &mut fs).unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::AlreadyExists);
+    assert_eq!(std::fs::read_dir(outside.path()).unwrap().count(), 0);
+
+    let mut corrupt = archive.clone();
+    let pos = corrupt
+        .windows(10)
+        .position(|w| w == b"hello sink")
+        .unwrap();
+    corrupt[pos] ^= 0x01;
+    let mut fs = MemFs::new();
+    assert!(extract_to_sink(&mut io::Cursor::new(&corrupt), &mut fs).is_err());
+    assert_eq!(fs.file("sub/a.txt"), None);
+}
+
+#[test]
+fn memory_sink_rejects_huge_holes() {
+    // a single 2 TiB hole, which `MemFs` would have to hold as zeros.
+    let mut body = (2u64 << 40).to_le_bytes().to_vec();
+    body.extend(0u64.to_le_bytes());
+    let path = b"huge.bin";
+    let meta = crate::Metadata::new(flags::FILE | flags::SPARSE, body.len() as _, 8, 0, 0, 0, 0);
+    let mut archive = meta.header(path, &[]).to_vec();
+    archive.extend(path);
+    archive.extend(&body);
+    archive.extend(meta.footer(crate::crc32::digest(&body)).to_vec());
+
+    let mut fs = MemFs::new();
+    let err = extract_to_sink(&mut io::Cursor::new(&archive), &mut fs).unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    assert!(matches!(
+        crate::decode_err(err),
+        crate::DecodeError::TooLarge { .. }
+    ));
+    assert_eq!(fs.file("huge.bin"), None);
+}
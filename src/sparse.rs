@@ -0,0 +1,270 @@
+//! Bodies of files flagged with [`flags::SPARSE`](crate::flags::SPARSE),
+//! which leave out runs of zeros.
+//!
+//! The body is a sequence of records, each a little-endian `u64` hole length
+//! and `u64` data length followed by the data. A trailing hole is stored as
+//! a record without data.
+
+use std::{
+    cell::Cell,
+    collections::VecDeque,
+    io::{self, Read, Seek, SeekFrom, Write},
+    rc::Rc,
+};
+
+/// Granularity of holes, smaller runs of zeros are stored as data.
+const BLOCK: usize = 4096;
+
+const RECORD_HEADER_LEN: usize = 16;
+
+/// Largest original size of a sparse file restored with holes, the largest file offset.
+pub const MAX_LEN: u64 = i64::MAX as u64;
+
+/// Largest original size of a sparse file expanded into a writer, where every hole
+/// is written out as zeros.
+pub const MAX_EXPANDED_LEN: u64 = 1 << 40;
+
+/// A run of zeros followed by a run of data.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Segment {
+    hole: u64,
+    data: u64,
+}
+
+/// Splits the contents of `file` into segments, leaving its position at the end.
+pub fn scan(file: &mut impl Read) -> io::Result<Vec<Segment>> {
+    let mut segments = vec![];
+    let mut current = Segment { hole: 0, data: 0 };
+    let mut block = vec![0u8; BLOCK];
+
+    loop {
+        let n = read_block(file, &mut block)?;
+        if n == 0 {
+            break;
+        }
+        if block[..n].iter().all(|&b| b == 0) {
+            if current.data > 0 {
+                segments.push(current);
+                current = Segment { hole: 0, data: 0 };
+            }
+            current.hole += n as u64;
+        } else {
+            current.data += n as u64;
+        }
+    }
+
+    if current.hole > 0 || current.data > 0 {
+        segments.push(current);
+    }
+    Ok(segments)
+}
+
+/// Fills `block` as far as possible, only returning less at the end of the file.
+fn read_block(file: &mut impl Read, block: &mut [u8]) -> io::Result<usize> {
+    let mut filled = 0;
+    while filled < block.len() {
+        match file.read(&mut block[filled..]) {
+            Ok(0) => break,
+            Ok(n) => filled += n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(filled)
+}
+
+/// Whether storing `segments` sparsely saves anything.
+pub fn has_holes(segments: &[Segment]) -> bool {
+    segments.iter().any(|s| s.hole > 0)
+}
+
+/// Size of the body encoding `segments`.
+pub fn stored_size(segments: &[Segment]) -> u64 {
+    segments
+        .iter()
+        .map(|s| RECORD_HEADER_LEN as u64 + s.data)
+        .sum()
+}
+
+/// Produces the sparse body of a file from its segments.
+///
+/// The data is read from the file again, if it shrank in the meantime
+/// the missing data is replaced with zeros and if it grew the rest is left out.
+/// Either sets `resized`, like the body of any other file changing size.
+pub struct Encoder<R> {
+    file: R,
+    segments: VecDeque<Segment>,
+    header: io::Cursor<[u8; RECORD_HEADER_LEN]>,
+    data_left: u64,
+    resized: Rc<Cell<bool>>,
+}
+
+impl<R: Read + Seek> Encoder<R> {
+    pub fn new(mut file: R, segments: Vec<Segment>, resized: Rc<Cell<bool>>) -> io::Result<Self> {
+        file.rewind()?;
+        let mut header = io::Cursor::new([0; RECORD_HEADER_LEN]);
+        header.set_position(RECORD_HEADER_LEN as u64);
+        Ok(Self {
+            file,
+            segments: segments.into(),
+            header,
+            data_left: 0,
+            resized,
+        })
+    }
+}
+
+impl<R: Read + Seek> Read for Encoder<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            if self.header.position() < RECORD_HEADER_LEN as u64 {
+                return self.header.read(buf);
+            }
+            if self.data_left > 0 {
+                let max = buf
+                    .len()
+                    .min(self.data_left.try_into().unwrap_or(usize::MAX));
+                let mut n = self.file.read(&mut buf[..max])?;
+                if n == 0 {
+                    self.resized.set(true);
+                    buf[..max].fill(0);
+                    n = max;
+                }
+                self.data_left -= n as u64;
+                return Ok(n);
+            }
+            let Some(segment) = self.segments.pop_front() else {
+                if !self.resized.get() && self.file.read(&mut [0])? != 0 {
+                    self.resized.set(true);
+                }
+                return Ok(0);
+            };
+            self.file.seek(SeekFrom::Current(segment.hole as _))?;
+            let header = self.header.get_mut();
+            header[..8].copy_from_slice(&segment.hole.to_le_bytes());
+            header[8..].copy_from_slice(&segment.data.to_le_bytes());
+            self.header.set_position(0);
+            self.data_left = segment.data;
+        }
+    }
+}
+
+/// Reads the next record header, `None` at the end of the body.
+fn read_record(body: &mut impl Read) -> io::Result<Option<Segment>> {
+    let mut header = [0u8; RECORD_HEADER_LEN];
+    match read_block(body, &mut header)? {
+        0 => return Ok(None),
+        RECORD_HEADER_LEN => {}
+        _ => return Err(io::ErrorKind::UnexpectedEof.into()),
+    }
+    Ok(Some(Segment {
+        hole: u64::from_le_bytes(header[..8].try_into().unwrap()),
+        data: u64::from_le_bytes(header[8..].try_into().unwrap()),
+    }))
+}
+
+fn copy_data(body: &mut impl Read, len: u64, out: &mut impl Write) -> io::Result<()> {
+    let copied = io::copy(&mut body.take(len), out)?;
+    if copied != len {
+        return Err(io::ErrorKind::UnexpectedEof.into());
+    }
+    Ok(())
+}
+
+/// Writes the original contents of a sparse file to `out`, leaving every hole to `hole`.
+///
+/// Fails with [`DecodeError::TooLarge`](crate::DecodeError::TooLarge) before writing a
+/// record that would take the contents beyond `max_len` bytes, as the lengths of holes
+/// and data come straight from the archive.
+pub fn restore<W: Write>(
+    body: &mut impl Read,
+    out: &mut W,
+    max_len: u64,
+    mut hole: impl FnMut(&mut W, u64) -> io::Result<()>,
+) -> io::Result<()> {
+    let mut len = 0u64;
+    while let Some(segment) = read_record(body)? {
+        len = len
+            .checked_add(segment.hole)
+            .and_then(|len| len.checked_add(segment.data))
+            .filter(|&len| len <= max_len)
+            .ok_or_else(|| {
+                log::error!("Sparse file exceeds {max_len}B");
+                crate::DecodeError::TooLarge { offset: None }
+            })?;
+        hole(out, segment.hole)?;
+        copy_data(body, segment.data, out)?;
+    }
+    Ok(())
+}
+
+/// Writes the original contents of a sparse file, with the holes filled with zeros.
+///
+/// Fails for files beyond [`MAX_EXPANDED_LEN`].
+pub fn expand(body: &mut impl Read, out: &mut impl Write) -> io::Result<()> {
+    restore(body, out, MAX_EXPANDED_LEN, |out, len| {
+        io::copy(&mut io::repeat(0).take(len), out)?;
+        Ok(())
+    })
+}
+
+#[test]
+fn sparse_body_roundtrip() {
+    let mut contents = vec![0u8; 5 * BLOCK + 10];
+    contents[BLOCK + 1] = 1;
+    contents[3 * BLOCK] = 2;
+
+    let segments = scan(&mut contents.as_slice()).unwrap();
+    let hole = BLOCK as u64;
+    assert_eq!(
+        segments,
+        [
+            Segment { hole, data: hole },
+            Segment { hole, data: hole },
+            Segment {
+                hole: hole + 10,
+                data: 0
+            },
+        ]
+    );
+
+    let mut body = vec![];
+    let resized = Rc::default();
+    Encoder::new(
+        io::Cursor::new(&contents),
+        segments.clone(),
+        Rc::clone(&resized),
+    )
+    .unwrap()
+    .read_to_end(&mut body)
+    .unwrap();
+    assert_eq!(body.len() as u64, stored_size(&segments));
+    assert!(!resized.get());
+
+    let mut expanded = vec![];
+    expand(&mut body.as_slice(), &mut expanded).unwrap();
+    assert!(expanded == contents);
+}
+
+#[test]
+fn oversized_holes_are_rejected() {
+    let mut body = vec![];
+    for (hole, data) in [(BLOCK as u64, 1), (u64::MAX, 0)] {
+        body.extend(hole.to_le_bytes());
+        body.extend(u64::to_le_bytes(data));
+        body.extend(vec![1; data as usize]);
+    }
+    let mut out = vec![];
+    let err = expand(&mut body.as_slice(), &mut out).unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    assert_eq!(out.len(), BLOCK + 1);
+
+    let mut holes = 0;
+    let hole = |_: &mut Vec<u8>, len| {
+        holes += len;
+        Ok(())
+    };
+    let err = restore(&mut body.as_slice(), &mut vec![], MAX_LEN, hole).unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    assert_eq!(holes, BLOCK as u64);
+}
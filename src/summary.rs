@@ -0,0 +1,164 @@
+use std::{
+    fmt,
+    io::{Read, Seek},
+};
+
+use crate::{decode_err, read1, trailer, DecodeError, EntryKind};
+
+/// Totals over all entries of an archive, see [`summarize`].
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ArchiveSummary {
+    /// Number of entries.
+    pub entries: usize,
+    /// Sum of the stored body sizes.
+    pub body_bytes: u64,
+    /// Everything but the bodies: metadata, paths, extended attributes,
+    /// the comment, the index and the trailer.
+    pub metadata_bytes: u64,
+    pub files: usize,
+    pub directories: usize,
+    pub soft_links: usize,
+    pub hard_links: usize,
+    /// FIFOs and device nodes.
+    pub special_files: usize,
+}
+
+impl ArchiveSummary {
+    /// Metadata bytes per body byte, infinite for an archive without any body.
+    pub fn overhead_ratio(&self) -> f64 {
+        if self.body_bytes == 0 {
+            return f64::INFINITY;
+        }
+        self.metadata_bytes as f64 / self.body_bytes as f64
+    }
+}
+
+impl fmt::Display for ArchiveSummary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "{} entries, {}B + {}B metadata",
+            self.entries, self.body_bytes, self.metadata_bytes
+        )?;
+        writeln!(f, "  {} files", self.files)?;
+        writeln!(f, "  {} directories", self.directories)?;
+        writeln!(f, "  {} soft links", self.soft_links)?;
+        writeln!(f, "  {} hard links", self.hard_links)?;
+        write!(f, "  {} special files", self.special_files)
+    }
+}
+
+/// Counts the entries of an archive in a single pass, seeking past the bodies.
+///
+/// Body and metadata bytes add up to the length of the archive.
+pub fn summarize<R: Read + Seek>(archive: &mut R) -> Result<ArchiveSummary, DecodeError> {
+    let mut summary = ArchiveSummary::default();
+    if trailer::find(archive).map_err(decode_err)?.is_some() {
+        summary.metadata_bytes += trailer::LEN as u64;
+    }
+
+    // not [`Entries`](crate::Entries), the comment and the index count as metadata.
+    let mut path_buf = vec![];
+    let mut first = true;
+    loop {
+        let entry = match read1(archive, &mut path_buf) {
+            Ok(entry) => entry,
+            Err(DecodeError::Exhausted) => break,
+            Err(e) if first => return Err(e),
+            Err(e) => return Err(e.mid_archive()),
+        };
+        first = false;
+        if entry.is_comment() || entry.is_index() {
+            summary.metadata_bytes += entry.size_on_disk();
+            continue;
+        }
+        summary.entries += 1;
+        summary.body_bytes += entry.size();
+        summary.metadata_bytes += entry.size_on_disk() - entry.size();
+        *match entry.kind() {
+            EntryKind::File => &mut summary.files,
+            EntryKind::Directory => &mut summary.directories,
+            EntryKind::SoftLink => &mut summary.soft_links,
+            EntryKind::HardLink => &mut summary.hard_links,
+            EntryKind::Fifo | EntryKind::CharDevice | EntryKind::BlockDevice => {
+                &mut summary.special_files
+            }
+        } += 1;
+    }
+    Ok(summary)
+}
+
+#[test]
+fn summary_counts_kinds() {
+    let dir = tempfile::tempdir().unwrap();
+    std::fs::create_dir(dir.path().join("sub")).unwrap();
+    std::fs::write(dir.path().join("a.txt"), b"abc").unwrap();
+    std::fs::write(dir.path().join("sub/b.txt"), b"defg").unwrap();
+
+    let mut archive = vec![];
+    crate::recursive_archive(&mut archive, dir.path()).unwrap();
+    let summary = summarize(&mut std::io::Cursor::new(&archive)).unwrap();
+
+    let metadata_bytes = archive.len() as u64 - 7;
+    assert_eq!(
+        summary,
+        ArchiveSummary {
+            entries: 4,
+            body_bytes: 7,
+            metadata_bytes,
+            files: 2,
+            directories: 2,
+            ..Default::default()
+        }
+    );
+    assert_eq!(summary.overhead_ratio(), metadata_bytes as f64 / 7.0);
+    assert!(summary.to_string().starts_with(&format!(
+        "4 entries, 7B + {metadata_bytes}B metadata\n  2 files\n"
+    )));
+}
+
+#[test]
+fn summary_accounts_for_whole_archive() {
+    let mut archiver = crate::Archiver::new(vec![])
+        .index(true)
+        .trailer(true)
+        .build();
+    archiver.set_comment("counted as metadata").unwrap();
+    archiver
+        .add_reader("a.txt", &mut &b"abc"[..], 3, 0)
+        .unwrap();
+    let archive = archiver.finish().unwrap();
+
+    let summary = summarize(&mut std::io::Cursor::new(&archive)).unwrap();
+    assert_eq!(summary.entries, 1);
+    assert_eq!(summary.body_bytes, 3);
+    assert_eq!(
+        summary.body_bytes + summary.metadata_bytes,
+        archive.len() as u64
+    );
+
+    let empty = summarize(&mut std::io::Cursor::new(&[])).unwrap();
+    assert_eq!(empty.overhead_ratio(), f64::INFINITY);
+}
+
+#[cfg(feature = "xattr")]
+#[test]
+fn summary_counts_xattrs_as_metadata() {
+    let dir = tempfile::tempdir().unwrap();
+    let file = dir.path().join("a.txt");
+    std::fs::write(&file, b"abc").unwrap();
+    if xattr::set(&file, "user.test", b"value").is_err() {
+        // the file system doesn't support extended attributes.
+        return;
+    }
+
+    let mut archiver = crate::Archiver::new(vec![]).xattrs(true).build();
+    archiver.add_path(&file).unwrap();
+    let archive = archiver.finish().unwrap();
+
+    let summary = summarize(&mut std::io::Cursor::new(&archive)).unwrap();
+    assert_eq!(
+        summary.body_bytes + summary.metadata_bytes,
+        archive.len() as u64
+    );
+}
@@ -0,0 +1,146 @@
+//! Optional checksum over a whole archive, appended after its last entry.
+//!
+//! | bytes | content                                      |
+//! |-------|----------------------------------------------|
+//! | 4     | [`MAGIC`]                                    |
+//! | 4     | CRC-32 of all bytes from the archive start   |
+//!
+//! both little-endian. Sequential readers take it for the end of the archive,
+//! so entries are appended in its place and followed by a new one, see
+//! [`append_path_to_existing`](crate::append_path_to_existing).
+
+use std::io::{self, Read, Seek, SeekFrom};
+
+use crate::{crc32, DecodeError};
+
+/// Randomly generated, distinct from the magic of the metadata.
+const MAGIC: u32 = 0x53_c4_1d_7e;
+
+pub const LEN: usize = 8;
+
+pub fn encode(checksum: u32) -> [u8; LEN] {
+    let mut bytes = [0u8; LEN];
+    bytes[..4].copy_from_slice(&MAGIC.to_le_bytes());
+    bytes[4..].copy_from_slice(&checksum.to_le_bytes());
+    bytes
+}
+
+/// Whether `bytes`, the rest of an archive, are a trailer.
+pub fn is_trailer(bytes: &[u8]) -> bool {
+    bytes.len() == LEN && bytes[..4] == MAGIC.to_le_bytes()
+}
+
+/// Offset of the trailer at the end of `archive`, if there is one.
+/// The position of `archive` is kept.
+pub fn find<R: Read + Seek>(archive: &mut R) -> io::Result<Option<u64>> {
+    let pos = archive.stream_position()?;
+    let end = archive.seek(SeekFrom::End(0))?;
+    let found = match end.checked_sub(LEN as u64) {
+        Some(start) if start >= pos => {
+            let mut bytes = [0u8; LEN];
+            archive.seek(SeekFrom::Start(start))?;
+            archive.read_exact(&mut bytes)?;
+            is_trailer(&bytes).then_some(start)
+        }
+        _ => None,
+    };
+    archive.seek(SeekFrom::Start(pos))?;
+    Ok(found)
+}
+
+/// Offset of a trailer cut off at the end of `archive`, recognized by its magic.
+/// The position of `archive` is kept.
+fn find_cut<R: Read + Seek>(archive: &mut R) -> io::Result<Option<u64>> {
+    let pos = archive.stream_position()?;
+    let end = archive.seek(SeekFrom::End(0))?;
+    let mut found = None;
+    // shorter rests don't hold the whole magic, which leaves too much to chance.
+    for len in 4..LEN as u64 {
+        let Some(start) = end.checked_sub(len).filter(|&start| start >= pos) else {
+            break;
+        };
+        let mut magic = [0u8; 4];
+        archive.seek(SeekFrom::Start(start))?;
+        archive.read_exact(&mut magic)?;
+        if magic == MAGIC.to_le_bytes() {
+            found = Some(start);
+            break;
+        }
+    }
+    archive.seek(SeekFrom::Start(pos))?;
+    Ok(found)
+}
+
+/// Checks the trailer of the archive starting at the current position, if it has one.
+/// A trailer that is cut off fails like one that doesn't match.
+/// The position of `archive` is kept.
+pub fn check<R: Read + Seek>(archive: &mut R) -> Result<(), DecodeError> {
+    let io_err = |e: io::Error| {
+        log::error!("Failed to read trailer: {e:?}");
+        DecodeError::Crop { offset: None }
+    };
+
+    let pos = archive.stream_position().map_err(io_err)?;
+    let Some(start) = find(archive).map_err(io_err)? else {
+        if let Some(start) = find_cut(archive).map_err(io_err)? {
+            log::error!("Archive trailer cut off");
+            return Err(DecodeError::Checksum {
+                offset: Some(start),
+            });
+        }
+        return Ok(());
+    };
+    let mut covered = crc32::Reader::new(archive.take(start - pos));
+    io::copy(&mut covered, &mut io::sink()).map_err(io_err)?;
+    let checksum = covered.digest();
+
+    let mut stored = [0u8; 4];
+    archive.seek(SeekFrom::Start(start + 4)).map_err(io_err)?;
+    archive.read_exact(&mut stored).map_err(io_err)?;
+    archive.seek(SeekFrom::Start(pos)).map_err(io_err)?;
+    if u32::from_le_bytes(stored) != checksum {
+        log::error!("Archive checksum mismatch");
+        return Err(DecodeError::Checksum {
+            offset: Some(start),
+        });
+    }
+    Ok(())
+}
+
+#[test]
+fn trailer_covers_whole_archive() {
+    let dir = tempfile::tempdir().unwrap();
+    std::fs::write(dir.path().join("a.txt"), b"abc").unwrap();
+    let mut archiver = crate::Archiver::new(vec![])
+        .index(true)
+        .trailer(true)
+        .build();
+    archiver.add_path(dir.path()).unwrap();
+    let archive = archiver.finish().unwrap();
+
+    assert_eq!(
+        crate::list(&mut io::Cursor::new(&archive)).unwrap().len(),
+        2
+    );
+    assert_eq!(crate::verify(&mut io::Cursor::new(&archive)).unwrap().ok, 2);
+    let indexed = crate::open_indexed(io::Cursor::new(&archive)).unwrap();
+    assert_eq!(indexed.entries().len(), 2);
+
+    let trailer_start = archive.len() - LEN;
+    let mut corrupt = archive.clone();
+    *corrupt.last_mut().unwrap() ^= 0x01;
+    assert_eq!(
+        crate::verify(&mut io::Cursor::new(&corrupt)),
+        Err(DecodeError::Checksum {
+            offset: Some(trailer_start as u64)
+        })
+    );
+
+    let truncated = &archive[..archive.len() - 1];
+    assert_eq!(
+        crate::verify(&mut io::Cursor::new(truncated)),
+        Err(DecodeError::Checksum {
+            offset: Some(trailer_start as u64)
+        })
+    );
+}
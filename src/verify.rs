@@ -0,0 +1,312 @@
+use std::io::{self, Read, Seek, SeekFrom, Write};
+
+use crate::{
+    check_footer, crc32, read_footer, read_header, read_path, remaining_len, trailer, xattrs,
+    DecodeError, Metadata, MAGIC,
+};
+
+/// Outcome of [`verify`].
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct VerifyReport {
+    /// Number of entries that passed all checks.
+    pub ok: usize,
+    /// Number of entries with a corrupt header, body or footer.
+    pub corrupt: usize,
+    /// Paths of the corrupt entries.
+    /// Entries whose header is unreadable are named by their offset instead.
+    pub corrupt_paths: Vec<String>,
+    /// Paths of intact files that changed size while they were archived,
+    /// see [`flags::RESIZED`](crate::flags::RESIZED).
+    pub resized_paths: Vec<String>,
+}
+
+fn io_err(e: io::Error) -> DecodeError {
+    log::error!("Failed to access archive: {e:?}");
+    DecodeError::Crop { offset: None }
+}
+
+/// Checks every header, footer and body checksum without extracting anything.
+/// A checksum over the whole archive is checked first if the archive has one,
+/// failing with [`DecodeError::Checksum`] on a mismatch.
+///
+/// Unlike reading, verification doesn't stop at the first corrupt entry.
+/// An entry with an intact header is skipped using its recorded sizes,
+/// otherwise the archive is scanned for the next valid header.
+pub fn verify<R: Read + Seek>(archive: &mut R) -> Result<VerifyReport, DecodeError> {
+    let mut report = VerifyReport::default();
+    trailer::check(archive)?;
+
+    loop {
+        let start = archive.stream_position().map_err(io_err)?;
+        // failures from here on are reported at the entry.
+        let io_err = |e| io_err(e).at(start);
+        let header =
+            read_header(archive).and_then(|header| Ok((read_path(&header, archive)?, header)));
+        let (path, header) = match header {
+            Ok(header) => header,
+            Err(DecodeError::Exhausted) => return Ok(report),
+            Err(DecodeError::Crop { .. }) => {
+                // the archive ends within the header or the path.
+                report.corrupt += 1;
+                report
+                    .corrupt_paths
+                    .push(format!("<path at offset {start}>"));
+                return Ok(report);
+            }
+            Err(_) => {
+                report.corrupt += 1;
+                report
+                    .corrupt_paths
+                    .push(format!("<header at offset {start}>"));
+                match find_header(archive, start + 1).map_err(io_err)? {
+                    Some(next) => archive.seek(SeekFrom::Start(next)).map_err(io_err)?,
+                    None => return Ok(report),
+                };
+                continue;
+            }
+        };
+        if xattrs::read(&header, &path, archive).is_err() {
+            report.corrupt += 1;
+            report
+                .corrupt_paths
+                .push(format!("<path at offset {start}>"));
+            return Ok(report);
+        }
+        let path = String::from_utf8_lossy(&path).into_owned();
+        let body_start = archive.stream_position().map_err(io_err)?;
+
+        if let Ok(footer) = check_entry(archive, &header, body_start) {
+            // neither the index nor the comment is an entry of its own.
+            if !header.flags.is_special() {
+                report.ok += 1;
+            }
+            if footer.flags.resized() {
+                report.resized_paths.push(path);
+            }
+        } else if header.flags.is_index() {
+            report.corrupt += 1;
+            report.corrupt_paths.push("<index>".into());
+        } else if header.flags.is_comment() {
+            report.corrupt += 1;
+            report.corrupt_paths.push("<comment>".into());
+        } else {
+            report.corrupt += 1;
+            report.corrupt_paths.push(path);
+        }
+
+        let end = body_start + header.file_size + Metadata::SIZE as u64;
+        archive.seek(SeekFrom::Start(end)).map_err(io_err)?;
+    }
+}
+
+/// Verifies body and footer of the entry whose body starts at `body_start`,
+/// where `archive` is positioned, returning the footer.
+fn check_entry<R: Read + Seek>(
+    archive: &mut R,
+    header: &Metadata,
+    body_start: u64,
+) -> Result<Metadata, DecodeError> {
+    let remaining = remaining_len(archive).map_err(|e| io_err(e).at(body_start))?;
+    if header.file_size > remaining.saturating_sub(Metadata::SIZE as u64) {
+        return Err(DecodeError::Crop {
+            offset: Some(body_start),
+        });
+    }
+
+    let mut body = crc32::Reader::for_body(archive.take(header.file_size), header.flags);
+    io::copy(&mut body, &mut io::sink()).map_err(|e| io_err(e).at(body_start))?;
+    let body_checksum = body.digest();
+
+    let footer_start = body_start + header.file_size;
+    let footer = read_footer(archive).map_err(|e| e.at(footer_start))?;
+    check_footer(header, &footer).map_err(|e| e.at(footer_start))?;
+    if footer.body_checksum != body_checksum {
+        return Err(DecodeError::Checksum {
+            offset: Some(body_start),
+        });
+    }
+    Ok(footer)
+}
+
+/// Outcome of [`repack`].
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct RepackReport {
+    /// Number of entries copied.
+    pub kept: usize,
+    /// Number of corrupt entries left out.
+    pub dropped: usize,
+}
+
+/// Copies every intact entry of `src` to `dst`, leaving out corrupt ones.
+///
+/// Corrupt entries are skipped like [`verify`] does. Indexes are left out, as their
+/// offsets would no longer hold. For the same reason deduplicated entries are
+/// dropped once an entry before them was, as the chunks they refer to moved.
+/// Failing to write `dst` is reported as [`DecodeError::Crop`] and logged.
+pub fn repack<R: Read + Seek, W: Write>(
+    src: &mut R,
+    dst: &mut W,
+) -> Result<RepackReport, DecodeError> {
+    let mut report = RepackReport::default();
+
+    loop {
+        let start = src.stream_position().map_err(io_err)?;
+        // failures from here on are reported at the entry.
+        let io_err = |e| io_err(e).at(start);
+        let header = read_header(src).and_then(|header| {
+            let path = read_path(&header, src)?;
+            xattrs::read(&header, &path, src)?;
+            Ok(header)
+        });
+        let header = match header {
+            Ok(header) => header,
+            Err(DecodeError::Exhausted) => return Ok(report),
+            Err(DecodeError::Crop { .. }) => {
+                log::warn!("Dropping entry cut off at offset {start}");
+                report.dropped += 1;
+                return Ok(report);
+            }
+            Err(e) => {
+                log::warn!("Dropping entry: {}", e.at(start));
+                report.dropped += 1;
+                match find_header(src, start + 1).map_err(io_err)? {
+                    Some(next) => src.seek(SeekFrom::Start(next)).map_err(io_err)?,
+                    None => return Ok(report),
+                };
+                continue;
+            }
+        };
+        let body_start = src.stream_position().map_err(|e| io_err(e).at(start))?;
+        let end = body_start + header.file_size + Metadata::SIZE as u64;
+
+        match check_entry(src, &header, body_start) {
+            Ok(_) if header.flags.is_index() => {
+                log::info!("Leaving out the index at offset {start}");
+            }
+            Ok(_) if header.flags.chunked() && report.dropped > 0 => {
+                log::warn!("Dropping entry at offset {start}, its chunks may have been dropped");
+                report.dropped += 1;
+            }
+            Ok(_) => {
+                src.seek(SeekFrom::Start(start)).map_err(io_err)?;
+                let copied = io::copy(&mut src.take(end - start), dst).map_err(|e| {
+                    log::error!("Failed to write repacked archive: {e:?}");
+                    DecodeError::Crop {
+                        offset: Some(start),
+                    }
+                })?;
+                if copied != end - start {
+                    return Err(DecodeError::Crop {
+                        offset: Some(start + copied),
+                    });
+                }
+                report.kept += 1;
+            }
+            Err(e) => {
+                log::warn!("Dropping entry: {e}");
+                report.dropped += 1;
+            }
+        }
+        src.seek(SeekFrom::Start(end)).map_err(io_err)?;
+    }
+}
+
+/// Position of the next intact header at or after `from`.
+fn find_header<R: Read + Seek>(archive: &mut R, from: u64) -> io::Result<Option<u64>> {
+    const WINDOW: u64 = 64 * 1024;
+    let magic = MAGIC.to_le_bytes();
+    let mut pos = from;
+
+    loop {
+        archive.seek(SeekFrom::Start(pos))?;
+        let mut buf = vec![];
+        archive.take(WINDOW).read_to_end(&mut buf)?;
+        if buf.len() < Metadata::SIZE {
+            return Ok(None);
+        }
+
+        for (i, candidate) in buf.windows(Metadata::SIZE).enumerate() {
+            if candidate[Metadata::MAGIC_OFFSET..][..4] != magic {
+                continue;
+            }
+            let Some(meta) = Metadata::from_bytes(candidate) else {
+                continue;
+            };
+            if !meta.flags.is_header() {
+                continue;
+            }
+            // the checksum covers the path, which may lie beyond the window.
+            let start = pos + i as u64;
+            archive.seek(SeekFrom::Start(start + Metadata::SIZE as u64))?;
+            if read_path(&meta, archive).is_ok() {
+                return Ok(Some(start));
+            }
+        }
+
+        pos += (buf.len() - Metadata::SIZE + 1) as u64;
+    }
+}
+
+#[test]
+fn verify_reports_all_corrupt_entries() {
+    let dir = tempfile::tempdir().unwrap();
+    for name in ["a.txt", "b.txt", "c.txt", "d.txt"] {
+        std::fs::write(dir.path().join(name), name.repeat(10)).unwrap();
+    }
+
+    let mut archive = vec![];
+    for name in ["a.txt", "b.txt", "c.txt", "d.txt"] {
+        crate::append_to_archive(&mut archive, &dir.path().join(name)).unwrap();
+    }
+    let entry_len = archive.len() / 4;
+    let report = verify(&mut io::Cursor::new(&archive)).unwrap();
+    assert_eq!(report.ok, 4);
+    assert_eq!(report.corrupt, 0);
+
+    // corrupt the body of b.txt and the header of d.txt.
+    archive[2 * entry_len - Metadata::SIZE - 1] ^= 0x01;
+    archive[3 * entry_len] ^= 0x01;
+
+    let report = verify(&mut io::Cursor::new(&archive)).unwrap();
+    assert_eq!(report.ok, 2);
+    assert_eq!(report.corrupt, 2);
+    assert!(report.corrupt_paths[0].ends_with("b.txt"));
+    assert_eq!(
+        report.corrupt_paths[1],
+        format!("<header at offset {}>", 3 * entry_len)
+    );
+}
+
+#[test]
+fn repack_drops_corrupt_entries() {
+    let mut archive = vec![];
+    for name in ["a.txt", "b.txt", "c.txt", "d.txt"] {
+        crate::append_reader(&mut archive, name, &mut name.repeat(10).as_bytes(), 50, 0).unwrap();
+    }
+    let entry_len = archive.len() / 4;
+
+    // corrupt the body of b.txt and the header of d.txt.
+    archive[2 * entry_len - Metadata::SIZE - 1] ^= 0x01;
+    archive[3 * entry_len] ^= 0x01;
+
+    let mut repacked = vec![];
+    let report = repack(&mut io::Cursor::new(&archive), &mut repacked).unwrap();
+    assert_eq!(
+        report,
+        RepackReport {
+            kept: 2,
+            dropped: 2
+        }
+    );
+
+    let paths = crate::list(&mut io::Cursor::new(&repacked))
+        .unwrap()
+        .into_iter()
+        .map(|entry| entry.path)
+        .collect::<Vec<_>>();
+    assert_eq!(
+        paths,
+        [std::path::Path::new("a.txt"), std::path::Path::new("c.txt")]
+    );
+    assert_eq!(verify(&mut io::Cursor::new(&repacked)).unwrap().corrupt, 0);
+}
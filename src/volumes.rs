@@ -0,0 +1,218 @@
+//! Archives split into volumes of bounded size, e.g. to fit on FAT32.
+//!
+//! The volumes are consecutive pieces of a single archive. An entry is
+//! moved to the next volume if it would cross the limit of the current
+//! one, only entries larger than a whole volume are cut.
+
+use std::{
+    ffi::OsString,
+    fs::File,
+    io::{self, BufWriter, Read, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+};
+
+use crate::{ArchiveError, Archiver};
+
+/// Path of the volume numbered `n`, counting from 1.
+fn volume_path(base_name: &Path, n: usize) -> PathBuf {
+    let mut name = OsString::from(base_name.as_os_str());
+    name.push(format!(".{n:03}"));
+    name.into()
+}
+
+/// Writes to numbered volume files, starting a new one when full.
+struct SplitWriter {
+    base: PathBuf,
+    max_volume_bytes: u64,
+    volumes: Vec<PathBuf>,
+    current: Option<BufWriter<File>>,
+    /// bytes written to the current volume.
+    written: u64,
+}
+
+impl SplitWriter {
+    /// Closes the current volume if an entry of `len` bytes doesn't fit it,
+    /// unless it's empty anyway.
+    fn start_entry(&mut self, len: u64) -> io::Result<()> {
+        if self.written > 0 && self.written + len > self.max_volume_bytes {
+            self.close()?;
+        }
+        Ok(())
+    }
+
+    fn close(&mut self) -> io::Result<()> {
+        if let Some(mut volume) = self.current.take() {
+            volume.flush()?;
+        }
+        self.written = 0;
+        Ok(())
+    }
+
+    /// The volume to write to and the room left in it, opening the next one if needed.
+    fn volume(&mut self) -> io::Result<(&mut BufWriter<File>, u64)> {
+        if self.written >= self.max_volume_bytes {
+            self.close()?;
+        }
+        if self.current.is_none() {
+            self.volumes
+                .push(volume_path(&self.base, self.volumes.len() + 1));
+            self.current = Some(BufWriter::new(File::create(self.volumes.last().unwrap())?));
+        }
+        let room = self.max_volume_bytes - self.written;
+        Ok((self.current.as_mut().unwrap(), room))
+    }
+}
+
+impl Write for SplitWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let (volume, room) = self.volume()?;
+        let len = buf.len().min(room.try_into().unwrap_or(usize::MAX));
+        let n = volume.write(&buf[..len])?;
+        self.written += n as u64;
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match &mut self.current {
+            Some(volume) => volume.flush(),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Archives the tree below `path` into volumes of at most `max_volume_bytes`,
+/// named after `base_name` with `.001`, `.002`, … appended.
+///
+/// Returns the paths of the volumes in order. Read them through [`Volumes`].
+pub fn recursive_archive_split(
+    base_name: &Path,
+    max_volume_bytes: u64,
+    path: &Path,
+) -> Result<Vec<PathBuf>, ArchiveError> {
+    if max_volume_bytes == 0 {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, "volumes can't be empty").into());
+    }
+    let writer = SplitWriter {
+        base: base_name.into(),
+        max_volume_bytes,
+        volumes: vec![],
+        current: None,
+        written: 0,
+    };
+    let mut archiver = Archiver::new(writer).build();
+    archiver.before_entry(SplitWriter::start_entry);
+    archiver.add_path(path)?;
+    let mut writer = archiver.finish()?;
+    writer.close()?;
+    Ok(writer.volumes)
+}
+
+/// The volumes of a split archive, read as the single archive they form.
+pub struct Volumes<R> {
+    volumes: Vec<R>,
+    /// offset of every volume within the archive, followed by the archive length.
+    starts: Vec<u64>,
+    pos: u64,
+}
+
+impl<R: Read + Seek> Volumes<R> {
+    /// Chains `volumes`, which have to be given in order.
+    pub fn new(mut volumes: Vec<R>) -> io::Result<Self> {
+        let mut starts = vec![0];
+        for volume in &mut volumes {
+            let len = volume.seek(SeekFrom::End(0))?;
+            starts.push(starts.last().unwrap() + len);
+        }
+        Ok(Self {
+            volumes,
+            starts,
+            pos: 0,
+        })
+    }
+
+    /// Returns the volumes.
+    pub fn into_inner(self) -> Vec<R> {
+        self.volumes
+    }
+}
+
+impl Volumes<File> {
+    /// Opens the volumes written by [`recursive_archive_split`] for `base_name`.
+    pub fn open(base_name: &Path) -> io::Result<Self> {
+        let mut volumes = vec![];
+        loop {
+            match File::open(volume_path(base_name, volumes.len() + 1)) {
+                Ok(volume) => volumes.push(volume),
+                Err(e) if e.kind() == io::ErrorKind::NotFound && !volumes.is_empty() => break,
+                Err(e) => return Err(e),
+            }
+        }
+        Self::new(volumes)
+    }
+}
+
+impl<R: Read + Seek> Read for Volumes<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        // the last volume starting at or before the position.
+        let i = self.starts.partition_point(|&start| start <= self.pos) - 1;
+        let Some(volume) = self.volumes.get_mut(i) else {
+            return Ok(0);
+        };
+        let room = self.starts[i + 1] - self.pos;
+        let len = buf.len().min(room.try_into().unwrap_or(usize::MAX));
+        volume.seek(SeekFrom::Start(self.pos - self.starts[i]))?;
+        let n = volume.read(&mut buf[..len])?;
+        self.pos += n as u64;
+        Ok(n)
+    }
+}
+
+impl<R: Read + Seek> Seek for Volumes<R> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let len = *self.starts.last().unwrap();
+        let pos = match pos {
+            SeekFrom::Start(pos) => Some(pos),
+            SeekFrom::End(offset) => len.checked_add_signed(offset),
+            SeekFrom::Current(offset) => self.pos.checked_add_signed(offset),
+        };
+        self.pos = pos.ok_or_else(|| {
+            io::Error::new(io::ErrorKind::InvalidInput, "seek before the archive start")
+        })?;
+        Ok(self.pos)
+    }
+}
+
+#[test]
+fn split_archive_reads_like_one() {
+    let dir = tempfile::tempdir().unwrap();
+    let src = dir.path().join("src");
+    std::fs::create_dir(&src).unwrap();
+    for i in 0..4 {
+        std::fs::write(src.join(format!("{i}.txt")), vec![b'a' + i; 300]).unwrap();
+    }
+    // larger than a volume, so it's cut.
+    std::fs::write(src.join("big.bin"), vec![7u8; 2500]).unwrap();
+
+    let base = dir.path().join("archive.bit");
+    let volumes = recursive_archive_split(&base, 1000, &src).unwrap();
+    assert!(volumes.len() >= 4);
+    assert!(volumes[0].to_str().unwrap().ends_with("archive.bit.001"));
+    for volume in &volumes {
+        assert!(std::fs::metadata(volume).unwrap().len() <= 1000);
+    }
+
+    let mut whole = vec![];
+    crate::recursive_archive(&mut whole, &src).unwrap();
+    let mut joined = vec![];
+    Volumes::open(&base)
+        .unwrap()
+        .read_to_end(&mut joined)
+        .unwrap();
+    // the volumes don't end in a trailer.
+    assert_eq!(joined, whole[..whole.len() - crate::trailer::LEN]);
+
+    let entries = crate::list(&mut Volumes::open(&base).unwrap()).unwrap();
+    assert_eq!(entries.len(), 6);
+    let dest = tempfile::tempdir().unwrap();
+    crate::extract(&mut Volumes::open(&base).unwrap(), dest.path()).unwrap();
+}
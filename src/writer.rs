@@ -0,0 +1,180 @@
+use std::{
+    io::{self, Seek, SeekFrom, Write},
+    path::Path,
+};
+
+use crate::{crc32::Crc32, platform, EntryKind, Metadata, MAX_PATH_LEN};
+
+/// The entry currently being written by an [`ArchiveWriter`].
+struct OpenEntry {
+    /// Offset of the header, which is rewritten once the size is known.
+    header_at: u64,
+    meta: Metadata,
+    path: Vec<u8>,
+    crc: Crc32,
+}
+
+/// Writes entries whose size isn't known upfront.
+///
+/// Everything written between [`start_entry`](Self::start_entry) and
+/// [`finish_entry`](Self::finish_entry) becomes the body of that entry. The header
+/// is written with a size of zero and patched afterwards, hence the `Seek` bound.
+/// Use [`Archiver`](crate::Archiver) for bodies of known size and unseekable outputs.
+///
+/// Dropping the writer without [`finish`](Self::finish) or `finish_entry` leaves
+/// the open entry with a header of size zero and no footer, so the archive is
+/// corrupt from that entry on.
+pub struct ArchiveWriter<W: Write + Seek> {
+    inner: W,
+    entry: Option<OpenEntry>,
+}
+
+impl<W: Write + Seek> ArchiveWriter<W> {
+    pub fn new(inner: W) -> Self {
+        Self { inner, entry: None }
+    }
+
+    /// Starts an entry of `kind` archived as `path`, finishing the previous one.
+    ///
+    /// The body is written as for the kind, e.g. the target of a soft link.
+    pub fn start_entry(
+        &mut self,
+        path: impl AsRef<Path>,
+        kind: EntryKind,
+        perms: u16,
+        owner: u16,
+        group: u16,
+        modified_at: u64,
+    ) -> io::Result<()> {
+        self.finish_entry()?;
+
+        let path = platform::path_to_bytes(path.as_ref());
+        if path.len() > MAX_PATH_LEN {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("path of {}B exceeds {MAX_PATH_LEN}B", path.len()),
+            ));
+        }
+        let meta = Metadata::new(
+            kind.into(),
+            0,
+            path.len() as _,
+            perms,
+            owner,
+            group,
+            modified_at,
+        );
+        let header_at = self.inner.stream_position()?;
+        self.inner.write_all(&meta.header(&path, &[]).to_bytes())?;
+        self.inner.write_all(&path)?;
+        self.entry = Some(OpenEntry {
+            header_at,
+            meta,
+            path,
+            crc: Crc32::new(),
+        });
+        Ok(())
+    }
+
+    /// Writes the footer of the current entry and patches its header, if one is open.
+    pub fn finish_entry(&mut self) -> io::Result<()> {
+        let Some(OpenEntry {
+            header_at,
+            meta,
+            path,
+            crc,
+        }) = self.entry.take()
+        else {
+            return Ok(());
+        };
+        self.inner
+            .write_all(&meta.footer(crc.finalize()).to_bytes())?;
+
+        let end = self.inner.stream_position()?;
+        self.inner.seek(SeekFrom::Start(header_at))?;
+        self.inner.write_all(&meta.header(&path, &[]).to_bytes())?;
+        self.inner.seek(SeekFrom::Start(end))?;
+        Ok(())
+    }
+
+    /// Finishes the current entry and returns the underlying writer.
+    pub fn finish(mut self) -> io::Result<W> {
+        self.finish_entry()?;
+        self.inner.flush()?;
+        Ok(self.inner)
+    }
+}
+
+impl<W: Write + Seek> Write for ArchiveWriter<W> {
+    /// Appends to the body of the current entry, failing if none is open.
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let Some(entry) = &mut self.entry else {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "no entry started to write to",
+            ));
+        };
+        let n = self.inner.write(buf)?;
+        entry.crc.update(&buf[..n]);
+        entry.meta.file_size += n as u64;
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+#[test]
+fn writer_patches_unknown_sizes() {
+    let mut writer = ArchiveWriter::new(io::Cursor::new(vec![]));
+    writer
+        .start_entry(
+            "lines.txt",
+            EntryKind::File,
+            0o640,
+            1000,
+            100,
+            1_700_000_000,
+        )
+        .unwrap();
+    for i in 0..1000 {
+        writeln!(writer, "line {i}").unwrap();
+    }
+    writer
+        .start_entry("empty.txt", EntryKind::File, 0o644, 0, 0, 0)
+        .unwrap();
+    writer
+        .start_entry("link", EntryKind::SoftLink, 0o777, 0, 0, 0)
+        .unwrap();
+    writer.write_all(b"lines.txt").unwrap();
+    let archive = writer.finish().unwrap().into_inner();
+
+    let report = crate::verify(&mut io::Cursor::new(&archive)).unwrap();
+    assert!(report.corrupt_paths.is_empty());
+    let entries = crate::list(&mut io::Cursor::new(&archive)).unwrap();
+    let expected = (0..1000).map(|i| format!("line {i}\n")).collect::<String>();
+    assert_eq!(entries[0].size, expected.len() as u64);
+    assert_eq!(entries[0].perms, 0o640);
+    assert_eq!(entries[1].size, 0);
+    assert_eq!(entries[2].kind, EntryKind::SoftLink);
+    let headers = crate::Entries::new(io::Cursor::new(&archive))
+        .collect::<Result<Vec<_>, _>>()
+        .unwrap();
+    assert_eq!((headers[0].meta.owner, headers[0].meta.group), (1000, 100));
+
+    let mut restored = vec![];
+    assert!(
+        crate::extract_one(&mut io::Cursor::new(&archive), "lines.txt", &mut restored).unwrap()
+    );
+    assert_eq!(restored, expected.as_bytes());
+}
+
+#[test]
+fn writing_without_entry_fails() {
+    let mut writer = ArchiveWriter::new(io::Cursor::new(vec![]));
+    assert_eq!(
+        writer.write_all(b"orphan").unwrap_err().kind(),
+        io::ErrorKind::InvalidInput
+    );
+}
@@ -0,0 +1,178 @@
+//! Extended attributes, stored as a blob between the path and the body
+//! of entries flagged with [`flags::HAS_XATTR`](crate::flags::HAS_XATTR).
+//!
+//! | bytes | content                                 |
+//! |-------|-----------------------------------------|
+//! | 4     | length of the rest of the blob          |
+//! | 2     | name length, followed by the name       |
+//! | 4     | value length, followed by the value     |
+//!
+//! with the last two rows repeated for every attribute, all little-endian.
+//! Since format version 3 the header checksum covers the whole blob.
+
+use std::io::{self, Read};
+#[cfg(feature = "xattr")]
+use std::path::Path;
+
+#[cfg(test)]
+use crate::flags;
+use crate::{DecodeError, Metadata};
+
+/// Upper bound for the blob of a single entry, protecting against corrupt lengths.
+pub const MAX_LEN: usize = 1 << 20;
+
+/// Names and values of the extended attributes of one object.
+pub type Xattrs = Vec<(Vec<u8>, Vec<u8>)>;
+
+/// Serializes `xattrs` into the blob stored in the archive.
+pub fn encode(xattrs: &Xattrs) -> Vec<u8> {
+    let mut blob = vec![0u8; 4];
+    for (name, value) in xattrs {
+        blob.extend_from_slice(&(name.len() as u16).to_le_bytes());
+        blob.extend_from_slice(name);
+        blob.extend_from_slice(&(value.len() as u32).to_le_bytes());
+        blob.extend_from_slice(value);
+    }
+    let len = (blob.len() - 4) as u32;
+    blob[..4].copy_from_slice(&len.to_le_bytes());
+    blob
+}
+
+/// Reads the attributes following `path`, if the entry has any,
+/// and checks them against the header checksum.
+pub fn read<R: Read>(
+    header: &Metadata,
+    path: &[u8],
+    archive: &mut R,
+) -> Result<Xattrs, DecodeError> {
+    if !header.flags.has_xattr() {
+        return Ok(vec![]);
+    }
+
+    let read_failed = |e| crate::read::read_failed("extended attributes", e);
+    let mut blob = vec![0u8; 4];
+    archive.read_exact(&mut blob).map_err(read_failed)?;
+    blob.resize(4 + blob_len(&blob)?, 0);
+    archive.read_exact(&mut blob[4..]).map_err(read_failed)?;
+    decode_blob(header, path, &blob)
+}
+
+/// Length of the rest of the blob starting with `prefix`, its first four bytes.
+pub fn blob_len(prefix: &[u8]) -> Result<usize, DecodeError> {
+    let len = u32::from_le_bytes(prefix[..4].try_into().unwrap()) as usize;
+    if len > MAX_LEN {
+        log::error!("Extended attributes of {len}B exceed the maximum");
+        return Err(DecodeError::Header { offset: None });
+    }
+    Ok(len)
+}
+
+/// Checks the whole `blob` read for the entry with `header` and `path`
+/// against the header checksum and decodes it.
+pub fn decode_blob(header: &Metadata, path: &[u8], blob: &[u8]) -> Result<Xattrs, DecodeError> {
+    header
+        .verify_xattrs(path, blob)
+        .inspect_err(|e| log::error!("Failed to verify extended attributes: {e}"))?;
+
+    decode(&blob[4..]).ok_or_else(|| {
+        log::error!("Malformed extended attributes");
+        DecodeError::Header { offset: None }
+    })
+}
+
+fn decode(mut blob: &[u8]) -> Option<Xattrs> {
+    fn take<'a>(blob: &mut &'a [u8], n: usize) -> Option<&'a [u8]> {
+        if blob.len() < n {
+            return None;
+        }
+        let (head, tail) = blob.split_at(n);
+        *blob = tail;
+        Some(head)
+    }
+
+    let mut xattrs = vec![];
+    while !blob.is_empty() {
+        let name_len = u16::from_le_bytes(take(&mut blob, 2)?.try_into().ok()?);
+        let name = take(&mut blob, name_len as usize)?.to_vec();
+        let value_len = u32::from_le_bytes(take(&mut blob, 4)?.try_into().ok()?);
+        let value = take(&mut blob, value_len as usize)?.to_vec();
+        xattrs.push((name, value));
+    }
+    Some(xattrs)
+}
+
+/// Lists the extended attributes of `path`, following symlinks if `deref` is set.
+#[cfg(all(feature = "xattr", unix))]
+pub fn get(path: &Path, deref: bool) -> io::Result<Xattrs> {
+    use std::os::unix::ffi::OsStrExt;
+
+    let names = if deref {
+        xattr::list_deref(path)?
+    } else {
+        xattr::list(path)?
+    };
+    let mut xattrs = vec![];
+    for name in names {
+        let value = if deref {
+            xattr::get_deref(path, &name)?
+        } else {
+            xattr::get(path, &name)?
+        };
+        // the attribute may have been removed in the meantime.
+        if let Some(value) = value {
+            xattrs.push((name.as_bytes().to_vec(), value));
+        }
+    }
+    Ok(xattrs)
+}
+
+#[cfg(all(feature = "xattr", not(unix)))]
+pub fn get(_path: &Path, _deref: bool) -> io::Result<Xattrs> {
+    Ok(vec![])
+}
+
+/// Applies `xattrs` to `path`, without following symlinks.
+#[cfg(all(feature = "xattr", unix))]
+pub fn set(path: &Path, xattrs: &[(Vec<u8>, Vec<u8>)]) -> io::Result<()> {
+    use std::{ffi::OsStr, os::unix::ffi::OsStrExt};
+
+    for (name, value) in xattrs {
+        xattr::set(path, OsStr::from_bytes(name), value)?;
+    }
+    Ok(())
+}
+
+#[cfg(not(all(feature = "xattr", unix)))]
+pub fn set(_path: &std::path::Path, xattrs: &[(Vec<u8>, Vec<u8>)]) -> io::Result<()> {
+    if xattrs.is_empty() {
+        return Ok(());
+    }
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "extended attributes require the `xattr` feature on unix",
+    ))
+}
+
+#[test]
+fn blob_roundtrip() {
+    let xattrs = vec![
+        (b"user.a".to_vec(), b"1".to_vec()),
+        (b"user.empty".to_vec(), vec![]),
+    ];
+    let blob = encode(&xattrs);
+    let header = Metadata::new(flags::FILE | flags::HAS_XATTR, 0, 5, 0, 0, 0, 0);
+    let header = header.header(b"a.txt", &blob);
+    assert_eq!(read(&header, b"a.txt", &mut &blob[..]), Ok(xattrs));
+    assert_eq!(
+        read(&header, b"a.txt", &mut &blob[..blob.len() - 1]),
+        Err(DecodeError::Crop { offset: None })
+    );
+
+    // the header checksum covers the attributes.
+    let mut tampered = blob.clone();
+    *tampered.last_mut().unwrap() ^= 1;
+    assert_eq!(
+        read(&header, b"a.txt", &mut &tampered[..]),
+        Err(DecodeError::Checksum { offset: None })
+    );
+}
@@ -0,0 +1,59 @@
+//! Walking an archive must not allocate more than needed for the entries themselves.
+
+#![cfg(feature = "std")]
+
+use std::{
+    alloc::{GlobalAlloc, Layout, System},
+    io,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+/// Counts allocations.
+struct Counting;
+
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for Counting {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.realloc(ptr, layout, new_size)
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: Counting = Counting;
+
+#[test]
+fn entries_reuse_path_buffer() {
+    const ENTRIES: usize = 1000;
+
+    let mut archive = vec![];
+    let mut paths = vec![];
+    for i in 0..ENTRIES {
+        // lengths vary, so a buffer sized for a short path has to grow again.
+        let path = format!("{}/{i}.txt", "dir".repeat(i % 7 + 1));
+        bitumen::append_reader(&mut archive, &path, &mut io::empty(), 0, 0).unwrap();
+        paths.push(path);
+    }
+
+    let before = ALLOCATIONS.load(Ordering::Relaxed);
+    let mut entries = bitumen::Entries::new(io::Cursor::new(&archive));
+    for path in &paths {
+        let entry = entries.next().unwrap().unwrap();
+        assert_eq!(entry.path(), path);
+    }
+    assert!(entries.next().is_none());
+    let allocations = ALLOCATIONS.load(Ordering::Relaxed) - before;
+
+    // one for the path string of every entry, and a few for the buffer.
+    assert!(allocations <= ENTRIES + 8, "{allocations} allocations");
+}
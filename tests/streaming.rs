@@ -0,0 +1,81 @@
+//! Archiving must stream bodies instead of holding the archive in memory.
+
+#![cfg(feature = "std")]
+
+use std::{
+    alloc::{GlobalAlloc, Layout, System},
+    fs::File,
+    io::{self, BufReader, BufWriter, Read, Write},
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+/// Records the largest single allocation.
+struct Largest;
+
+static LARGEST: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for Largest {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        LARGEST.fetch_max(layout.size(), Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        LARGEST.fetch_max(new_size, Ordering::Relaxed);
+        System.realloc(ptr, layout, new_size)
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: Largest = Largest;
+
+#[test]
+fn archive_large_file_to_disk() {
+    // large enough that buffering it whole would show in the largest allocation.
+    const SIZE: u64 = 8 << 20;
+
+    let dir = tempfile::tempdir().unwrap();
+    let src = dir.path().join("src");
+    std::fs::create_dir(&src).unwrap();
+    let mut big = BufWriter::new(File::create(src.join("big.bin")).unwrap());
+    io::copy(&mut io::repeat(0xAB).take(SIZE), &mut big).unwrap();
+    big.flush().unwrap();
+    drop(big);
+
+    let archive_path = dir.path().join("src.bit");
+    let mut archive = BufWriter::new(File::create(&archive_path).unwrap());
+    bitumen::recursive_archive(&mut archive, &src).unwrap();
+    drop(archive);
+    assert!(LARGEST.load(Ordering::Relaxed) < 1 << 20);
+
+    let mut archive = BufReader::new(File::open(&archive_path).unwrap());
+    let entries = bitumen::list(&mut archive).unwrap();
+    assert_eq!(entries.len(), 2);
+    assert_eq!(entries[1].size, SIZE);
+
+    let mut archive = BufReader::new(File::open(&archive_path).unwrap());
+    // archived paths always use `/` as separator.
+    let path = src.join("big.bin").to_str().unwrap().replace('\\', "/");
+    let mut out = Count(0);
+    assert!(bitumen::extract_one(&mut archive, &path, &mut out).unwrap());
+    assert_eq!(out.0, SIZE);
+    assert!(LARGEST.load(Ordering::Relaxed) < 1 << 20);
+}
+
+/// Counts the bytes written to it.
+struct Count(u64);
+
+impl Write for Count {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0 += buf.len() as u64;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}